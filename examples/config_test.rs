@@ -1,3 +1,12 @@
+use std::path::Path;
+use any_message::config::ConfigBuilder;
+
 fn main() {
+    let config = ConfigBuilder::from_file(Path::new("examples/test_config_01.yaml"))
+        .and_then(|mut builder| builder.build());
 
-}
\ No newline at end of file
+    match config {
+        Ok(config) => println!("{:#?}", config),
+        Err(e) => eprintln!("{}", e),
+    }
+}
@@ -1,12 +1,12 @@
 use std::time::Duration;
-use actix::{Actor, Addr, Context, Handler};
+use actix::{Actor, Addr, Context, Handler, ArbiterHandle};
 use actix_rt::System;
-use any_message::core::{Core, CoreBuilder};
+use any_message::core::CoreBuilder;
 use any_message::message::{BaseMessage, Parcel};
 use any_message::node::Node;
-use any_message::service::{Service, ServiceCore, ServiceRecipient};
+use any_message::service::{Service, ServiceCore, ServiceError, ServiceRecipients, spawn_service_actor};
 use any_message::signal::Tick;
-use log::trace;
+use log::{trace, error};
 
 #[derive(Clone)]
 pub struct Consumer {}
@@ -15,68 +15,62 @@ impl Consumer {}
 
 impl Actor for Consumer {
     type Context = Context<Self>;
-    fn started(&mut self, ctx: &mut Self::Context) {
+    fn started(&mut self, _ctx: &mut Self::Context) {
         trace!("Consumer started!");
     }
 }
 
 impl Service for Consumer {
-    fn config_system(service_code: &mut ServiceCore, node: Addr<Node>, core: &Core) {
+    fn config_system(&mut self, service_code: &mut ServiceCore, _node: Addr<Node>) {
         service_code.set_consuming_messages_types(vec!["Asterisk Message".to_string()]);
     }
 
     fn handle_message(&self, message: &BaseMessage) {
-        todo!()
+        trace!("{:?} Consuming in comsumer {:?}", std::thread::current().id(), message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
     }
 }
 
 impl Handler<Parcel> for Consumer {
     type Result = ();
 
-    fn handle(&mut self, msg: Parcel, ctx: &mut Self::Context) -> Self::Result {
-
-        trace!("{:?} Consuming in comsumer {:?}", std::thread::current().id(), msg);
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        for message in msg.unpack() {
+            self.handle_message(message);
+        }
     }
 }
 
 impl Handler<Tick> for Consumer {
     type Result = ();
 
-    fn handle(&mut self, msg: Tick, ctx: &mut Self::Context) -> Self::Result {}
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
 }
 
 fn main() {
-    dotenv::dotenv();
+    dotenv::dotenv().ok();
     env_logger::init();
     let _j = std::thread::spawn(|| {
         std::thread::sleep(Duration::from_secs(5));
         std::process::exit(0);
     });
     System::new().block_on(async move {
-        let mut core =
-            CoreBuilder::new(|| {
-                let node = Node::new("telnet".to_string());
-
-                node
-            }).build();
-
-        let service = core.service("Consumer".to_string(), Box::new(Consumer::config_system)).await;
-        // let telnet_service = core.service("Asterisk".to_string(), Box::new(TelnetService::config_system)).await;
-        //
-        // let telnet_actor = TelnetService::new(
-        //     "Asterisk Message".to_string(),
-        //     "185.179.2.33".to_string(),
-        //     5038,
-        //     10000000,
-        //     Some(50)).start();
-
-        let consumer_actor = Consumer::start(Consumer{});
-
-        // ServiceCore::link(telnet_service, telnet_actor.recipients());
-        ServiceCore::link(service, consumer_actor.recipients());
-
-
-
-        core.run().await;
+        let core = CoreBuilder::new(|| {
+            Node::new("telnet".to_string())
+        }).service("Consumer".to_string(), |_node| {
+            Box::new(Consumer {})
+        }).build().await;
+
+        match core {
+            Ok(mut core) => {
+                if let Err(e) = core.run().await {
+                    error!("{}", e);
+                }
+            }
+            Err(e) => error!("Can`t start core: {}", e),
+        }
     });
-}
\ No newline at end of file
+}
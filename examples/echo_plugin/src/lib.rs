@@ -0,0 +1,113 @@
+//! Example plugin library. The `EchoService` publishes every message it consumes again
+//! under another message type.
+//!
+//! ```yaml
+//! Core:
+//!   name: "EchoCore"
+//!   plugins:
+//!     - "target/debug/libecho_plugin.so"
+//! Node:
+//!   name: "Node01"
+//!   echo:
+//!     type: "EchoService"
+//!     parameters:
+//!       message_type: "EchoRequest"
+//!       reply_type: "EchoReply"
+//! ```
+
+use actix::{Actor, Addr, ArbiterHandle, Context, Handler};
+use any_message::config::ServiceConfig;
+use any_message::core::Core;
+use any_message::message::{BaseMessage, Parcel};
+use any_message::node::Node;
+use any_message::plugin::Plugin;
+use any_message::route::{Route, RouteSheet, Target};
+use any_message::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use any_message::signal::Tick;
+use any_message::{declare_plugin, declare_service};
+use log::{error, info, trace};
+
+pub struct EchoService {
+    message_type: String,
+    reply_type: String,
+    route: Route,
+    node: Option<Addr<Node>>,
+}
+
+impl EchoService {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn std::error::Error>> {
+        Ok(Box::new(EchoService {
+            message_type: config.parse_parameter_or("message_type", "EchoRequest".to_string())?,
+            reply_type: config.parse_parameter_or("reply_type", "EchoReply".to_string())?,
+            route: Route::new(),
+            node: None,
+        }))
+    }
+}
+
+impl Actor for EchoService {
+    type Context = Context<Self>;
+}
+
+impl Service for EchoService {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        service_core.set_consuming_messages_types(vec![self.message_type.clone()]);
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Echo {:?}", message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl Handler<Parcel> for EchoService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        let messages: Vec<BaseMessage> = msg.unpack().iter()
+            .map(|message| {
+                self.handle_message(message);
+                BaseMessage::new(message.data().clone(), None)
+            })
+            .collect();
+
+        if let Some(node) = &self.node {
+            let route_sheet = RouteSheet::new(Target::Consumer(self.reply_type.clone()), self.route.clone());
+            node.do_send(Parcel::new(messages, route_sheet));
+        }
+    }
+}
+
+impl Handler<Tick> for EchoService {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+declare_service!(echo_service => EchoService::on_start);
+
+#[derive(Default)]
+pub struct EchoPlugin;
+
+impl Plugin for EchoPlugin {
+    fn name(&self) -> &'static str {
+        "EchoPlugin"
+    }
+
+    fn on_load(&self, core: &mut Core) {
+        if let Err(e) = core.service_config("EchoService".to_string(), echo_service) {
+            error!("EchoService is not available: {}", e);
+        }
+    }
+
+    fn on_unload(&self) {
+        info!("Echo plugin unloaded");
+    }
+}
+
+declare_plugin!(EchoPlugin, EchoPlugin::default);
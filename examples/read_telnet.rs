@@ -1,37 +1,36 @@
 use std::time::Duration;
-use actix::{System, Actor};
-use base::core::CoreBuilder;
-use base::node::Node;
-use base::service::{ServiceRecipient, Service};
-use any_message_telnet::TelnetService;
-use telnet::Telnet;
-use base::message::Parcel;
+use actix::System;
+use any_message::core::CoreBuilder;
+use any_message::node::Node;
+use any_message::any_message_telnet::TelnetService;
+use log::error;
 
 
 fn main() {
-    dotenv::dotenv();
+    dotenv::dotenv().ok();
     env_logger::init();
-    let j = std::thread::spawn(|| {
+    let _j = std::thread::spawn(|| {
         std::thread::sleep(Duration::from_secs(5));
         std::process::exit(0);
     });
     System::new().block_on(async move {
-        let core = unsafe {
-            CoreBuilder::new(|| {
-                Node::new("telnet".to_string())
-            }).build()
-        };
+        let core = CoreBuilder::new(|| {
+            Node::new("telnet".to_string())
+        }).service("telnet".to_string(), |_node| {
+            Box::new(TelnetService::new(
+                "Asterisk Message".to_string(),
+                "185.179.2.33".to_string(),
+                5038,
+                4096))
+        }).build().await;
 
-
-        TelnetService::new(
-            core.node().recipient::<Parcel>(),
-            "185.179.2.33".to_string(),
-            5038,
-            10000000,
-            Some(50)).start();
-
-
-
-        core.run().await;
+        match core {
+            Ok(mut core) => {
+                if let Err(e) = core.run().await {
+                    error!("{}", e);
+                }
+            }
+            Err(e) => error!("Can`t start core: {}", e),
+        }
     });
-}
\ No newline at end of file
+}
@@ -0,0 +1,234 @@
+//! Asterisk Manager Interface framing. Asterisk greets with a banner line, after which
+//! everything is a block of `Key: Value` lines ending with a blank line, in both directions.
+
+use crate::message::BaseMessage;
+
+/// ActionID of the login the service sends on every connect
+pub const LOGIN_ACTION_ID: &str = "any_message-login";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AmiMessage {
+    headers: Vec<(String, String)>,
+}
+
+impl AmiMessage {
+    pub fn new() -> Self {
+        Self { headers: vec![] }
+    }
+
+    pub fn action(action: &str) -> Self {
+        Self::new().with("Action", action)
+    }
+
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn push(&mut self, key: String, value: String) {
+        self.headers.push((key, value));
+    }
+
+    /// The first value of the key, keys are case insensitive
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Replaces every value of the key with one
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.headers.retain(|(name, _)| !name.eq_ignore_ascii_case(key));
+        self.headers.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn headers(&self) -> &Vec<(String, String)> {
+        &self.headers
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut block = String::new();
+        for (key, value) in &self.headers {
+            block.push_str(key);
+            block.push_str(": ");
+            block.push_str(value);
+            block.push_str("\r\n");
+        }
+        block.push_str("\r\n");
+        block.into_bytes()
+    }
+
+    /// The block as data and its keys as headers, repeated keys are joined with ", "
+    pub fn to_message(&self) -> BaseMessage {
+        let mut message = BaseMessage::new(self.encode(), None);
+        for (key, value) in &self.headers {
+            let header = message.headers_mut().entry(key.clone()).or_default();
+            if !header.is_empty() {
+                header.push_str(", ");
+            }
+            header.push_str(value);
+        }
+        message
+    }
+
+    /// Parses a block as encoded by `encode`, e.g. the data of a message for the AMI
+    pub fn parse(data: &[u8]) -> Self {
+        let mut framer = AmiFramer::new();
+        framer.expect_banner = false;
+        let mut messages = framer.feed(data);
+        messages.extend(framer.feed(b"\r\n\r\n"));
+        messages.into_iter().next().unwrap_or_default()
+    }
+}
+
+pub fn login(username: &str, secret: &str, events: &str) -> AmiMessage {
+    AmiMessage::action("Login")
+        .with("Username", username)
+        .with("Secret", secret)
+        .with("Events", events)
+        .with("ActionID", LOGIN_ACTION_ID)
+}
+
+/// Response and event list of an action, collected by its ActionID
+#[derive(Debug, Clone, Default)]
+pub struct ActionResult {
+    response: Option<AmiMessage>,
+    events: Vec<AmiMessage>,
+}
+
+impl ActionResult {
+    /// Adds a block of the action, true once the action is complete: with its response,
+    /// or with the `EventList: Complete` event if the response started an event list
+    pub fn add(&mut self, message: AmiMessage) -> bool {
+        let complete = match message.get("Event") {
+            Some(_) => message.get("EventList").is_some_and(|list| list.eq_ignore_ascii_case("Complete")),
+            None => !message.get("EventList").is_some_and(|list| list.eq_ignore_ascii_case("start")),
+        };
+        match message.get("Event") {
+            Some(_) => self.events.push(message),
+            None => self.response = Some(message),
+        }
+        complete && self.response.is_some()
+    }
+
+    pub fn response(&self) -> Option<&AmiMessage> {
+        self.response.as_ref()
+    }
+
+    pub fn events(&self) -> &Vec<AmiMessage> {
+        &self.events
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.response.as_ref()
+            .and_then(|response| response.get("Response"))
+            .is_some_and(|response| response.eq_ignore_ascii_case("Error"))
+    }
+
+    /// The blocks one after another as data, the keys of the response as headers
+    pub fn to_message(&self) -> BaseMessage {
+        let mut message = self.response.as_ref().map(AmiMessage::to_message).unwrap_or_else(|| BaseMessage::new(vec![], None));
+        let data = self.events.iter().fold(message.data().clone(), |mut data, event| {
+            data.extend(event.encode());
+            data
+        });
+        let headers = std::mem::take(message.headers_mut());
+        let mut message = BaseMessage::new(data, None);
+        *message.headers_mut() = headers;
+        message
+    }
+}
+
+/// Splits the stream into blocks, keeping an incomplete line or block between reads
+#[derive(Debug, Default)]
+pub struct AmiFramer {
+    buffer: Vec<u8>,
+    block: AmiMessage,
+    /// Only the first line can be the banner
+    expect_banner: bool,
+    banner: Option<String>,
+}
+
+impl AmiFramer {
+    pub fn new() -> Self {
+        Self { buffer: vec![], block: AmiMessage::new(), expect_banner: true, banner: None }
+    }
+
+    /// The banner, e.g. "Asterisk Call Manager/5.0.1", once it is read
+    pub fn banner(&self) -> Option<&str> {
+        self.banner.as_deref()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Vec<AmiMessage> {
+        self.buffer.extend_from_slice(data);
+        let mut messages = vec![];
+
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if self.expect_banner && !line.is_empty() {
+                self.expect_banner = false;
+                if !line.contains(": ") {
+                    self.banner = Some(line.to_string());
+                    continue;
+                }
+            }
+
+            if line.is_empty() {
+                if !self.block.headers.is_empty() {
+                    messages.push(std::mem::take(&mut self.block));
+                }
+                continue;
+            }
+
+            match line.split_once(':') {
+                Some((key, value)) if !key.contains(' ') => self.block.push(key.to_string(), value.trim_start().to_string()),
+                // Output of commands before Asterisk 14 has no key
+                _ => self.block.push("Output".to_string(), line.to_string()),
+            }
+        }
+
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::any_message_telnet::ami::{login, ActionResult, AmiFramer, AmiMessage};
+
+    #[test]
+    fn test_framer() {
+        let mut framer = AmiFramer::new();
+        let messages = framer.feed(b"Asterisk Call Manager/5.0.1\r\nResponse: Success\r\nMessage: Authentication accepted\r\n\r\nEvent: Newch");
+        assert_eq!(framer.banner(), Some("Asterisk Call Manager/5.0.1"));
+        assert_eq!(messages, vec![AmiMessage::new().with("Response", "Success").with("Message", "Authentication accepted")]);
+
+        let messages = framer.feed(b"annel\r\nChannel: SIP/100-1\r\nVariable: a=1\r\nVariable: b=2\r\n\r\n");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get("event"), Some("Newchannel"));
+        let message = messages[0].to_message();
+        assert_eq!(message.header("Variable"), Some(&"a=1, b=2".to_string()));
+        assert_eq!(AmiMessage::parse(message.data()), messages[0]);
+
+        assert_eq!(login("admin", "s3cret", "on").encode(),
+                   b"Action: Login\r\nUsername: admin\r\nSecret: s3cret\r\nEvents: on\r\nActionID: any_message-login\r\n\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_action_result() {
+        let mut result = ActionResult::default();
+        assert!(!result.add(AmiMessage::new().with("Response", "Success").with("EventList", "start")));
+        assert!(!result.add(AmiMessage::new().with("Event", "Status").with("Channel", "SIP/100-1")));
+        assert!(result.add(AmiMessage::new().with("Event", "StatusComplete").with("EventList", "Complete")));
+        assert_eq!(result.events().len(), 2);
+        assert!(!result.is_error());
+        assert_eq!(AmiFramer::new().feed(result.to_message().data()).len(), 3);
+
+        let mut result = ActionResult::default();
+        assert!(result.add(AmiMessage::new().with("Response", "Error").with("Message", "Permission denied")));
+        assert!(result.is_error());
+        assert_eq!(result.to_message().header("Message"), Some(&"Permission denied".to_string()));
+    }
+}
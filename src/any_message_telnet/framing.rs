@@ -0,0 +1,94 @@
+//! How the output of a telnet session is cut into messages outside of AMI mode
+
+use regex::bytes::Regex;
+
+/// What is kept of the output while no message is complete
+const MAX_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub enum TextFraming {
+    /// Every read as it is
+    Raw,
+    /// Lines without their `\r\n` or `\n`
+    Line,
+    /// Everything before the prompt, e.g. the output of a command. The prompt can`t match
+    /// empty output.
+    Prompt(Regex),
+}
+
+#[derive(Debug)]
+pub struct TextFramer {
+    framing: TextFraming,
+    buffer: Vec<u8>,
+}
+
+impl TextFramer {
+    pub fn new(framing: TextFraming) -> Self {
+        Self { framing, buffer: vec![] }
+    }
+
+    /// Complete messages, empty lines and empty output are skipped
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        if let TextFraming::Raw = self.framing {
+            return match data.is_empty() {
+                true => vec![],
+                false => vec![data.to_vec()],
+            };
+        }
+
+        self.buffer.extend_from_slice(data);
+        let mut messages = vec![];
+        loop {
+            let (end, next) = match &self.framing {
+                TextFraming::Line => match self.buffer.iter().position(|&byte| byte == b'\n') {
+                    Some(end) => (end, end + 1),
+                    None => break,
+                },
+                TextFraming::Prompt(prompt) => match prompt.find(&self.buffer) {
+                    // An empty match at the start would never consume the buffer
+                    Some(found) if found.end() > 0 => (found.start(), found.end()),
+                    _ => break,
+                },
+                TextFraming::Raw => break,
+            };
+
+            let mut message: Vec<u8> = self.buffer.drain(..next).take(end).collect();
+            if let TextFraming::Line = self.framing {
+                if message.last() == Some(&b'\r') {
+                    message.pop();
+                }
+            }
+            if !message.is_empty() {
+                messages.push(message);
+            }
+        }
+
+        if self.buffer.len() > MAX_BUFFER_SIZE {
+            self.buffer.drain(..self.buffer.len() - MAX_BUFFER_SIZE);
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::any_message_telnet::framing::{TextFramer, TextFraming};
+    use regex::bytes::Regex;
+
+    #[test]
+    fn test_framing() {
+        let mut lines = TextFramer::new(TextFraming::Line);
+        assert_eq!(lines.feed(b"first\r\nsec"), vec![b"first".to_vec()]);
+        assert_eq!(lines.feed(b"ond\n\r\n"), vec![b"second".to_vec()]);
+
+        let mut prompt = TextFramer::new(TextFraming::Prompt(Regex::new(r"\r?\nrouter> ").unwrap()));
+        assert_eq!(prompt.feed(b"up 3 days\r\nload 0.1\r\nrouter"), Vec::<Vec<u8>>::new());
+        assert_eq!(prompt.feed(b"> "), vec![b"up 3 days\r\nload 0.1".to_vec()]);
+
+        // Returns instead of looping on the empty match, keeping the end of the output
+        let mut empty = TextFramer::new(TextFraming::Prompt(Regex::new(r"(> )?").unwrap()));
+        assert_eq!(empty.feed(b"output> "), Vec::<Vec<u8>>::new());
+        assert_eq!(empty.feed(&[b'x'; 100_000]), Vec::<Vec<u8>>::new());
+        assert_eq!(empty.buffer.len(), super::MAX_BUFFER_SIZE);
+    }
+}
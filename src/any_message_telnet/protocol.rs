@@ -0,0 +1,365 @@
+//! The part of the telnet protocol (RFC 854/855) the service needs: separating data from
+//! commands, option negotiation and subnegotiation. The parser keeps its state between
+//! reads, so a command split over two reads is still recognized. `Negotiator` keeps the state
+//! of each option for both sides after RFC 1143, so negotiations never loop.
+
+use std::collections::{HashMap, HashSet};
+
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+pub const BINARY: u8 = 0;
+pub const ECHO: u8 = 1;
+pub const SUPPRESS_GO_AHEAD: u8 = 3;
+pub const TERMINAL_TYPE: u8 = 24;
+pub const NAWS: u8 = 31;
+
+/// Subnegotiation commands of the terminal type option (RFC 1091)
+pub const TERMINAL_TYPE_IS: u8 = 0;
+pub const TERMINAL_TYPE_SEND: u8 = 1;
+
+const OPTION_NAMES: [(u8, &str); 5] = [
+    (BINARY, "binary"),
+    (ECHO, "echo"),
+    (SUPPRESS_GO_AHEAD, "sga"),
+    (TERMINAL_TYPE, "ttype"),
+    (NAWS, "naws"),
+];
+
+/// Name of a known option, otherwise its number
+pub fn option_name(option: u8) -> String {
+    match OPTION_NAMES.iter().find(|(known, _)| *known == option) {
+        Some((_, name)) => name.to_string(),
+        None => option.to_string(),
+    }
+}
+
+/// An option by its name, e.g. "naws", or its number
+pub fn parse_option(name: &str) -> Option<u8> {
+    let name = name.trim().to_ascii_lowercase();
+    match OPTION_NAMES.iter().find(|(_, known)| *known == name) {
+        Some((option, _)) => Some(*option),
+        None => name.parse().ok(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Negotiation {
+    Will,
+    Wont,
+    Do,
+    Dont,
+}
+
+impl Negotiation {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            WILL => Some(Negotiation::Will),
+            WONT => Some(Negotiation::Wont),
+            DO => Some(Negotiation::Do),
+            DONT => Some(Negotiation::Dont),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Negotiation::Will => "will",
+            Negotiation::Wont => "wont",
+            Negotiation::Do => "do",
+            Negotiation::Dont => "dont",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "will" => Some(Negotiation::Will),
+            "wont" => Some(Negotiation::Wont),
+            "do" => Some(Negotiation::Do),
+            "dont" => Some(Negotiation::Dont),
+            _ => None,
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        match self {
+            Negotiation::Will => WILL,
+            Negotiation::Wont => WONT,
+            Negotiation::Do => DO,
+            Negotiation::Dont => DONT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelnetEvent {
+    Data(Vec<u8>),
+    Negotiation(Negotiation, u8),
+    Subnegotiation(u8, Vec<u8>),
+    /// Any other command, e.g. NOP or GA
+    Command(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Data,
+    Iac,
+    Negotiation(Negotiation),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+#[derive(Debug)]
+pub struct TelnetParser {
+    state: State,
+    data: Vec<u8>,
+    subnegotiation: Vec<u8>,
+}
+
+impl Default for TelnetParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TelnetParser {
+    pub fn new() -> Self {
+        Self { state: State::Data, data: vec![], subnegotiation: vec![] }
+    }
+
+    /// Events of the bytes, data is returned up to the last complete byte
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<TelnetEvent> {
+        let mut events = vec![];
+
+        for &byte in bytes {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, byte) => {
+                    self.data.push(byte);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    self.data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, SB) => {
+                    self.flush_data(&mut events);
+                    self.subnegotiation.clear();
+                    State::Subnegotiation
+                }
+                (State::Iac, byte) => match Negotiation::from_byte(byte) {
+                    Some(negotiation) => State::Negotiation(negotiation),
+                    None => {
+                        self.flush_data(&mut events);
+                        events.push(TelnetEvent::Command(byte));
+                        State::Data
+                    }
+                },
+                (State::Negotiation(negotiation), option) => {
+                    self.flush_data(&mut events);
+                    events.push(TelnetEvent::Negotiation(negotiation, option));
+                    State::Data
+                }
+                (State::Subnegotiation, IAC) => State::SubnegotiationIac,
+                (State::Subnegotiation, byte) => {
+                    self.subnegotiation.push(byte);
+                    State::Subnegotiation
+                }
+                (State::SubnegotiationIac, SE) => {
+                    let mut subnegotiation = std::mem::take(&mut self.subnegotiation);
+                    if !subnegotiation.is_empty() {
+                        let option = subnegotiation.remove(0);
+                        events.push(TelnetEvent::Subnegotiation(option, subnegotiation));
+                    }
+                    State::Data
+                }
+                (State::SubnegotiationIac, byte) => {
+                    self.subnegotiation.push(byte);
+                    State::Subnegotiation
+                }
+            };
+        }
+
+        self.flush_data(&mut events);
+        events
+    }
+
+    fn flush_data(&mut self, events: &mut Vec<TelnetEvent>) {
+        if !self.data.is_empty() {
+            events.push(TelnetEvent::Data(std::mem::take(&mut self.data)));
+        }
+    }
+}
+
+/// Doubles IAC bytes of data written to the connection
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+pub fn negotiation(negotiation: Negotiation, option: u8) -> Vec<u8> {
+    vec![IAC, negotiation.byte(), option]
+}
+
+pub fn subnegotiation(option: u8, data: &[u8]) -> Vec<u8> {
+    [&[IAC, SB, option][..], &escape(data), &[IAC, SE][..]].concat()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OptionState {
+    No,
+    WantYes,
+    Yes,
+    WantNo,
+}
+
+/// Which options each side may enable and which are enabled. The local side is this client,
+/// enabled by WILL/DO, the remote side the server, enabled by DO/WILL.
+#[derive(Debug, Clone, Default)]
+pub struct Negotiator {
+    local_policy: HashSet<u8>,
+    remote_policy: HashSet<u8>,
+    local: HashMap<u8, OptionState>,
+    remote: HashMap<u8, OptionState>,
+}
+
+impl Negotiator {
+    pub fn new(local_policy: HashSet<u8>, remote_policy: HashSet<u8>) -> Self {
+        Self { local_policy, remote_policy, local: HashMap::new(), remote: HashMap::new() }
+    }
+
+    /// Every option is off on a new connection
+    pub fn reset(&mut self) {
+        self.local.clear();
+        self.remote.clear();
+    }
+
+    pub fn is_local_enabled(&self, option: u8) -> bool {
+        self.local.get(&option) == Some(&OptionState::Yes)
+    }
+
+    pub fn is_remote_enabled(&self, option: u8) -> bool {
+        self.remote.get(&option) == Some(&OptionState::Yes)
+    }
+
+    /// The answer to a negotiation of the server, `None` when it needs none
+    pub fn receive(&mut self, negotiation: Negotiation, option: u8) -> Option<Negotiation> {
+        let (states, policy, enable, agree, refuse) = match negotiation {
+            Negotiation::Will | Negotiation::Wont => (&mut self.remote, &self.remote_policy, negotiation == Negotiation::Will, Negotiation::Do, Negotiation::Dont),
+            Negotiation::Do | Negotiation::Dont => (&mut self.local, &self.local_policy, negotiation == Negotiation::Do, Negotiation::Will, Negotiation::Wont),
+        };
+        let state = states.entry(option).or_insert(OptionState::No);
+
+        match (enable, *state) {
+            (true, OptionState::No) if policy.contains(&option) => {
+                *state = OptionState::Yes;
+                Some(agree)
+            }
+            (true, OptionState::No) => Some(refuse),
+            // The server answered our refusal by enabling it, RFC 1143 leaves the option off
+            (true, OptionState::WantNo) => {
+                *state = OptionState::No;
+                None
+            }
+            (true, _) => {
+                *state = OptionState::Yes;
+                None
+            }
+            (false, OptionState::Yes) => {
+                *state = OptionState::No;
+                Some(refuse)
+            }
+            (false, _) => {
+                *state = OptionState::No;
+                None
+            }
+        }
+    }
+
+    /// What to send to ask the server for the negotiation, `None` when the option already
+    /// is, or is about to be, in that state. Options outside the policy can`t be enabled.
+    pub fn request(&mut self, negotiation: Negotiation, option: u8) -> Result<Option<Negotiation>, String> {
+        let (states, policy, side, enable) = match negotiation {
+            Negotiation::Do | Negotiation::Dont => (&mut self.remote, &self.remote_policy, "remote", negotiation == Negotiation::Do),
+            Negotiation::Will | Negotiation::Wont => (&mut self.local, &self.local_policy, "local", negotiation == Negotiation::Will),
+        };
+        if enable && !policy.contains(&option) {
+            return Err(format!("{} is not one of the {} options", option_name(option), side));
+        }
+        let state = states.entry(option).or_insert(OptionState::No);
+
+        match (enable, *state) {
+            (true, OptionState::No) => *state = OptionState::WantYes,
+            (false, OptionState::Yes) => *state = OptionState::WantNo,
+            _ => return Ok(None),
+        }
+        Ok(Some(negotiation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::any_message_telnet::protocol::{escape, parse_option, Negotiation, Negotiator, TelnetEvent, TelnetParser, DO, ECHO, IAC, NAWS, SB, SE, WILL};
+
+    #[test]
+    fn test_parser() {
+        let mut parser = TelnetParser::new();
+        let events = parser.feed(&[b'o', b'k', IAC, DO, 1, IAC, IAC, b'!', IAC, 241]);
+        assert_eq!(events, vec![
+            TelnetEvent::Data(b"ok".to_vec()),
+            TelnetEvent::Negotiation(Negotiation::Do, 1),
+            TelnetEvent::Data(vec![IAC, b'!']),
+            TelnetEvent::Command(241),
+        ]);
+
+        // Commands split over reads
+        assert_eq!(parser.feed(&[b'a', IAC]), vec![TelnetEvent::Data(b"a".to_vec())]);
+        assert_eq!(parser.feed(&[WILL]), vec![]);
+        assert_eq!(parser.feed(&[31, IAC, SB, 24, 0, b'x']), vec![TelnetEvent::Negotiation(Negotiation::Will, 31)]);
+        assert_eq!(parser.feed(&[IAC, IAC, IAC, SE, b'b']), vec![
+            TelnetEvent::Subnegotiation(24, vec![0, b'x', IAC]),
+            TelnetEvent::Data(b"b".to_vec()),
+        ]);
+
+        assert_eq!(escape(&[1, IAC, 2]), vec![1, IAC, IAC, 2]);
+    }
+
+    #[test]
+    fn test_negotiator() {
+        let mut negotiator = Negotiator::new([NAWS].iter().copied().collect(), [ECHO].iter().copied().collect());
+        assert_eq!(negotiator.receive(Negotiation::Do, NAWS), Some(Negotiation::Will));
+        assert_eq!(negotiator.receive(Negotiation::Do, NAWS), None);
+        assert!(negotiator.is_local_enabled(NAWS));
+        assert_eq!(negotiator.receive(Negotiation::Do, ECHO), Some(Negotiation::Wont));
+        assert_eq!(negotiator.receive(Negotiation::Will, ECHO), Some(Negotiation::Do));
+        assert_eq!(negotiator.receive(Negotiation::Wont, ECHO), Some(Negotiation::Dont));
+        assert!(!negotiator.is_remote_enabled(ECHO));
+
+        // Asked by us, so the answer needs none
+        assert_eq!(negotiator.request(Negotiation::Do, ECHO), Ok(Some(Negotiation::Do)));
+        assert_eq!(negotiator.request(Negotiation::Do, ECHO), Ok(None));
+        assert_eq!(negotiator.receive(Negotiation::Will, ECHO), None);
+        assert!(negotiator.is_remote_enabled(ECHO));
+        assert!(negotiator.request(Negotiation::Do, NAWS).is_err());
+
+        // A refusal answered by enabling the option is an error, the option stays off
+        assert_eq!(negotiator.request(Negotiation::Dont, ECHO), Ok(Some(Negotiation::Dont)));
+        assert_eq!(negotiator.receive(Negotiation::Will, ECHO), None);
+        assert!(!negotiator.is_remote_enabled(ECHO));
+
+        assert_eq!(parse_option("NAWS"), Some(NAWS));
+        assert_eq!(parse_option("42"), Some(42));
+        assert_eq!(parse_option("colour"), None);
+    }
+}
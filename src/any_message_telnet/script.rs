@@ -0,0 +1,208 @@
+//! Expect/send scripts for the login dialogue of telnet devices. A script is YAML, e.g.
+//!
+//! ```yaml
+//! - expect: "login: "
+//!   send: "${ROUTER_USER}"
+//! - expect: "Password: "
+//!   send: "${ROUTER_PASSWORD}"
+//!   timeout_in_millis: 3000
+//! - expect: "> $"
+//! ```
+//!
+//! Each step waits for its regex, if any, and then sends its text as a line. `${NAME}` in the
+//! text is replaced with the environment variable, so secrets stay out of the config.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use regex::bytes::Regex;
+use serde::Deserialize;
+
+/// What is kept of the output while waiting for a step
+const MAX_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct StepConfig {
+    expect: Option<String>,
+    send: Option<String>,
+    timeout_in_millis: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptStep {
+    pub expect: Option<Regex>,
+    pub send: Option<String>,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Script {
+    steps: Vec<ScriptStep>,
+}
+
+impl Script {
+    /// Steps without `timeout_in_millis` wait `default_timeout`, variables are resolved by `lookup`
+    pub fn parse<F: Fn(&str) -> Option<String>>(yaml: &str, default_timeout: Duration, lookup: F) -> Result<Self, ScriptError> {
+        let configs: Vec<StepConfig> = serde_yaml::from_str(yaml).map_err(ScriptError::Parse)?;
+
+        let mut steps = Vec::with_capacity(configs.len());
+        for (step, config) in configs.into_iter().enumerate() {
+            let expect = match &config.expect {
+                Some(expect) => Some(Regex::new(expect).map_err(|e| ScriptError::Regex { step, source: e })?),
+                None => None,
+            };
+            let send = match &config.send {
+                Some(send) => Some(interpolate(send, &lookup).map_err(|name| ScriptError::UnknownVariable { step, name })?),
+                None => None,
+            };
+            steps.push(ScriptStep {
+                expect,
+                send,
+                timeout: config.timeout_in_millis.map_or(default_timeout, Duration::from_millis),
+            });
+        }
+
+        Ok(Self { steps })
+    }
+
+    pub fn steps(&self) -> &Vec<ScriptStep> {
+        &self.steps
+    }
+}
+
+/// Replaces `${NAME}` with the value of `lookup`, the error is the name of a missing variable
+pub fn interpolate<F: Fn(&str) -> Option<String>>(text: &str, lookup: F) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name = &rest[start + 2..end];
+        result.push_str(&rest[..start]);
+        result.push_str(&lookup(name).ok_or_else(|| name.to_string())?);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// A script running on one connection
+#[derive(Debug, Default)]
+pub struct ScriptRun {
+    step: usize,
+    buffer: Vec<u8>,
+}
+
+/// Lines to send and whether the script is done
+#[derive(Debug, PartialEq)]
+pub struct Advanced {
+    pub send: Vec<String>,
+    pub finished: bool,
+}
+
+impl ScriptRun {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The step the run waits for
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Runs the steps the output allows, output matched by a step is consumed
+    pub fn feed(&mut self, script: &Script, data: &[u8]) -> Advanced {
+        self.buffer.extend_from_slice(data);
+        let mut send = vec![];
+
+        while let Some(step) = script.steps.get(self.step) {
+            if let Some(expect) = &step.expect {
+                match expect.find(&self.buffer) {
+                    Some(found) => {
+                        self.buffer.drain(..found.end());
+                    }
+                    None => {
+                        if self.buffer.len() > MAX_BUFFER_SIZE {
+                            self.buffer.drain(..self.buffer.len() - MAX_BUFFER_SIZE);
+                        }
+                        return Advanced { send, finished: false };
+                    }
+                }
+            }
+            send.extend(step.send.clone());
+            self.step += 1;
+        }
+
+        Advanced { send, finished: true }
+    }
+
+    /// Output after the last match, it belongs to the session
+    pub fn take_rest(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Parse(serde_yaml::Error),
+    Regex { step: usize, source: regex::Error },
+    UnknownVariable { step: usize, name: String },
+}
+
+impl Error for ScriptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScriptError::Parse(e) => Some(e),
+            ScriptError::Regex { source, .. } => Some(source),
+            ScriptError::UnknownVariable { .. } => None,
+        }
+    }
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptError::Parse(e) => write!(f, "can`t parse script: {}", e),
+            ScriptError::Regex { step, source } => write!(f, "invalid regex in step {}: {}", step + 1, source),
+            ScriptError::UnknownVariable { step, name } => write!(f, "unknown variable {} in step {}", name, step + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::any_message_telnet::script::{Advanced, Script, ScriptError, ScriptRun};
+    use std::time::Duration;
+
+    #[test]
+    fn test_script() {
+        let yaml = r#"
+- expect: "login: "
+  send: "${USER}"
+- expect: "[Pp]assword: "
+  send: "${PASSWORD}!"
+  timeout_in_millis: 3000
+- expect: "> "
+"#;
+        let lookup = |name: &str| match name {
+            "USER" => Some("admin".to_string()),
+            "PASSWORD" => Some("s3cret".to_string()),
+            _ => None,
+        };
+        let script = Script::parse(yaml, Duration::from_secs(10), lookup).unwrap();
+        assert_eq!(script.steps()[1].timeout, Duration::from_millis(3000));
+
+        let mut run = ScriptRun::new();
+        assert_eq!(run.feed(&script, b"Welcome\r\nlog"), Advanced { send: vec![], finished: false });
+        assert_eq!(run.feed(&script, b"in: "), Advanced { send: vec!["admin".to_string()], finished: false });
+        assert_eq!(run.feed(&script, b"Password: "), Advanced { send: vec!["s3cret!".to_string()], finished: false });
+        assert_eq!(run.step(), 2);
+        assert!(run.feed(&script, b"router> uptime").finished);
+        assert_eq!(run.take_rest(), b"uptime".to_vec());
+
+        assert!(matches!(Script::parse("- send: \"${TOKEN}\"", Duration::from_secs(1), lookup),
+                         Err(ScriptError::UnknownVariable { step: 0, .. })));
+    }
+}
@@ -1,6 +1,12 @@
-use uuid::Version;
-use std::path::Path;
-use std::collections::HashMap;
+use semver::Version;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer};
+use crate::operation::Operation;
+use crate::plugin::PluginError;
 
 pub struct ConfigBuilder {
     config_string: String,
@@ -15,58 +21,367 @@ impl ConfigBuilder {
         builder
     }
 
-    pub fn from_file(path: &Path) -> Self {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
         match std::fs::read_to_string(path) {
-            Ok(string) => return Self::from_string(string),
-            Err(e) => {
-                panic!("{:?}", e);
+            Ok(string) => Ok(Self::from_string(string)),
+            Err(e) => Err(ConfigError::Io(path.to_path_buf(), e)),
+        }
+    }
+
+    pub fn build(&mut self) -> Result<CoreConfig, ConfigError> {
+        let file: ConfigFile = serde_yaml::from_str(&self.config_string)
+            .map_err(ConfigError::Parse)?;
+
+        let mut node_config = file.node;
+        for (name, service_config) in node_config.services.iter_mut() {
+            if service_config.name.is_empty() {
+                service_config.name = name.clone();
             }
         }
+
+        Ok(CoreConfig {
+            name: file.core.name,
+            plugins: file.core.plugins,
+            plugin_directories: file.core.plugin_directories,
+            node_config,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(rename = "Core")]
+    core: CoreSection,
+    #[serde(rename = "Node")]
+    node: NodeConfig,
+}
+
+#[derive(Deserialize)]
+struct CoreSection {
+    name: String,
+    #[serde(default)]
+    plugins: Vec<String>,
+    #[serde(default)]
+    plugin_directories: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(serde_yaml::Error),
+    NoConfigFile,
+    UnsupportedChange(String),
+    UnknownServiceType { service: String, service_type: String },
+    MissingParameter { service: String, parameter: String },
+    InvalidParameter { service: String, parameter: String, value: String, reason: String },
+    ServiceStart { service: String, reason: String },
+    Plugin(PluginError),
+    PluginInUse { plugin: String, service: String },
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(_, e) => Some(e),
+            ConfigError::Parse(e) => Some(e),
+            ConfigError::Plugin(e) => Some(e),
+            _ => None,
+        }
     }
+}
 
-    pub fn build(&mut self) -> CoreConfig {
-        CoreConfig{ name: "".to_string(), node_config: NodeConfig { name: "".to_string(), service_config: ServiceConfig {
-            name: "".to_string(),
-            operation_config: Default::default(),
-            parameters: Default::default()
-        } } }
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "can`t read config file {}: {}", path.display(), e),
+            ConfigError::Parse(e) => write!(f, "can`t parse config: {}", e),
+            ConfigError::NoConfigFile => write!(f, "core was not started from a config file"),
+            ConfigError::UnsupportedChange(change) => write!(f, "{} can`t be changed without restart", change),
+            ConfigError::UnknownServiceType { service, service_type } => {
+                write!(f, "service {} has unknown type \"{}\"", service, service_type)
+            }
+            ConfigError::MissingParameter { service, parameter } => {
+                write!(f, "parameter {} of service {} is missing", parameter, service)
+            }
+            ConfigError::InvalidParameter { service, parameter, value, reason } => {
+                write!(f, "parameter {} of service {} has invalid value \"{}\": {}", parameter, service, value, reason)
+            }
+            ConfigError::ServiceStart { service, reason } => write!(f, "can`t start service {}: {}", service, reason),
+            ConfigError::Plugin(e) => write!(f, "{}", e),
+            ConfigError::PluginInUse { plugin, service } => {
+                write!(f, "plugin {} can`t be removed while service {} uses it", plugin, service)
+            }
+        }
     }
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct CoreConfig {
     name: String,
+    plugins: Vec<String>,
+    plugin_directories: Vec<PathBuf>,
     node_config: NodeConfig,
 }
 
 impl CoreConfig {
-    pub fn new(name: String, config_string: String) {
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn plugins(&self) -> &Vec<String> {
+        &self.plugins
+    }
+
+    /// Directories scanned for plugins with a manifest, see `plugin::manifest`
+    pub fn plugin_directories(&self) -> &Vec<PathBuf> {
+        &self.plugin_directories
+    }
+
+    pub fn node_config(&self) -> &NodeConfig {
+        &self.node_config
+    }
+
+    /// Compares this (running) config with a new one and lists what has to be done
+    /// to move from the first to the second.
+    pub fn diff(&self, new: &CoreConfig) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+
+        let old_plugins: HashSet<&String> = self.plugins.iter().collect();
+        let new_plugins: HashSet<&String> = new.plugins.iter().collect();
+        diff.plugins_added = new_plugins.difference(&old_plugins).map(|p| p.to_string()).collect();
+        diff.plugins_removed = old_plugins.difference(&new_plugins).map(|p| p.to_string()).collect();
+
+        let old_directories: HashSet<&PathBuf> = self.plugin_directories.iter().collect();
+        let new_directories: HashSet<&PathBuf> = new.plugin_directories.iter().collect();
+        diff.plugin_directories_added = new_directories.difference(&old_directories).map(|d| d.to_path_buf()).collect();
+        diff.plugin_directories_removed = old_directories.difference(&new_directories).map(|d| d.to_path_buf()).collect();
+
+        let old_services = &self.node_config.services;
+        let new_services = &new.node_config.services;
+
+        for (name, new_service) in new_services {
+            match old_services.get(name) {
+                None => diff.services_added.push(name.clone()),
+                Some(old_service) => {
+                    if old_service.service_type != new_service.service_type
+                        || old_service.parameters != new_service.parameters {
+                        diff.services_restarted.push(name.clone());
+                    } else if old_service.operation_config != new_service.operation_config
+                        || old_service.subscribe_on_messages != new_service.subscribe_on_messages {
+                        diff.routes_changed.push(name.clone());
+                    }
+                }
+            }
+        }
+
+        for name in old_services.keys() {
+            if !new_services.contains_key(name) {
+                diff.services_removed.push(name.clone());
+            }
+        }
 
+        diff.sort();
+        diff
     }
 }
 
+/// Changes between two versions of `CoreConfig`, see `CoreConfig::diff`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigDiff {
+    pub plugins_added: Vec<String>,
+    pub plugins_removed: Vec<String>,
+    pub plugin_directories_added: Vec<PathBuf>,
+    pub plugin_directories_removed: Vec<PathBuf>,
+    pub services_added: Vec<String>,
+    pub services_removed: Vec<String>,
+    pub services_restarted: Vec<String>,
+    pub routes_changed: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.plugins_added.is_empty()
+            && self.plugins_removed.is_empty()
+            && self.plugin_directories_added.is_empty()
+            && self.plugin_directories_removed.is_empty()
+            && self.services_added.is_empty()
+            && self.services_removed.is_empty()
+            && self.services_restarted.is_empty()
+            && self.routes_changed.is_empty()
+    }
+
+    fn sort(&mut self) {
+        self.plugins_added.sort();
+        self.plugins_removed.sort();
+        self.plugin_directories_added.sort();
+        self.plugin_directories_removed.sort();
+        self.services_added.sort();
+        self.services_removed.sort();
+        self.services_restarted.sort();
+        self.routes_changed.sort();
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
 pub struct NodeConfig {
     name: String,
-    service_config: ServiceConfig,
+    #[serde(flatten)]
+    services: HashMap<String, ServiceConfig>,
 }
 
-#[derive(Debug)]
+impl NodeConfig {
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn services(&self) -> &HashMap<String, ServiceConfig> {
+        &self.services
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
 pub struct ServiceConfig {
+    #[serde(default)]
     pub name: String,
+    #[serde(rename = "type", default)]
+    pub service_type: String,
+    #[serde(rename = "operations", default)]
     pub operation_config: HashMap<String, OperationConfig>,
+    #[serde(default, deserialize_with = "deserialize_parameters")]
     pub parameters: HashMap<String, String>,
+    #[serde(default)]
+    pub subscribe_on_messages: Vec<String>,
 }
 
-#[derive(Debug)]
+impl ServiceConfig {
+    pub fn operations(&self) -> Vec<Operation> {
+        self.operation_config.values()
+            .map(|config| Operation::new(config.name.clone(), config.version.clone(), config.description.clone()))
+            .collect()
+    }
+
+    pub fn parameter(&self, parameter: &str) -> Result<&String, ConfigError> {
+        self.parameters.get(parameter).ok_or_else(|| ConfigError::MissingParameter {
+            service: self.name.clone(),
+            parameter: parameter.to_string(),
+        })
+    }
+
+    pub fn parse_parameter<T>(&self, parameter: &str) -> Result<T, ConfigError>
+        where T: FromStr, T::Err: Display
+    {
+        let value = self.parameter(parameter)?;
+        value.parse().map_err(|e: T::Err| ConfigError::InvalidParameter {
+            service: self.name.clone(),
+            parameter: parameter.to_string(),
+            value: value.clone(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// Like `parse_parameter`, but a missing parameter falls back to `default`.
+    pub fn parse_parameter_or<T>(&self, parameter: &str, default: T) -> Result<T, ConfigError>
+        where T: FromStr, T::Err: Display
+    {
+        match self.parameters.contains_key(parameter) {
+            true => self.parse_parameter(parameter),
+            false => Ok(default),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct OperationConfig {
     name: String,
     version: Version,
-    description: String
+    #[serde(default)]
+    description: String,
+}
+
+/// Parameters are plain strings for services, but it is handy to write `port: 5038` in yaml.
+fn deserialize_parameters<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+    where D: Deserializer<'de>
+{
+    let values: HashMap<String, serde_yaml::Value> = HashMap::deserialize(deserializer)?;
+    let mut parameters = HashMap::with_capacity(values.len());
+
+    for (name, value) in values {
+        let value = match value {
+            serde_yaml::Value::String(string) => string,
+            serde_yaml::Value::Bool(boolean) => boolean.to_string(),
+            serde_yaml::Value::Number(number) => number.to_string(),
+            serde_yaml::Value::Null => String::new(),
+            _ => return Err(serde::de::Error::custom(format!("parameter {} must be a scalar", name))),
+        };
+        parameters.insert(name, value);
+    }
+
+    Ok(parameters)
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::config::ConfigBuilder;
+
+    const CONFIG: &str = r#"
+Core:
+  name: "TestCore"
+  plugins:
+    - "libtelnet.so"
+  plugin_directories:
+    - "/usr/lib/any_message"
+Node:
+  name: "Node01"
+  ServiceOne:
+    type: "TelnetService"
+    parameters:
+      host: "127.0.0.1"
+      port: 5038
+    operations:
+      TestOperation01:
+        name: "TestOperation01"
+        version: "0.1.12"
+    subscribe_on_messages:
+      - "TestMessage01"
+  ServiceTwo:
+    name: "ServiceTwo"
+"#;
+
     #[test]
     fn test() {
-        
+        let config = ConfigBuilder::from_string(CONFIG.to_string()).build().unwrap();
+        let service = &config.node_config().services()["ServiceOne"];
+
+        assert_eq!(config.name(), "TestCore");
+        assert_eq!(config.plugin_directories()[0].to_str(), Some("/usr/lib/any_message"));
+        assert_eq!(service.name, "ServiceOne");
+        assert_eq!(service.parameters["port"], "5038");
+        assert_eq!(service.operations().len(), 1);
+        assert_eq!(service.parse_parameter::<u16>("port").unwrap(), 5038);
+        assert_eq!(service.parse_parameter_or::<u32>("buffer_size", 1024).unwrap(), 1024);
+        assert!(service.parse_parameter::<u16>("host").is_err());
+        assert!(service.parameter("message_type").is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_diff() {
+        let old = ConfigBuilder::from_string(CONFIG.to_string()).build().unwrap();
+        let new = ConfigBuilder::from_string(CONFIG
+            .replace("5038", "5039")
+            .replace("TestMessage01", "TestMessage02")
+            .replace("ServiceTwo", "ServiceThree")
+            .replace("libtelnet.so", "libtcp.so")).build().unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.services_restarted, vec!["ServiceOne".to_string()]);
+        assert_eq!(diff.services_added, vec!["ServiceThree".to_string()]);
+        assert_eq!(diff.services_removed, vec!["ServiceTwo".to_string()]);
+        assert_eq!(diff.plugins_added, vec!["libtcp.so".to_string()]);
+        assert_eq!(diff.plugins_removed, vec!["libtelnet.so".to_string()]);
+        assert!(diff.routes_changed.is_empty());
+
+        let routes_only = ConfigBuilder::from_string(CONFIG.replace("TestMessage01", "TestMessage02")).build().unwrap();
+        assert_eq!(old.diff(&routes_only).routes_changed, vec!["ServiceOne".to_string()]);
+        assert!(old.diff(&old).is_empty());
+    }
+}
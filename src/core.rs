@@ -1,19 +1,47 @@
 use crate::error::Error;
 use crate::node::Node;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use actix::{Addr, Arbiter, Actor, ArbiterHandle};
-use crate::signal::{Heartbeat, RegisterServiceInNodeSignal};
-use std::time::Duration;
-use log::{info, trace, error, debug};
-use crate::service::{Service, ServiceCore, ServiceFunctions};
-use crate::transport::Transport;
+use crate::signal::{Heartbeat, RegisterServiceInNodeSignal, UnregisterServiceInNodeSignal, UpdateServiceRoutesSignal};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use actix::MailboxError;
+use log::{info, debug, trace, error, warn};
+use crate::service::{Service, ServiceCore};
+use crate::transport::{Transport, TransportError};
+use crate::metrics::MetricsServer;
+use actix_rt::net::TcpListener;
+use std::net::SocketAddr;
 use crate::message::Parcel;
-use crate::config::ServiceConfig;
-use crate::plugin::PluginManager;
+use crate::config::{ServiceConfig, CoreConfig, ConfigBuilder, ConfigDiff, ConfigError};
+use crate::operation::Operation;
+use crate::plugin::{PluginManager, PluginError, PluginStatus, ServiceFactory};
+use crate::plugin::process::process_service;
+use crate::plugin::wasm::wasm_service;
+use crate::services::tcp::tcp_service;
+use crate::services::tcp_client::tcp_client_service;
+use crate::services::file::file_service;
+use crate::services::webhook::webhook_service;
+use crate::services::websocket::websocket_service;
+use crate::services::udp::udp_service;
+#[cfg(unix)]
+use crate::services::unix::unix_service;
+#[cfg(unix)]
+use crate::services::unix_client::unix_client_service;
+use crate::interceptor::{Interceptor, InterceptorChain, InterceptorError};
+use crate::plugin::manifest::{manifest_path, DiscoveredPlugin, PluginDiscovery, PluginManifest, SkippedPlugin};
 
 
 type ServiceTypeName = String;
 
+type Command = (CoreCommand, oneshot::Sender<Result<(), Error>>);
+
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct CoreBuilder<F>
     where
         F: Fn() -> Node,
@@ -21,7 +49,9 @@ pub struct CoreBuilder<F>
     factory: F,
     service_builders: HashMap<String, Box<fn(Addr<Node>) -> Box<dyn Service>>>,
     plugins: Vec<String>,
-    plugin_manager: PluginManager,
+    plugin_directories: Vec<PathBuf>,
+    config_path: Option<PathBuf>,
+    metrics_address: Option<String>,
 }
 
 impl<F> CoreBuilder<F>
@@ -31,9 +61,11 @@ impl<F> CoreBuilder<F>
     pub fn new(factory: F) -> CoreBuilder<F> {
         let builder = CoreBuilder {
             factory,
-            plugin_manager: PluginManager::new(),
             plugins: vec![],
+            plugin_directories: vec![],
             service_builders: HashMap::default(),
+            config_path: None,
+            metrics_address: None,
         };
 
         builder
@@ -44,14 +76,35 @@ impl<F> CoreBuilder<F>
         self
     }
 
+    /// Plugins with a manifest in `path` are loaded on build, see `plugin::manifest`
+    pub fn plugin_directory<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.plugin_directories.push(path.as_ref().to_path_buf());
+        self
+    }
+
     pub fn service(&mut self, service_name: String, config: fn(Addr<Node>) -> Box<dyn Service>) -> &mut Self {
         self.service_builders.insert(service_name, Box::from(config));
 
         self
     }
 
-    pub async fn build(&mut self) -> Core {
-        let node = (self.factory)();
+    /// Services and plugins from the file are started on build, the file is watched
+    /// for changes (and SIGHUP) while the core runs.
+    pub fn config_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.config_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Serves Prometheus metrics on `address`, e.g. "0.0.0.0:9100"
+    pub fn metrics(&mut self, address: &str) -> &mut Self {
+        self.metrics_address = Some(address.to_string());
+        self
+    }
+
+    pub async fn build(&mut self) -> Result<Core, Error> {
+        let mut node = (self.factory)();
+        let interceptors = InterceptorChain::new();
+        node.set_interceptors(interceptors.clone());
 
         let arbiter = Arbiter::new().handle();
         let node = Node::start_in_arbiter(&arbiter, |_ctx| {
@@ -59,90 +112,210 @@ impl<F> CoreBuilder<F>
         });
 
 
+        let (commands, command_receiver) = unbounded_channel();
         let mut core = Core {
             arbiter,
             node: node.clone(),
             service_factories: Default::default(),
             services: Default::default(),
+            plugin_manager: PluginManager::new(),
+            plugin_service_types: Default::default(),
+            loading_plugin: None,
+            interceptors,
+            config: None,
+            config_path: self.config_path.clone(),
+            config_modified: None,
+            commands,
+            command_receiver,
         };
+        core.register_builtin_services();
+
+        if let Some(address) = &self.metrics_address {
+            core.serve_metrics(address)?;
+        }
 
         for (service_name, service_builder) in &self.service_builders {
-            let mut service = service_builder(node.clone());
+            let service = service_builder(node.clone());
+            core.start_service(service_name.clone(), service, None).await?;
+        }
 
-            let mut service_core = ServiceCore::new(service_name.clone(), node.clone());
-            service.config_system(&mut service_core, node.clone());
+        for plugin in &self.plugins {
+            trace!("Loading plugin in build");
+            core.load_plugin(plugin)?;
+        }
 
-            let arbiter = Arbiter::new().handle();
-            let consumed_messages = service_core.get_consuming_message_types();
-            let operations = service_core.get_operations().clone();
+        if !self.plugin_directories.is_empty() {
+            core.load_plugin_directories(&self.plugin_directories)?;
+        }
 
-            let service_addr = ServiceCore::start_in_arbiter(&arbiter, |_ctx| {
-                service_core
-            });
+        if core.config_path.is_some() {
+            core.reload().await?;
+        }
 
-            match node.send(RegisterServiceInNodeSignal {
-                transport: Transport::new(service_addr.clone().recipient::<Parcel>()),
-                name: service_name.clone(),
-                operations: operations.clone(),
-                consume_messages: consumed_messages,
-            }).await {
-                Err(e) => {
-                    error!("Error {:?}", e);
+        Ok(core)
+    }
+}
+
+struct RunningService {
+    arbiter: Arbiter,
+    config: Option<ServiceConfig>,
+    transport: Transport,
+    operations: Vec<Operation>,
+    consume_messages: Vec<String>,
+}
+
+impl RunningService {
+    /// Routes declared by the service itself extended with the ones from its config
+    fn routes(&self, config: Option<&ServiceConfig>) -> (Vec<Operation>, Vec<String>) {
+        let mut operations = self.operations.clone();
+        let mut consume_messages = self.consume_messages.clone();
+
+        if let Some(config) = config {
+            for operation in config.operations() {
+                if !operations.contains(&operation) {
+                    operations.push(operation);
                 }
-                _ => {}
             }
-        }
 
-        for plugin in &self.plugins {
-            trace!("Loading plugin in build");
-            trace!("{:?}",std::fs::File::open(plugin.clone()));
-            unsafe {
-                match self.plugin_manager.load_plugin(plugin, &mut core) {
-                    Ok(_result) => {
-                        trace!("Plugin loaded!");
-                    }
-                    Err(e) => {
-                        error!("Error while loading plugin\n{:?}", e);
-                    }
+            for message_type in &config.subscribe_on_messages {
+                if !consume_messages.contains(message_type) {
+                    consume_messages.push(message_type.clone());
                 }
             }
         }
 
-
-        core
+        (operations, consume_messages)
     }
 }
 
 pub struct Core {
     arbiter: ArbiterHandle,
     node: Addr<Node>,
-    service_factories: HashMap<ServiceTypeName, Box<extern "C" fn(&ServiceConfig) -> Result<ServiceFunctions, Box<dyn std::error::Error>>>>,
-    services: HashMap<String, Addr<ServiceCore>>,
+    service_factories: HashMap<ServiceTypeName, ServiceFactory>,
+    services: HashMap<String, RunningService>,
+    plugin_manager: PluginManager,
+    /// Service types registered by each plugin
+    plugin_service_types: HashMap<String, Vec<ServiceTypeName>>,
+    loading_plugin: Option<String>,
+    interceptors: InterceptorChain,
+    config: Option<CoreConfig>,
+    config_path: Option<PathBuf>,
+    config_modified: Option<SystemTime>,
+    commands: UnboundedSender<Command>,
+    command_receiver: UnboundedReceiver<Command>,
+}
+
+/// What a `CoreHandle` asks the running core to do
+enum CoreCommand {
+    UnloadPlugin(String),
+    ReloadPlugin(String),
+}
+
+/// Reaches a core while `Core::run` holds it, the commands are carried out between heartbeats
+#[derive(Clone)]
+pub struct CoreHandle {
+    commands: UnboundedSender<Command>,
+}
+
+impl CoreHandle {
+    /// See `Core::unload_plugin`
+    pub async fn unload_plugin(&self, name: &str) -> Result<(), Error> {
+        self.send(CoreCommand::UnloadPlugin(name.to_string())).await
+    }
+
+    /// See `Core::reload_plugin`
+    pub async fn reload_plugin(&self, name: &str) -> Result<(), Error> {
+        self.send(CoreCommand::ReloadPlugin(name.to_string())).await
+    }
+
+    async fn send(&self, command: CoreCommand) -> Result<(), Error> {
+        let (reply, answer) = oneshot::channel();
+        self.commands.send((command, reply)).map_err(|_e| MailboxError::Closed)?;
+        answer.await.map_err(|_e| MailboxError::Closed)?
+    }
 }
 
 impl Core {
     pub fn new(name: String) -> Self {
         info!("AnyMessage core started!");
         let arbiter = Arbiter::new().handle();
+        let interceptors = InterceptorChain::new();
+        let mut node = Node::new(name);
+        node.set_interceptors(interceptors.clone());
         let node = Node::start_in_arbiter(&arbiter, |_ctx| {
-            Node::new(name)
+            node
         });
 
-        Core {
+        let (commands, command_receiver) = unbounded_channel();
+        let mut core = Core {
             arbiter,
             node,
             service_factories: Default::default(),
             services: Default::default(),
+            plugin_manager: PluginManager::new(),
+            plugin_service_types: Default::default(),
+            loading_plugin: None,
+            interceptors,
+            config: None,
+            config_path: None,
+            config_modified: None,
+            commands,
+            command_receiver,
+        };
+        core.register_builtin_services();
+
+        core
+    }
+
+    /// Service types every core has, plugins add more. They are built into the host,
+    /// so their declarations are not checked.
+    fn register_builtin_services(&mut self) {
+        self.service_factories.insert("ProcessService".to_string(), process_service);
+        self.service_factories.insert("WasmService".to_string(), wasm_service);
+        self.service_factories.insert("TcpService".to_string(), tcp_service);
+        self.service_factories.insert("TcpClientService".to_string(), tcp_client_service);
+        self.service_factories.insert("FileService".to_string(), file_service);
+        self.service_factories.insert("WebhookService".to_string(), webhook_service);
+        self.service_factories.insert("WebSocketService".to_string(), websocket_service);
+        self.service_factories.insert("UdpService".to_string(), udp_service);
+        #[cfg(unix)]
+        {
+            self.service_factories.insert("UnixService".to_string(), unix_service);
+            self.service_factories.insert("UnixClientService".to_string(), unix_client_service);
         }
     }
 
-    pub async fn run(&self) -> Result<(), Error> {
+    pub async fn run(&mut self) -> Result<(), Error> {
+        let reload_requested = Self::watch_reload_signal();
+        let mut config_checked = Instant::now();
+
         loop {
-            match self.node.send(Heartbeat {}).await {
-                Ok(()) => {}
-                Err(_e) => {}
+            if let Err(e) = self.node.send(Heartbeat {}).await {
+                error!("Node doesn`t answer heartbeat {:?}", e);
+            }
+
+            let mut reload = reload_requested.swap(false, Ordering::SeqCst);
+            if config_checked.elapsed() >= CONFIG_CHECK_INTERVAL {
+                config_checked = Instant::now();
+                reload = reload || self.config_changed();
+            }
+
+            if reload {
+                match self.reload().await {
+                    Ok(diff) => info!("Config reloaded {:?}", diff),
+                    Err(e) => error!("Config reload failed, keeping previous config: {}", e),
+                }
             }
-            std::thread::sleep(Duration::from_millis(50));
+
+            while let Ok((command, reply)) = self.command_receiver.try_recv() {
+                let result = match command {
+                    CoreCommand::UnloadPlugin(name) => self.unload_plugin(&name).await,
+                    CoreCommand::ReloadPlugin(name) => self.reload_plugin(&name).await,
+                };
+                let _ = reply.send(result);
+            }
+
+            actix_rt::time::sleep(Duration::from_millis(50)).await;
         };
     }
 
@@ -150,9 +323,586 @@ impl Core {
         self.node.clone()
     }
 
+    /// Handle for other tasks, its commands are carried out by `run`
+    pub fn handle(&self) -> CoreHandle {
+        CoreHandle { commands: self.commands.clone() }
+    }
+
+    pub fn config(&self) -> Option<&CoreConfig> {
+        self.config.as_ref()
+    }
+
+    /// Starts the Prometheus endpoint in the core's arbiter, returns the bound address.
+    pub fn serve_metrics(&self, address: &str) -> Result<SocketAddr, Error> {
+        let listener = std::net::TcpListener::bind(address).map_err(TransportError::from)?;
+        listener.set_nonblocking(true).map_err(TransportError::from)?;
+        let local_address = listener.local_addr().map_err(TransportError::from)?;
+
+        let server = MetricsServer::new(self.node.clone(), self.plugin_manager.statuses());
+        self.arbiter.spawn(async move {
+            match TcpListener::from_std(listener) {
+                Ok(listener) => server.serve(listener).await,
+                Err(e) => error!("Can`t serve metrics {:?}", e),
+            }
+        });
+
+        Ok(local_address)
+    }
 
+    /// Registers a service type, the factory is only used if it was built like the host.
+    pub fn service_config(&mut self, service_type_name: ServiceTypeName, service_factory: ServiceFactory) -> Result<(), PluginError> {
+        let origin = self.loading_plugin.clone().unwrap_or_else(|| service_type_name.clone());
+        // Built by `ServiceFactory::new`, the declaration points to static C strings
+        unsafe { service_factory.declaration.check(&origin)? };
 
-    pub fn service_config(&mut self, service_type_name: ServiceTypeName, service_factory: Box<extern "C" fn(&ServiceConfig) -> Result<ServiceFunctions, Box<dyn std::error::Error>>>) {
+        if let Some(plugin) = &self.loading_plugin {
+            self.plugin_service_types.entry(plugin.clone()).or_default().push(service_type_name.clone());
+        }
         self.service_factories.insert(service_type_name, service_factory);
+        Ok(())
+    }
+
+    /// Adds the interceptor to the node and every service, see `interceptor`.
+    /// Interceptors added by a plugin in `on_load` are removed when it is unloaded.
+    pub fn add_interceptor(&mut self, interceptor: Box<dyn Interceptor>) -> Result<(), InterceptorError> {
+        self.interceptors.add(self.loading_plugin.clone(), interceptor)
+    }
+
+    pub fn remove_interceptor(&mut self, name: &str) -> bool {
+        self.interceptors.remove(name)
+    }
+
+    pub fn interceptors(&self) -> Vec<String> {
+        self.interceptors.names()
+    }
+
+    /// Service types and interceptors registered while `plugin` is set belong to that plugin
+    pub(crate) fn set_loading_plugin(&mut self, plugin: Option<String>) {
+        self.loading_plugin = plugin;
+    }
+
+    pub fn load_plugin(&mut self, filename: &str) -> Result<(), PluginError> {
+        if self.plugin_manager.is_loaded(filename) {
+            trace!("Plugin {} is already loaded", filename);
+            return Ok(());
+        }
+
+        self.with_plugin_manager(|plugin_manager, core| unsafe { plugin_manager.load_plugin(filename, core) })
+    }
+
+    /// Loads the plugins found in `directories` in dependency order. Plugins that can`t be
+    /// loaded are skipped and reported in `plugins()`, only an unreadable directory is an error.
+    pub fn load_plugin_directories(&mut self, directories: &[PathBuf]) -> Result<(), PluginError> {
+        let discovery = PluginDiscovery::scan(directories)?;
+        for skipped in &discovery.skipped {
+            self.plugin_manager.skip(skipped);
+        }
+
+        let mut unavailable: HashSet<String> = discovery.skipped.iter()
+            .filter_map(|skipped| skipped.manifest.as_ref().map(|manifest| manifest.name.clone()))
+            .collect();
+
+        for plugin in &discovery.plugins {
+            let name = &plugin.manifest.name;
+            if let Some(dependency) = plugin.manifest.depends_on.iter().find(|dependency| unavailable.contains(*dependency)) {
+                self.plugin_manager.skip(&SkippedPlugin {
+                    path: plugin.path(),
+                    manifest: Some(plugin.manifest.clone()),
+                    error: PluginError::DependencyUnavailable { path: plugin.path(), dependency: dependency.clone() },
+                });
+                unavailable.insert(name.clone());
+                continue;
+            }
+
+            if self.plugin_manager.is_loaded(plugin.path()) {
+                trace!("Plugin {} is already loaded", name);
+                continue;
+            }
+
+            match self.with_plugin_manager(|plugin_manager, core| unsafe { plugin_manager.load_discovered(plugin, core) }) {
+                Ok(()) => {
+                    for service_type in &plugin.manifest.provides {
+                        if !self.service_factories.contains_key(service_type) {
+                            warn!("Plugin {} doesn`t register the service type {} from its manifest", name, service_type);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Plugin {} is not loaded: {}", name, e);
+                    unavailable.insert(name.clone());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops the services built from the plugin's service types, calls `on_unload` of the plugin
+    /// and drops its library. The services stay in the config, `reload_plugin` starts them again.
+    pub async fn unload_plugin(&mut self, name: &str) -> Result<(), Error> {
+        self.take_plugin(name).await?;
+        Ok(())
+    }
+
+    /// Unloads the plugin and loads its library again from the same path, e.g. after the
+    /// library file was replaced. The services of the plugin are restarted with their configs.
+    pub async fn reload_plugin(&mut self, name: &str) -> Result<(), Error> {
+        let manifest_known = self.plugin_manager.loaded(name)
+            .ok_or_else(|| PluginError::NotLoaded { name: name.to_string() })?
+            .manifest().is_some();
+
+        let (path, stopped) = self.take_plugin(name).await?;
+
+        if manifest_known {
+            let library = PathBuf::from(&path);
+            let manifest = PluginManifest::from_file(&manifest_path(&library))?;
+            manifest.check(&path)?;
+            let plugin = DiscoveredPlugin { library, manifest };
+            self.with_plugin_manager(|plugin_manager, core| unsafe { plugin_manager.load_discovered(&plugin, core) })?;
+        } else {
+            self.load_plugin(&path)?;
+        }
+        info!("Plugin {} reloaded from {}", name, path);
+
+        let mut result = Ok(());
+        for service_config in stopped {
+            let name = service_config.name.clone();
+            let started = match self.create_service(&service_config) {
+                Ok(service) => self.start_service(name.clone(), service, Some(service_config)).await,
+                Err(e) => Err(e.into()),
+            };
+
+            if let Err(e) = started {
+                error!("Service {} is not restarted after plugin reload: {}", name, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Unloads the plugin, returns its path and the configs of the services stopped for that
+    async fn take_plugin(&mut self, name: &str) -> Result<(String, Vec<ServiceConfig>), Error> {
+        if self.plugin_manager.loaded(name).is_none() {
+            return Err(PluginError::NotLoaded { name: name.to_string() }.into());
+        }
+
+        let service_types = self.plugin_service_types.remove(name).unwrap_or_default();
+        let mut services: Vec<String> = self.services.iter()
+            .filter(|(_, running)| {
+                running.config.as_ref().is_some_and(|config| service_types.contains(&config.service_type))
+            })
+            .map(|(service, _)| service.clone())
+            .collect();
+        services.sort();
+
+        // The plugin code must not be running anymore when the library is dropped
+        let mut stopped = vec![];
+        for service in services {
+            if let Some(config) = self.services.get(&service).and_then(|running| running.config.clone()) {
+                stopped.push(config);
+            }
+            self.stop_service(&service).await;
+        }
+
+        for service_type in &service_types {
+            self.service_factories.remove(service_type);
+        }
+        let interceptors = self.interceptors.remove_owned_by(name);
+        if !interceptors.is_empty() {
+            debug!("Removed interceptors {:?} of plugin {}", interceptors, name);
+        }
+
+        let path = self.plugin_manager.unload_plugin(name)?;
+        info!("Plugin {} unloaded, stopped services {:?}", name, stopped.iter().map(|config| &config.name).collect::<Vec<_>>());
+
+        Ok((path, stopped))
+    }
+
+    /// Every plugin the core has tried to load, including skipped ones
+    pub fn plugins(&self) -> Vec<PluginStatus> {
+        match self.plugin_manager.statuses().lock() {
+            Ok(statuses) => statuses.clone(),
+            Err(e) => {
+                error!("Error to access plugin statuses {:?}", e);
+                vec![]
+            }
+        }
+    }
+
+    fn with_plugin_manager<T>(&mut self, f: impl FnOnce(&mut PluginManager, &mut Core) -> T) -> T {
+        // The manager is taken out while the plugin registers itself in the core
+        let mut plugin_manager = std::mem::take(&mut self.plugin_manager);
+        let result = f(&mut plugin_manager, self);
+        self.plugin_manager = plugin_manager;
+
+        result
+    }
+
+    /// Reads the config file again and applies the difference with the running config.
+    pub async fn reload(&mut self) -> Result<ConfigDiff, ConfigError> {
+        let path = self.config_path.clone().ok_or(ConfigError::NoConfigFile)?;
+        let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        let config_string = std::fs::read_to_string(&path)
+            .map_err(|e| ConfigError::Io(path.clone(), e))?;
+
+        // Remember the attempt even if it fails, so a broken file is reported once
+        self.config_modified = modified;
+        let config = ConfigBuilder::from_string(config_string).build()?;
+
+        self.apply_config(config).await
+    }
+
+    /// Starts, stops and restarts services so that the core runs `config`.
+    /// The config is checked before anything is changed. If a service fails to start after
+    /// that, the services of the previous config are started again and the plugins loaded
+    /// for `config` are unloaded before the error is returned.
+    pub async fn apply_config(&mut self, config: CoreConfig) -> Result<ConfigDiff, ConfigError> {
+        let current = match &self.config {
+            Some(current) => {
+                if current.node_config().name() != config.node_config().name() {
+                    return Err(ConfigError::UnsupportedChange("node name".to_string()));
+                }
+                current.clone()
+            }
+            None => CoreConfig::default(),
+        };
+
+        let diff = current.diff(&config);
+        if diff.is_empty() {
+            self.config = Some(config);
+            return Ok(diff);
+        }
+
+        let removed_plugins = self.check_config(&config, &diff)?;
+
+        let loaded_plugins = self.plugin_manager.names();
+        if let Err(e) = self.load_config_plugins(&config, &diff) {
+            self.unload_plugins_since(&loaded_plugins).await;
+            return Err(e);
+        }
+
+        // Services are stopped before they are started again, a listener has to release its address first
+        let mut stopped = vec![];
+        let mut started = vec![];
+        let mut result = Ok(());
+        for name in diff.services_removed.iter().chain(diff.services_restarted.iter()) {
+            self.stop_service(name).await;
+            stopped.push(name.clone());
+        }
+        for name in diff.services_restarted.iter().chain(diff.services_added.iter()) {
+            match self.start_configured_service(name, &config.node_config().services()[name]).await {
+                Ok(()) => started.push(name.clone()),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        if let Err(e) = result {
+            error!("Config is not applied, restoring the previous services: {}", e);
+            for name in &started {
+                self.stop_service(name).await;
+            }
+            for name in &stopped {
+                if let Err(e) = self.start_configured_service(name, &current.node_config().services()[name]).await {
+                    error!("Service {} is not restored: {}", name, e);
+                }
+            }
+            self.unload_plugins_since(&loaded_plugins).await;
+            return Err(e);
+        }
+
+        for name in &diff.routes_changed {
+            let service_config = config.node_config().services()[name].clone();
+            if let Some(running) = self.services.get_mut(name) {
+                let (operations, consume_messages) = running.routes(Some(&service_config));
+                running.config = Some(service_config);
+                self.node.do_send(UpdateServiceRoutesSignal {
+                    name: name.clone(),
+                    operations,
+                    consume_messages,
+                });
+            }
+        }
+
+        for name in removed_plugins {
+            if let Err(e) = self.unload_plugin(&name).await {
+                error!("Can`t unload plugin {}: {}", name, e);
+            }
+        }
+
+        self.config = Some(config);
+        Ok(diff)
+    }
+
+    /// Checks what can be checked without loading the plugins of `config`,
+    /// returns the names of the plugins to unload.
+    fn check_config(&self, config: &CoreConfig, diff: &ConfigDiff) -> Result<Vec<String>, ConfigError> {
+        let mut removed_plugins = vec![];
+        let mut removed_service_types = vec![];
+        for path in &diff.plugins_removed {
+            let name = match self.plugin_manager.loaded_from(path) {
+                Some(plugin) => plugin.name().clone(),
+                None => continue,
+            };
+
+            let service_types = self.plugin_service_types.get(&name).cloned().unwrap_or_default();
+            let used_by = config.node_config().services().values()
+                .find(|service| service_types.contains(&service.service_type));
+            if let Some(service) = used_by {
+                return Err(ConfigError::PluginInUse { plugin: path.clone(), service: service.name.clone() });
+            }
+            removed_plugins.push(name);
+            removed_service_types.extend(service_types);
+        }
+
+        // Service types of new plugins are only known once they are loaded
+        let plugins_added = !diff.plugins_added.is_empty() || !diff.plugin_directories_added.is_empty();
+        for name in diff.services_added.iter().chain(diff.services_restarted.iter()) {
+            let service_type = &config.node_config().services()[name].service_type;
+            let known = self.service_factories.contains_key(service_type) && !removed_service_types.contains(service_type);
+            if !known && !plugins_added {
+                return Err(ConfigError::UnknownServiceType { service: name.clone(), service_type: service_type.clone() });
+            }
+        }
+
+        Ok(removed_plugins)
+    }
+
+    fn load_config_plugins(&mut self, config: &CoreConfig, diff: &ConfigDiff) -> Result<(), ConfigError> {
+        for plugin in &diff.plugins_added {
+            self.load_plugin(plugin).map_err(ConfigError::Plugin)?;
+        }
+
+        if !diff.plugin_directories_added.is_empty() {
+            // All directories are scanned, plugins may depend on plugins of other directories
+            self.load_plugin_directories(config.plugin_directories()).map_err(ConfigError::Plugin)?;
+        }
+
+        for directory in &diff.plugin_directories_removed {
+            warn!("Plugins of {} stay loaded until the core restarts", directory.display());
+        }
+
+        for name in diff.services_added.iter().chain(diff.services_restarted.iter()) {
+            let service_type = &config.node_config().services()[name].service_type;
+            if !self.service_factories.contains_key(service_type) {
+                return Err(ConfigError::UnknownServiceType { service: name.clone(), service_type: service_type.clone() });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unloads the plugins loaded after `loaded` was taken
+    async fn unload_plugins_since(&mut self, loaded: &[String]) {
+        for name in self.plugin_manager.names() {
+            if !loaded.contains(&name) {
+                if let Err(e) = self.unload_plugin(&name).await {
+                    error!("Can`t unload plugin {}: {}", name, e);
+                }
+            }
+        }
+    }
+
+    async fn start_configured_service(&mut self, name: &str, service_config: &ServiceConfig) -> Result<(), ConfigError> {
+        let service = self.create_service(service_config)?;
+        self.start_service(name.to_string(), service, Some(service_config.clone())).await
+            .map_err(|e| ConfigError::ServiceStart { service: name.to_string(), reason: e.to_string() })
+    }
+
+    fn create_service(&self, config: &ServiceConfig) -> Result<Box<dyn Service>, ConfigError> {
+        let factory = self.service_factories.get(&config.service_type)
+            .ok_or_else(|| ConfigError::UnknownServiceType {
+                service: config.name.clone(),
+                service_type: config.service_type.clone(),
+            })?;
+
+        let start_error = |e: Box<dyn std::error::Error>| ConfigError::ServiceStart {
+            service: config.name.clone(),
+            reason: e.to_string(),
+        };
+        let functions = factory.create(config).map_err(start_error)?;
+
+        (functions.on_start)(config.clone()).map_err(start_error)
+    }
+
+    /// Runs the service in its own arbiter and registers it in the node.
+    pub async fn start_service(&mut self, name: String, mut service: Box<dyn Service>, config: Option<ServiceConfig>) -> Result<(), Error> {
+        let mut service_core = ServiceCore::new(name.clone(), self.node.clone());
+        service_core.set_interceptors(self.interceptors.clone());
+        service.config_system(&mut service_core, self.node.clone());
+
+        let arbiter = Arbiter::new();
+        let consume_messages = service_core.get_consuming_message_types();
+        let operations = service_core.get_operations().clone();
+        let statistics = service_core.statistics();
+
+        let recipients = match service.spawn(&arbiter.handle()) {
+            Ok(recipients) => recipients,
+            Err(e) => {
+                arbiter.stop();
+                return Err(e.into());
+            }
+        };
+        service_core.recipients(recipients);
+        let service_addr = ServiceCore::start_in_arbiter(&arbiter.handle(), |_ctx| {
+            service_core
+        });
+
+        let running = RunningService {
+            arbiter,
+            transport: Transport::new(service_addr.clone().recipient::<Parcel>()),
+            operations,
+            consume_messages,
+            config: None,
+        };
+        let (operations, consume_messages) = running.routes(config.as_ref());
+        let transport = running.transport.clone();
+        self.services.insert(name.clone(), RunningService { config, ..running });
+
+        self.node.send(RegisterServiceInNodeSignal {
+            transport,
+            heartbeat: service_addr.recipient::<Heartbeat>(),
+            name,
+            operations,
+            consume_messages,
+            statistics,
+        }).await?;
+
+        Ok(())
+    }
+
+    /// Unregisters the service from the node and stops its arbiter, returns once the
+    /// arbiter thread is finished. Parcels for the service are kept by the node.
+    pub async fn stop_service(&mut self, name: &str) {
+        let running = match self.services.remove(name) {
+            Some(running) => running,
+            None => return,
+        };
+
+        if let Err(e) = self.node.send(UnregisterServiceInNodeSignal { name: name.to_string() }).await {
+            error!("Error {:?}", e);
+        }
+        running.arbiter.stop();
+
+        let arbiter = running.arbiter;
+        match tokio::task::spawn_blocking(move || arbiter.join()).await {
+            Ok(Ok(())) => trace!("Service {} stopped", name),
+            Ok(Err(_panic)) => error!("Service {} arbiter panicked", name),
+            Err(e) => error!("Can`t wait for service {} to stop {:?}", name, e),
+        }
+    }
+
+    fn config_changed(&self) -> bool {
+        let path = match &self.config_path {
+            Some(path) => path,
+            None => return false,
+        };
+
+        let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        modified.is_some() && modified != self.config_modified
+    }
+
+    #[cfg(unix)]
+    fn watch_reload_signal() -> Arc<AtomicBool> {
+        use actix_rt::signal::unix::{signal, SignalKind};
+
+        let requested = Arc::new(AtomicBool::new(false));
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                let requested = requested.clone();
+                actix_rt::spawn(async move {
+                    while hangup.recv().await.is_some() {
+                        info!("SIGHUP received, reloading config");
+                        requested.store(true, Ordering::SeqCst);
+                    }
+                });
+            }
+            Err(e) => error!("Can`t listen for SIGHUP {:?}", e),
+        }
+
+        requested
     }
-}
\ No newline at end of file
+
+    #[cfg(not(unix))]
+    fn watch_reload_signal() -> Arc<AtomicBool> {
+        Arc::new(AtomicBool::new(false))
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        // The node may still be running, but the plugin libraries go away with the plugin manager,
+        // so no service thread may run plugin code once it is dropped
+        self.interceptors.remove_plugin_interceptors();
+        for (name, running) in self.services.drain() {
+            self.node.do_send(UnregisterServiceInNodeSignal { name: name.clone() });
+            running.arbiter.stop();
+            match running.arbiter.join() {
+                Ok(()) => trace!("Service {} stopped", name),
+                Err(_panic) => error!("Service {} arbiter panicked", name),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::services::codec::{read_length_prefixed, write_length_prefixed};
+    use actix_rt::net::TcpStream;
+    use std::time::Duration;
+
+    const CONFIG: &str = r#"
+Core:
+  name: "ReloadCore"
+Node:
+  name: "Node01"
+  tcp:
+    type: "TcpService"
+    parameters:
+      address: "127.0.0.1:PORT"
+      target: "Consumer(TcpIn)"
+    subscribe_on_messages:
+      - "TcpIn"
+      - "TcpEcho"
+"#;
+
+    async fn echo(port: u16) -> Option<Vec<u8>> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        write_length_prefixed(&mut stream, b"ping").await.unwrap();
+        actix_rt::time::timeout(Duration::from_secs(2), read_length_prefixed(&mut stream, 1024)).await
+            .expect("No echo").unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_reload_listener() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = CONFIG.replace("PORT", &port.to_string());
+
+        let mut core = Core::new("Node01".to_string());
+        core.apply_config(ConfigBuilder::from_string(config.clone()).build().unwrap()).await.unwrap();
+        assert_eq!(echo(port).await, Some(b"ping".to_vec()));
+
+        // The listener is restarted on the same address
+        let changed = config.replace("Consumer(TcpIn)", "Consumer(TcpEcho)");
+        let diff = core.apply_config(ConfigBuilder::from_string(changed.clone()).build().unwrap()).await.unwrap();
+        assert_eq!(diff.services_restarted, vec!["tcp".to_string()]);
+        assert_eq!(echo(port).await, Some(b"ping".to_vec()));
+
+        // A service that can`t start brings the previous one back
+        let broken = changed.replace("target: \"Consumer(TcpEcho)\"", "max_frame_size: \"none\"");
+        assert!(core.apply_config(ConfigBuilder::from_string(broken).build().unwrap()).await.is_err());
+        assert_eq!(core.config().unwrap().node_config().services()["tcp"].parameters["target"], "Consumer(TcpEcho)");
+        assert_eq!(echo(port).await, Some(b"ping".to_vec()));
+
+        // Dropping the core stops its services, the address is free again
+        drop(core);
+        assert!(std::net::TcpListener::bind(("127.0.0.1", port)).is_ok());
+    }
+}
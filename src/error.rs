@@ -1,16 +1,119 @@
 use std::fmt::{Display, Formatter};
+use actix::MailboxError;
+use crate::config::ConfigError;
+use crate::topology::TopologyError;
+use crate::operation::OperationError;
+use crate::transport::TransportError;
+use crate::plugin::PluginError;
+use crate::message::CodecError;
+use crate::service::ServiceError;
 
 #[derive(Debug)]
 pub enum Error {
-    
+    Config(ConfigError),
+    Routing(TopologyError),
+    Operation(OperationError),
+    Transport(TransportError),
+    Plugin(PluginError),
+    Codec(CodecError),
+    Service(ServiceError),
+    Mailbox(MailboxError),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::Config(e) => write!(f, "config error: {}", e),
+            Error::Routing(e) => write!(f, "routing error: {}", e),
+            Error::Operation(e) => write!(f, "operation error: {}", e),
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::Plugin(e) => write!(f, "plugin error: {}", e),
+            Error::Codec(e) => write!(f, "codec error: {}", e),
+            Error::Service(e) => write!(f, "service error: {}", e),
+            Error::Mailbox(e) => write!(f, "actor mailbox error: {}", e),
+        }
     }
 }
 
 impl std::error::Error for Error {
-    
-}
\ No newline at end of file
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Config(e) => Some(e),
+            Error::Routing(e) => Some(e),
+            Error::Operation(e) => Some(e),
+            Error::Transport(e) => Some(e),
+            Error::Plugin(e) => Some(e),
+            Error::Codec(e) => Some(e),
+            Error::Service(e) => Some(e),
+            Error::Mailbox(e) => Some(e),
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<TopologyError> for Error {
+    fn from(e: TopologyError) -> Self {
+        Error::Routing(e)
+    }
+}
+
+impl From<OperationError> for Error {
+    fn from(e: OperationError) -> Self {
+        Error::Operation(e)
+    }
+}
+
+impl From<TransportError> for Error {
+    fn from(e: TransportError) -> Self {
+        Error::Transport(e)
+    }
+}
+
+impl From<PluginError> for Error {
+    fn from(e: PluginError) -> Self {
+        Error::Plugin(e)
+    }
+}
+
+impl From<CodecError> for Error {
+    fn from(e: CodecError) -> Self {
+        Error::Codec(e)
+    }
+}
+
+impl From<ServiceError> for Error {
+    fn from(e: ServiceError) -> Self {
+        Error::Service(e)
+    }
+}
+
+impl From<MailboxError> for Error {
+    fn from(e: MailboxError) -> Self {
+        Error::Mailbox(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::config::ConfigError;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_error_chain() {
+        let error: Error = ConfigError::InvalidParameter {
+            service: "telnet".to_string(),
+            parameter: "port".to_string(),
+            value: "50x38".to_string(),
+            reason: "invalid digit found in string".to_string(),
+        }.into();
+
+        assert_eq!(error.to_string(), "config error: parameter port of service telnet has invalid value \"50x38\": invalid digit found in string");
+        assert!(error.source().is_some());
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A service is unhealthy (and out of routing) after missing this many heartbeats in a row
+pub const DEFAULT_MAX_MISSED_HEARTBEATS: u32 = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Readiness {
+    pub ready: bool,
+    pub reason: Option<String>,
+}
+
+impl Readiness {
+    pub fn ready() -> Self {
+        Self { ready: true, reason: None }
+    }
+
+    pub fn not_ready(reason: String) -> Self {
+        Self { ready: false, reason: Some(reason) }
+    }
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self::ready()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceHealth {
+    /// Answers heartbeats, unhealthy services get no parcels
+    pub healthy: bool,
+    pub readiness: Readiness,
+    pub missed_heartbeats: u32,
+    pub since_last_heartbeat: Duration,
+}
+
+/// Answer to `GetHealth`
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    pub services: HashMap<String, ServiceHealth>,
+}
+
+impl HealthReport {
+    /// Every service answers heartbeats and is ready
+    pub fn healthy(&self) -> bool {
+        self.services.values().all(|service| service.healthy && service.readiness.ready)
+    }
+
+    pub fn unhealthy_services(&self) -> Vec<&String> {
+        self.services.iter()
+            .filter(|(_, service)| !service.healthy || !service.readiness.ready)
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health::{HealthReport, Readiness, ServiceHealth};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn test_health_report() {
+        let mut services = HashMap::new();
+        services.insert("telnet".to_string(), ServiceHealth {
+            healthy: true,
+            readiness: Readiness::ready(),
+            missed_heartbeats: 0,
+            since_last_heartbeat: Duration::from_millis(10),
+        });
+        let mut report = HealthReport { services };
+        assert!(report.healthy());
+
+        report.services.insert("ami".to_string(), ServiceHealth {
+            healthy: true,
+            readiness: Readiness::not_ready("login failed".to_string()),
+            missed_heartbeats: 0,
+            since_last_heartbeat: Duration::from_millis(10),
+        });
+        assert!(!report.healthy());
+        assert_eq!(report.unhealthy_services(), vec!["ami"]);
+    }
+}
@@ -0,0 +1,244 @@
+//! Just enough HTTP/1.1 for the built-in endpoints: one request per connection,
+//! bodies with `Content-Length` only.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub enum HttpError {
+    Io(std::io::Error),
+    Malformed(String),
+    HeadTooLarge,
+    BodyTooLarge { limit: usize },
+    LengthRequired,
+    /// The request wasn`t read in time
+    Timeout,
+}
+
+impl Error for HttpError {}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::Io(e) => write!(f, "{}", e),
+            HttpError::Malformed(reason) => write!(f, "malformed request: {}", reason),
+            HttpError::HeadTooLarge => write!(f, "request head is larger than {} bytes", MAX_HEAD_SIZE),
+            HttpError::BodyTooLarge { limit } => write!(f, "request body is larger than {} bytes", limit),
+            HttpError::LengthRequired => write!(f, "request body without Content-Length"),
+            HttpError::Timeout => write!(f, "request wasn`t read in time"),
+        }
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(e: std::io::Error) -> Self {
+        HttpError::Io(e)
+    }
+}
+
+impl HttpError {
+    /// Response the client gets when its request can`t be read
+    pub fn response(&self) -> HttpResponse {
+        let status = match self {
+            HttpError::Io(_) | HttpError::Malformed(_) => 400,
+            HttpError::HeadTooLarge => 431,
+            HttpError::BodyTooLarge { .. } => 413,
+            HttpError::LengthRequired => 411,
+            HttpError::Timeout => 408,
+        };
+
+        HttpResponse::new(status).text(self.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers.get(&name.to_lowercase())
+    }
+
+    /// Path without the query string
+    pub fn route(&self) -> &str {
+        self.path.split('?').next().unwrap_or("")
+    }
+}
+
+pub async fn read_request<S: AsyncRead + Unpin>(stream: &mut S, max_body_size: usize) -> Result<HttpRequest, HttpError> {
+    let mut buffer = Vec::with_capacity(1024);
+    let head_end = loop {
+        if let Some(position) = find(&buffer, b"\r\n\r\n") {
+            break position;
+        }
+        if buffer.len() > MAX_HEAD_SIZE {
+            return Err(HttpError::HeadTooLarge);
+        }
+
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(HttpError::Malformed("connection closed before end of head".to_string()));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = std::str::from_utf8(&buffer[..head_end])
+        .map_err(|_e| HttpError::Malformed("head is not utf-8".to_string()))?;
+    let mut lines = head.split("\r\n");
+
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split(' ');
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_string(), path.to_string())
+        }
+        _ => return Err(HttpError::Malformed(format!("bad request line \"{}\"", request_line))),
+    };
+
+    let mut headers = HashMap::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')
+            .ok_or_else(|| HttpError::Malformed(format!("bad header \"{}\"", line)))?;
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    if headers.contains_key("transfer-encoding") {
+        return Err(HttpError::LengthRequired);
+    }
+
+    let content_length = match headers.get("content-length") {
+        Some(length) => length.parse::<usize>()
+            .map_err(|_e| HttpError::Malformed(format!("bad content length \"{}\"", length)))?,
+        None => 0,
+    };
+    if content_length > max_body_size {
+        return Err(HttpError::BodyTooLarge { limit: max_body_size });
+    }
+
+    let mut body = buffer.split_off(head_end + 4);
+    body.truncate(content_length);
+    while body.len() < content_length {
+        let mut chunk = vec![0u8; (content_length - body.len()).min(64 * 1024)];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(HttpError::Malformed("connection closed before end of body".to_string()));
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    Ok(HttpRequest { method, path, headers, body })
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn text(self, text: String) -> Self {
+        self.header("Content-Type", "text/plain; charset=utf-8").body(text.into_bytes())
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub async fn write<S: AsyncWrite + Unpin>(&self, stream: &mut S) -> Result<(), HttpError> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", self.body.len()));
+
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{read_request, HttpError, HttpResponse};
+
+    #[actix_rt::test]
+    async fn test_request() {
+        let mut stream: &[u8] = b"POST /hooks/github?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+        let request = read_request(&mut stream, 1024).await.unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.route(), "/hooks/github");
+        assert_eq!(request.header("Content-Type").unwrap(), "application/json");
+        assert_eq!(request.body, b"{\"a\":1}");
+
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 2048\r\n\r\n";
+        assert!(matches!(read_request(&mut stream, 1024).await, Err(HttpError::BodyTooLarge { .. })));
+    }
+
+    #[actix_rt::test]
+    async fn test_response() {
+        let mut output = Vec::new();
+        HttpResponse::new(200).text("ok".to_string()).write(&mut output).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("Content-Length: 2\r\nConnection: close\r\n\r\nok"));
+    }
+}
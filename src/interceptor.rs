@@ -0,0 +1,308 @@
+//! Hooks that see every parcel on its way through the node. Interceptors run in the order they
+//! were added; each one can change the parcel, reject it or send it to another target.
+//!
+//! - `Ingress` when the node accepts a parcel
+//! - `BeforeDispatch` when the node has found a transport for the parcel
+//! - `BeforeDelivery` in the service, right before its actor gets the parcel
+//! - `DeliveryFailure` when the parcel couldn`t be handed over, a reroute gives it a second chance
+//!
+//! A rerouted parcel goes back to the node and passes `Ingress` again. A parcel is rerouted
+//! at most `MAX_REROUTES` times, a further reroute rejects it.
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Arc, RwLock};
+use log::{debug, error};
+use crate::message::Parcel;
+use crate::route::Target;
+
+pub const MAX_REROUTES: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterceptPoint {
+    Ingress,
+    BeforeDispatch,
+    BeforeDelivery,
+    DeliveryFailure,
+}
+
+pub const ALL_POINTS: [InterceptPoint; 4] = [
+    InterceptPoint::Ingress,
+    InterceptPoint::BeforeDispatch,
+    InterceptPoint::BeforeDelivery,
+    InterceptPoint::DeliveryFailure,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterceptAction {
+    Continue,
+    Reject(String),
+    Reroute(Target),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InterceptContext<'a> {
+    pub point: InterceptPoint,
+    /// Service the parcel is delivered to, only known from `BeforeDelivery` on
+    pub service: Option<&'a str>,
+    /// Why the delivery failed, for `DeliveryFailure`
+    pub error: Option<&'a str>,
+}
+
+impl<'a> InterceptContext<'a> {
+    pub fn new(point: InterceptPoint) -> Self {
+        Self { point, service: None, error: None }
+    }
+
+    pub fn service(mut self, service: &'a str) -> Self {
+        self.service = Some(service);
+        self
+    }
+
+    pub fn error(mut self, error: &'a str) -> Self {
+        self.error = Some(error);
+        self
+    }
+}
+
+pub trait Interceptor: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn points(&self) -> &[InterceptPoint] {
+        &ALL_POINTS
+    }
+
+    fn intercept(&self, context: &InterceptContext, parcel: &mut Parcel) -> InterceptAction;
+}
+
+/// What the chain decided about a parcel
+#[derive(Debug)]
+pub enum Intercepted {
+    Continue(Parcel),
+    Rejected { interceptor: String, reason: String },
+    /// The target of the parcel is already changed
+    Rerouted(Parcel),
+}
+
+#[derive(Debug)]
+pub enum InterceptorError {
+    DuplicateName(String),
+}
+
+impl Error for InterceptorError {}
+
+impl Display for InterceptorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterceptorError::DuplicateName(name) => write!(f, "interceptor {} is already added", name),
+        }
+    }
+}
+
+struct RegisteredInterceptor {
+    name: String,
+    /// Plugin that added the interceptor, its code lives in the plugin library
+    owner: Option<String>,
+    interceptor: Box<dyn Interceptor>,
+}
+
+/// Interceptors shared by the node and every service core. Interceptors are only called
+/// under the read lock, so one is not running anymore once `remove` returns.
+#[derive(Clone, Default)]
+pub struct InterceptorChain {
+    interceptors: Arc<RwLock<Vec<RegisteredInterceptor>>>,
+}
+
+impl Debug for InterceptorChain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InterceptorChain").field("interceptors", &self.names()).finish()
+    }
+}
+
+impl InterceptorChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, owner: Option<String>, interceptor: Box<dyn Interceptor>) -> Result<(), InterceptorError> {
+        let name = interceptor.name().to_string();
+        let mut interceptors = self.interceptors.write().unwrap_or_else(|e| e.into_inner());
+        if interceptors.iter().any(|registered| registered.name == name) {
+            return Err(InterceptorError::DuplicateName(name));
+        }
+
+        debug!("Adding interceptor {} at {:?}", name, interceptor.points());
+        interceptors.push(RegisteredInterceptor { name, owner, interceptor });
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        let mut interceptors = self.interceptors.write().unwrap_or_else(|e| e.into_inner());
+        let count = interceptors.len();
+        interceptors.retain(|registered| registered.name != name);
+        interceptors.len() != count
+    }
+
+    /// Removes the interceptors added by the plugin, returns their names
+    pub fn remove_owned_by(&self, plugin: &str) -> Vec<String> {
+        let mut interceptors = self.interceptors.write().unwrap_or_else(|e| e.into_inner());
+        let (removed, kept): (Vec<RegisteredInterceptor>, Vec<RegisteredInterceptor>) = interceptors.drain(..)
+            .partition(|registered| registered.owner.as_deref() == Some(plugin));
+        *interceptors = kept;
+
+        removed.into_iter().map(|registered| registered.name).collect()
+    }
+
+    /// Removes the interceptors of all plugins, before their libraries are dropped
+    pub fn remove_plugin_interceptors(&self) {
+        let mut interceptors = self.interceptors.write().unwrap_or_else(|e| e.into_inner());
+        interceptors.retain(|registered| registered.owner.is_none());
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        match self.interceptors.read() {
+            Ok(interceptors) => interceptors.iter().map(|registered| registered.name.clone()).collect(),
+            Err(e) => {
+                error!("Error to access interceptors {:?}", e);
+                vec![]
+            }
+        }
+    }
+
+    /// Some interceptor runs at `point`, e.g. to avoid cloning parcels for `DeliveryFailure`
+    pub fn intercepts(&self, point: InterceptPoint) -> bool {
+        match self.interceptors.read() {
+            Ok(interceptors) => interceptors.iter().any(|registered| registered.interceptor.points().contains(&point)),
+            Err(_e) => false,
+        }
+    }
+
+    pub fn run(&self, context: &InterceptContext, mut parcel: Parcel) -> Intercepted {
+        let interceptors = match self.interceptors.read() {
+            Ok(interceptors) => interceptors,
+            Err(e) => {
+                error!("Error to access interceptors {:?}", e);
+                return Intercepted::Continue(parcel);
+            }
+        };
+
+        for registered in interceptors.iter() {
+            if !registered.interceptor.points().contains(&context.point) {
+                continue;
+            }
+
+            match registered.interceptor.intercept(context, &mut parcel) {
+                InterceptAction::Continue => {}
+                InterceptAction::Reject(reason) => {
+                    return Intercepted::Rejected { interceptor: registered.name.clone(), reason };
+                }
+                InterceptAction::Reroute(target) => {
+                    if parcel.reroutes() >= MAX_REROUTES {
+                        let reason = format!("parcel to {} was already rerouted {} times", target.as_string(), MAX_REROUTES);
+                        return Intercepted::Rejected { interceptor: registered.name.clone(), reason };
+                    }
+
+                    debug!("Interceptor {} reroutes parcel from {} to {}", registered.name, parcel.target().as_string(), target.as_string());
+                    parcel.reroute(target);
+                    return Intercepted::Rerouted(parcel);
+                }
+            }
+        }
+
+        Intercepted::Continue(parcel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interceptor::{InterceptAction, InterceptContext, Intercepted, InterceptPoint, Interceptor, InterceptorChain, InterceptorError, MAX_REROUTES};
+    use crate::message::{BaseMessage, Parcel};
+    use crate::route::{Route, RouteSheet, Target};
+    use crate::core::Core;
+    use crate::signal::GetStatistics;
+
+    struct Policy;
+
+    impl Interceptor for Policy {
+        fn name(&self) -> &str {
+            "policy"
+        }
+
+        fn points(&self) -> &[InterceptPoint] {
+            &[InterceptPoint::Ingress]
+        }
+
+        fn intercept(&self, _context: &InterceptContext, parcel: &mut Parcel) -> InterceptAction {
+            match parcel.unpack().first().map(|message| message.data().as_slice()) {
+                Some(b"secret") => InterceptAction::Reject("secret data".to_string()),
+                Some(b"audit") if parcel.target() != &Target::Consumer("Audit".to_string()) => {
+                    InterceptAction::Reroute(Target::Consumer("Audit".to_string()))
+                }
+                Some(b"bounce") => {
+                    let other = if parcel.target() == &Target::Consumer("Ping".to_string()) { "Pong" } else { "Ping" };
+                    InterceptAction::Reroute(Target::Consumer(other.to_string()))
+                }
+                _ => {
+                    parcel.messages_mut().push(BaseMessage::new(b"enriched".to_vec(), None));
+                    InterceptAction::Continue
+                }
+            }
+        }
+    }
+
+    fn parcel(data: &[u8]) -> Parcel {
+        Parcel::new(vec![BaseMessage::new(data.to_vec(), None)], RouteSheet::new(Target::Consumer("Ping".to_string()), Route::new()))
+    }
+
+    #[test]
+    fn test_interceptor_chain() {
+        let chain = InterceptorChain::new();
+        chain.add(Some("audit_plugin".to_string()), Box::new(Policy)).unwrap();
+        assert!(matches!(chain.add(None, Box::new(Policy)), Err(InterceptorError::DuplicateName(_))));
+        assert!(chain.intercepts(InterceptPoint::Ingress));
+        assert!(!chain.intercepts(InterceptPoint::DeliveryFailure));
+
+        let ingress = InterceptContext::new(InterceptPoint::Ingress);
+        assert!(matches!(chain.run(&ingress, parcel(b"ping")), Intercepted::Continue(parcel) if parcel.unpack().len() == 2));
+        assert!(matches!(chain.run(&ingress, parcel(b"secret")), Intercepted::Rejected { interceptor, .. } if interceptor == "policy"));
+        assert!(matches!(chain.run(&ingress, parcel(b"audit")),
+            Intercepted::Rerouted(parcel) if parcel.target() == &Target::Consumer("Audit".to_string())));
+
+        let mut bounce = parcel(b"bounce");
+        for _ in 0..MAX_REROUTES {
+            bounce = match chain.run(&ingress, bounce) {
+                Intercepted::Rerouted(parcel) => parcel,
+                other => panic!("Parcel is not rerouted {:?}", other),
+            };
+        }
+        assert!(matches!(chain.run(&ingress, bounce), Intercepted::Rejected { .. }));
+
+        // Other points are not intercepted
+        let delivery = InterceptContext::new(InterceptPoint::BeforeDelivery).service("echo");
+        assert!(matches!(chain.run(&delivery, parcel(b"secret")), Intercepted::Continue(_)));
+
+        assert_eq!(chain.remove_owned_by("audit_plugin"), vec!["policy".to_string()]);
+        assert!(chain.names().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_node_ingress() {
+        let mut core = Core::new("Node01".to_string());
+        core.add_interceptor(Box::new(Policy)).unwrap();
+        assert_eq!(core.interceptors(), vec!["policy".to_string()]);
+
+        core.node().do_send(parcel(b"audit"));
+        core.node().do_send(parcel(b"secret"));
+        // Passes Ingress again after every reroute until the limit rejects it
+        core.node().do_send(parcel(b"bounce"));
+
+        let snapshot = core.node().send(GetStatistics { service: None }).await.unwrap().unwrap();
+        let queued = |target: &str| snapshot.node.targets.get(&Target::Consumer(target.to_string()).as_string()).map_or(0, |target| target.queued);
+        assert_eq!(queued("Audit"), 1);
+        assert_eq!(queued("Ping"), 0);
+        assert_eq!(queued("Pong"), 0);
+        assert_eq!(snapshot.node.parcels_dropped, 2);
+
+        assert!(core.remove_interceptor("policy"));
+    }
+}
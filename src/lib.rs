@@ -11,9 +11,14 @@ pub mod operation;
 pub mod core;
 mod tests;
 pub mod topology;
+pub mod statistics;
+pub mod health;
+pub mod interceptor;
+pub mod backoff;
+pub mod http;
+pub mod metrics;
 pub mod config;
 pub mod services;
 pub mod plugin;
-pub mod build;
 pub mod any_message_telnet;
 
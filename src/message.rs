@@ -1,19 +1,60 @@
 use actix::Message;
 use crate::route::{RouteSheet, Target};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use crate::operation::Operation;
 use log::trace;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::Utf8Error;
+use std::collections::HashMap;
+
+/// Header of replies: "ok", or "error" with the reason as data
+pub const REPLY_STATUS_HEADER: &str = "status";
+
+#[derive(Debug)]
+pub enum CodecError {
+    InvalidUtf8(Utf8Error),
+}
+
+impl Error for CodecError {}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::InvalidUtf8(e) => write!(f, "message is not valid utf-8: {}", e),
+        }
+    }
+}
 
 
 #[derive(Debug, Clone)]
 pub struct BaseMessage {
     data: Vec<u8>,
     operation: Option<Operation>,
+    /// Transport details, e.g. the connection a message came from
+    headers: HashMap<String, String>,
 }
 
 impl BaseMessage {
     pub fn new(data: Vec<u8>, operation: Option<Operation>) -> Self {
-        Self { data, operation }
+        Self { data, operation, headers: HashMap::new() }
+    }
+
+    pub fn with_header(mut self, name: &str, value: String) -> Self {
+        self.headers.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers.get(name)
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HashMap<String, String> {
+        &mut self.headers
     }
 
     pub fn operation(&self) -> Option<Operation> {
@@ -23,6 +64,10 @@ impl BaseMessage {
     pub fn data(&self) -> &Vec<u8> {
         &self.data
     }
+
+    pub fn text(&self) -> Result<&str, CodecError> {
+        std::str::from_utf8(&self.data).map_err(CodecError::InvalidUtf8)
+    }
 }
 
 // pub struct Envelope {
@@ -35,6 +80,9 @@ pub struct Parcel {
     route_sheet: RouteSheet,
     messages: Vec<BaseMessage>,
     ttl: Option<Duration>,
+    created_at: Instant,
+    /// Times an interceptor sent the parcel to another target
+    reroutes: u32,
 }
 
 impl Clone for Parcel {
@@ -43,22 +91,26 @@ impl Clone for Parcel {
         Self {
             route_sheet: self.route_sheet.clone(),
             messages: self.messages.clone(),
-            ttl: self.ttl.clone()
+            ttl: self.ttl,
+            created_at: self.created_at,
+            reroutes: self.reroutes,
         }
     }
 
     fn clone_from(&mut self, source: &Self) {
         trace!("Cloning parcel");
 
-        self.ttl = source.ttl.clone();
+        self.ttl = source.ttl;
         self.route_sheet = source.route_sheet.clone();
         self.messages = source.messages.clone();
+        self.created_at = source.created_at;
+        self.reroutes = source.reroutes;
     }
 }
 
 impl Parcel {
     pub fn new(messages: Vec<BaseMessage>, route_sheet: RouteSheet) -> Self {
-        Self { route_sheet, messages, ttl: None }
+        Self { route_sheet, messages, ttl: None, created_at: Instant::now(), reroutes: 0 }
     }
 
     pub fn target(&self) -> &Target {
@@ -72,6 +124,29 @@ impl Parcel {
     pub fn unpack(&self) -> &Vec<BaseMessage> {
         &self.messages
     }
+
+    pub fn messages_mut(&mut self) -> &mut Vec<BaseMessage> {
+        &mut self.messages
+    }
+
+    pub fn set_target(&mut self, target: Target) {
+        self.route_sheet.set_target(target);
+    }
+
+    pub fn reroutes(&self) -> u32 {
+        self.reroutes
+    }
+
+    /// Sets the target chosen by an interceptor
+    pub(crate) fn reroute(&mut self, target: Target) {
+        self.set_target(target);
+        self.reroutes += 1;
+    }
+
+    /// Time since the parcel was created
+    pub fn age(&self) -> Duration {
+        self.created_at.elapsed()
+    }
 }
 
 impl Drop for Parcel {
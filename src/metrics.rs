@@ -0,0 +1,216 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use actix::Addr;
+use actix_rt::net::{TcpListener, TcpStream};
+use log::{debug, error, info, trace};
+use crate::http::{read_request, HttpResponse};
+use crate::node::Node;
+use crate::plugin::PluginStatus;
+use crate::signal::GetStatistics;
+use crate::statistics::{Histogram, StatisticsSnapshot};
+
+/// Serves the node statistics in Prometheus text format on `GET /metrics`.
+pub struct MetricsServer {
+    node: Addr<Node>,
+    plugins: Arc<Mutex<Vec<PluginStatus>>>,
+}
+
+impl MetricsServer {
+    pub fn new(node: Addr<Node>, plugins: Arc<Mutex<Vec<PluginStatus>>>) -> Self {
+        Self { node, plugins }
+    }
+
+    pub async fn serve(self, listener: TcpListener) {
+        info!("Serving metrics on {:?}", listener.local_addr());
+        let server = Arc::new(self);
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    trace!("Metrics request from {}", peer);
+                    let server = server.clone();
+                    actix_rt::spawn(async move {
+                        server.handle_connection(stream).await;
+                    });
+                }
+                Err(e) => error!("Can`t accept metrics connection {:?}", e),
+            }
+        }
+    }
+
+    async fn handle_connection(&self, mut stream: TcpStream) {
+        let response = match read_request(&mut stream, 0).await {
+            Ok(request) if request.route() != "/metrics" => HttpResponse::new(404),
+            Ok(request) if request.method != "GET" => HttpResponse::new(405),
+            Ok(_request) => match self.node.send(GetStatistics { service: None }).await {
+                Ok(Ok(snapshot)) => {
+                    let plugins = match self.plugins.lock() {
+                        Ok(plugins) => plugins.clone(),
+                        Err(_e) => vec![],
+                    };
+                    HttpResponse::new(200)
+                        .header("Content-Type", "text/plain; version=0.0.4")
+                        .body(render(&snapshot, &plugins).into_bytes())
+                }
+                Ok(Err(e)) => HttpResponse::new(500).text(e.to_string()),
+                Err(e) => HttpResponse::new(503).text(e.to_string()),
+            },
+            Err(e) => e.response(),
+        };
+
+        if let Err(e) = response.write(&mut stream).await {
+            debug!("Can`t write metrics response {}", e);
+        }
+    }
+}
+
+pub fn render(snapshot: &StatisticsSnapshot, plugins: &[PluginStatus]) -> String {
+    let mut output = String::new();
+    let node = &snapshot.node;
+
+    header(&mut output, "any_message_uptime_seconds", "gauge", "Time since the node started");
+    sample(&mut output, "any_message_uptime_seconds", &[], node.uptime().as_secs_f64());
+
+    header(&mut output, "any_message_parcels_in_total", "counter", "Parcels accepted by the node");
+    sample(&mut output, "any_message_parcels_in_total", &[], node.parcels_in as f64);
+    header(&mut output, "any_message_parcels_out_total", "counter", "Parcels dispatched by the node");
+    sample(&mut output, "any_message_parcels_out_total", &[], node.parcels_out as f64);
+    header(&mut output, "any_message_parcels_dropped_total", "counter", "Parcels the node failed to dispatch");
+    sample(&mut output, "any_message_parcels_dropped_total", &[], node.parcels_dropped as f64);
+
+    let mut targets: Vec<_> = node.targets.iter().collect();
+    targets.sort_by(|a, b| a.0.cmp(b.0));
+
+    header(&mut output, "any_message_queue_depth", "gauge", "Parcels waiting in the node per target");
+    for (target, statistics) in &targets {
+        sample(&mut output, "any_message_queue_depth", &[("target", target)], statistics.queued as f64);
+    }
+    header(&mut output, "any_message_target_parcels_in_total", "counter", "Parcels accepted per target");
+    for (target, statistics) in &targets {
+        sample(&mut output, "any_message_target_parcels_in_total", &[("target", target)], statistics.parcels_in as f64);
+    }
+    header(&mut output, "any_message_target_parcels_out_total", "counter", "Parcels dispatched per target");
+    for (target, statistics) in &targets {
+        sample(&mut output, "any_message_target_parcels_out_total", &[("target", target)], statistics.parcels_out as f64);
+    }
+    header(&mut output, "any_message_target_parcels_dropped_total", "counter", "Parcels dropped per target");
+    for (target, statistics) in &targets {
+        sample(&mut output, "any_message_target_parcels_dropped_total", &[("target", target)], statistics.parcels_dropped as f64);
+    }
+    header(&mut output, "any_message_queue_latency_seconds", "histogram", "Time parcels wait in the node per target");
+    for (target, statistics) in &targets {
+        histogram(&mut output, "any_message_queue_latency_seconds", &[("target", target)], &statistics.queue_latency);
+    }
+
+    let mut services: Vec<_> = snapshot.services.iter().collect();
+    services.sort_by(|a, b| a.0.cmp(b.0));
+
+    header(&mut output, "any_message_service_messages_handled_total", "counter", "Messages delivered to the service");
+    for (service, statistics) in &services {
+        sample(&mut output, "any_message_service_messages_handled_total", &[("service", service)], statistics.messages_handled() as f64);
+    }
+    header(&mut output, "any_message_service_requests_handled_total", "counter", "Operation calls delivered to the service");
+    for (service, statistics) in &services {
+        sample(&mut output, "any_message_service_requests_handled_total", &[("service", service)], statistics.requests_handled() as f64);
+    }
+    header(&mut output, "any_message_service_bytes_handled_total", "counter", "Message bytes delivered to the service");
+    for (service, statistics) in &services {
+        sample(&mut output, "any_message_service_bytes_handled_total", &[("service", service)], statistics.bytes_handled() as f64);
+    }
+    header(&mut output, "any_message_service_errors_total", "counter", "Failed deliveries to the service");
+    for (service, statistics) in &services {
+        sample(&mut output, "any_message_service_errors_total", &[("service", service)], statistics.errors() as f64);
+    }
+    header(&mut output, "any_message_service_latency_seconds", "histogram", "Time the service takes to handle a parcel");
+    for (service, statistics) in &services {
+        histogram(&mut output, "any_message_service_latency_seconds", &[("service", service)], statistics.latency());
+    }
+    header(&mut output, "any_message_service_operation_handled_total", "counter", "Calls of the service operation");
+    for (service, statistics) in &services {
+        let mut operations: Vec<_> = statistics.operations().iter().collect();
+        operations.sort_by(|a, b| a.0.cmp(b.0));
+        for (operation, counters) in operations {
+            sample(&mut output, "any_message_service_operation_handled_total", &[("service", service), ("operation", operation)], counters.count as f64);
+        }
+    }
+
+    header(&mut output, "any_message_plugin_loaded", "gauge", "Whether the plugin library is loaded");
+    for plugin in plugins {
+        let name = plugin.name.clone().unwrap_or_default();
+        sample(&mut output, "any_message_plugin_loaded", &[("path", &plugin.path), ("plugin", &name)], if plugin.loaded { 1.0 } else { 0.0 });
+    }
+
+    output
+}
+
+fn header(output: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+}
+
+fn sample(output: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    output.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels.iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+            .collect();
+        let _ = write!(output, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(output, " {}", value);
+}
+
+fn histogram(output: &mut String, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+    let bucket_name = format!("{}_bucket", name);
+    for (bound, count) in histogram.cumulative_buckets() {
+        let bound = bound.to_string();
+        let mut bucket_labels = labels.to_vec();
+        bucket_labels.push(("le", &bound));
+        sample(output, &bucket_name, &bucket_labels, count as f64);
+    }
+
+    let mut bucket_labels = labels.to_vec();
+    bucket_labels.push(("le", "+Inf"));
+    sample(output, &bucket_name, &bucket_labels, histogram.count() as f64);
+    sample(output, &format!("{}_sum", name), labels, histogram.sum().as_secs_f64());
+    sample(output, &format!("{}_count", name), labels, histogram.count() as f64);
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::render;
+    use crate::statistics::{NodeStatistics, StatisticsSnapshot};
+    use crate::service::ServiceStatistics;
+    use crate::plugin::PluginStatus;
+    use crate::route::{Route, Target};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let mut node = NodeStatistics::new();
+        node.record_in("Consumer(Hangup)".to_string());
+        node.record_out("Consumer(Hangup)".to_string(), Duration::from_millis(3));
+
+        let mut service = ServiceStatistics::new();
+        let target = Target::Route(Route::new().set_operation_name("Originate".to_string()).clone());
+        service.record_delivery(&target, 1, 10, Duration::from_millis(2), true);
+
+        let mut queued = HashMap::new();
+        queued.insert("Consumer(\"quoted\")".to_string(), 4);
+        let mut services = HashMap::new();
+        services.insert("telnet".to_string(), service);
+
+        let plugins = vec![PluginStatus { path: "libtelnet.so".to_string(), name: None, loaded: false, error: Some("not found".to_string()), manifest: None }];
+        let output = render(&StatisticsSnapshot { node: node.snapshot(queued), services }, &plugins);
+
+        assert!(output.contains("any_message_parcels_in_total 1\n"));
+        assert!(output.contains("any_message_queue_depth{target=\"Consumer(\\\"quoted\\\")\"} 4\n"));
+        assert!(output.contains("any_message_service_latency_seconds_bucket{service=\"telnet\",le=\"0.005\"} 1\n"));
+        assert!(output.contains("any_message_service_operation_handled_total{service=\"telnet\",operation=\"Originate\"} 1\n"));
+        assert!(output.contains("any_message_plugin_loaded{path=\"libtelnet.so\",plugin=\"\"} 0\n"));
+    }
+}
@@ -1,23 +1,67 @@
 use crate::route::{Route, Target};
 use std::collections::HashMap;
 use crate::transport::Transport;
-use actix::{Actor, Context, Handler, AsyncContext};
+use actix::{Actor, Context, Handler, AsyncContext, Recipient, ActorFutureExt};
+use actix::fut::wrap_future;
 use crate::message::{Parcel, Request};
-use crate::signal::{RegisterServiceInNodeSignal, Heartbeat, Tick};
-use log::{trace, error};
-use std::time::{Duration};
+use crate::signal::{RegisterServiceInNodeSignal, Heartbeat, Tick, UnregisterServiceInNodeSignal, UpdateServiceRoutesSignal, GetStatistics, ReportReadiness, GetHealth, SubscribeTransientSignal, UnsubscribeTransientSignal};
+use log::{trace, debug, error, warn, info};
+use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
 use crate::topology::Topology;
+use crate::operation::Operation;
+use crate::service::ServiceStatistics;
+use crate::statistics::{NodeStatistics, StatisticsSnapshot};
+use crate::error::Error;
+use crate::topology::TopologyError;
+use crate::health::{HealthReport, Readiness, ServiceHealth, DEFAULT_MAX_MISSED_HEARTBEATS};
+use crate::interceptor::{InterceptContext, Intercepted, InterceptPoint, InterceptorChain};
+
+#[derive(Debug)]
+struct ServiceRegistration {
+    transport: Transport,
+    heartbeat: Recipient<Heartbeat>,
+    operations: Vec<Operation>,
+    consume_messages: Vec<String>,
+    statistics: Arc<Mutex<ServiceStatistics>>,
+    healthy: bool,
+    awaiting_heartbeat: bool,
+    missed_heartbeats: u32,
+    last_heartbeat: Instant,
+}
+
+impl ServiceRegistration {
+    /// Besides its operations and message types the service is reachable by "::name", so
+    /// parcels can be answered with the route they came from
+    fn add_routes(&self, name: &str, topology: &mut Topology) {
+        let service_route = Route::new().set_service_name(name.to_string()).clone();
+        topology.add_target_transport(Target::Route(service_route), self.transport.clone());
+
+        for message_type in &self.consume_messages {
+            let target = Target::Consumer(message_type.clone());
+            topology.add_subscriber(target.as_string(), self.transport.clone());
+        }
+
+        for operation in &self.operations {
+            let route = Route::new().set_operation_name(operation.name().clone()).clone();
+            topology.add_target_transport(Target::Route(route.clone()), self.transport.clone());
+        }
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Node {
     route: Route,
     topology: Topology,
-    services: HashMap<String, Transport>,
+    services: HashMap<String, ServiceRegistration>,
     operations: HashMap<String, Transport>,
     messages: Arc<Mutex<HashMap<Target, Vec<Parcel>>>>,
     requests: HashMap<String, Request>,
+    statistics: NodeStatistics,
+    readiness: HashMap<String, Readiness>,
+    max_missed_heartbeats: u32,
+    interceptors: InterceptorChain,
 }
 
 impl Node {
@@ -31,14 +75,63 @@ impl Node {
             operations: Default::default(),
             messages: Default::default(),
             requests: Default::default(),
+            statistics: NodeStatistics::new(),
+            readiness: Default::default(),
+            max_missed_heartbeats: DEFAULT_MAX_MISSED_HEARTBEATS,
+            interceptors: InterceptorChain::new(),
         }
     }
 
+    pub fn set_max_missed_heartbeats(&mut self, max_missed_heartbeats: u32) -> &mut Self {
+        self.max_missed_heartbeats = max_missed_heartbeats;
+        self
+    }
+
+
+    /// The core shares its chain with the node before starting it
+    pub fn set_interceptors(&mut self, interceptors: InterceptorChain) -> &mut Self {
+        self.interceptors = interceptors;
+        self
+    }
 
     pub fn route(&self) -> &Route {
         &self.route
     }
 
+    fn heartbeat_answered(&mut self, name: &str, answered: bool) {
+        let registration = match self.services.get_mut(name) {
+            Some(registration) => registration,
+            None => return,
+        };
+        registration.awaiting_heartbeat = false;
+
+        if !answered {
+            self.heartbeat_missed(name);
+            return;
+        }
+
+        registration.missed_heartbeats = 0;
+        registration.last_heartbeat = Instant::now();
+        if !registration.healthy {
+            info!("Service {} is healthy again", name);
+            registration.healthy = true;
+            registration.add_routes(name, &mut self.topology);
+        }
+    }
+
+    fn heartbeat_missed(&mut self, name: &str) {
+        let registration = match self.services.get_mut(name) {
+            Some(registration) => registration,
+            None => return,
+        };
+
+        registration.missed_heartbeats += 1;
+        if registration.healthy && registration.missed_heartbeats >= self.max_missed_heartbeats {
+            warn!("Service {} missed {} heartbeats, removing it from routing", name, registration.missed_heartbeats);
+            registration.healthy = false;
+            self.topology.remove_transport(&registration.transport);
+        }
+    }
 }
 
 impl Actor for Node {
@@ -55,25 +148,119 @@ impl Handler<RegisterServiceInNodeSignal> for Node {
 
     fn handle(&mut self, msg: RegisterServiceInNodeSignal, _ctx: &mut Context<Self>) -> Self::Result {
         trace!("Registering service {} transport {:?}", msg.name, msg.transport);
-        self.services.insert(msg.name, msg.transport.clone());
+        if let Some(previous) = self.services.remove(&msg.name) {
+            self.topology.remove_transport(&previous.transport);
+        }
 
-        for message_type in msg.consume_messages {
-            let target = Target::Consumer(message_type);
-            self.topology.add_subscriber(target.as_string(), msg.transport.clone());
+        let registration = ServiceRegistration {
+            transport: msg.transport,
+            heartbeat: msg.heartbeat,
+            operations: msg.operations,
+            consume_messages: msg.consume_messages,
+            statistics: msg.statistics,
+            healthy: true,
+            awaiting_heartbeat: false,
+            missed_heartbeats: 0,
+            last_heartbeat: Instant::now(),
+        };
+        registration.add_routes(&msg.name, &mut self.topology);
+        self.services.insert(msg.name, registration);
+    }
+}
+
+impl Handler<UnregisterServiceInNodeSignal> for Node {
+    type Result = ();
+
+    fn handle(&mut self, msg: UnregisterServiceInNodeSignal, _ctx: &mut Context<Self>) -> Self::Result {
+        trace!("Unregistering service {}", msg.name);
+        // Parcels for the service's targets stay in `messages` until someone serves them again
+        if let Some(registration) = self.services.remove(&msg.name) {
+            self.topology.remove_transport(&registration.transport);
         }
+        self.readiness.remove(&msg.name);
+    }
+}
 
-        for operation in msg.operations {
-            let route = Route::new().set_operation_name(operation.name().clone()).clone();
-            self.topology.add_target_transport(Target::Route(route.clone()), msg.transport.clone());
+impl Handler<UpdateServiceRoutesSignal> for Node {
+    type Result = ();
+
+    fn handle(&mut self, msg: UpdateServiceRoutesSignal, _ctx: &mut Context<Self>) -> Self::Result {
+        trace!("Updating routes of service {}", msg.name);
+        let mut registration = match self.services.remove(&msg.name) {
+            Some(registration) => registration,
+            None => {
+                error!("Can`t update routes of unknown service {}", msg.name);
+                return;
+            }
+        };
+
+        self.topology.remove_transport(&registration.transport);
+        registration.operations = msg.operations;
+        registration.consume_messages = msg.consume_messages;
+        if registration.healthy {
+            registration.add_routes(&msg.name, &mut self.topology);
+        }
+        self.services.insert(msg.name, registration);
+    }
+}
+
+impl Handler<SubscribeTransientSignal> for Node {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeTransientSignal, _ctx: &mut Context<Self>) -> Self::Result {
+        for message_type in msg.message_types {
+            let target = Target::Consumer(message_type).as_string();
+            if !self.topology.is_subscriber(&target, &msg.transport) {
+                self.topology.add_subscriber(target, msg.transport.clone());
+            }
+        }
+    }
+}
+
+impl Handler<UnsubscribeTransientSignal> for Node {
+    type Result = ();
+
+    fn handle(&mut self, msg: UnsubscribeTransientSignal, _ctx: &mut Context<Self>) -> Self::Result {
+        match msg.message_types {
+            Some(message_types) => {
+                for message_type in message_types {
+                    self.topology.remove_subscriber(&Target::Consumer(message_type).as_string(), &msg.transport);
+                }
+            }
+            None => self.topology.remove_transport(&msg.transport),
         }
     }
 }
 
 impl Handler<Parcel> for Node {
     type Result = ();
-    #[allow(unused_must_use)]
+
     fn handle(&mut self, parcel: Parcel, _ctx: &mut Context<Self>) -> Self::Result {
+        self.accept(parcel);
+    }
+}
+
+impl Node {
+    /// Runs `Ingress` and queues the parcel for its target. A parcel rerouted at
+    /// `Ingress` runs it again for the new target.
+    fn accept(&mut self, parcel: Parcel) {
         trace!("Accepting parcel to {}", parcel.route_sheet().target().as_string());
+        self.statistics.record_in(parcel.target().as_string());
+
+        let mut parcel = parcel;
+        let parcel = loop {
+            let target = parcel.target().as_string();
+            parcel = match self.interceptors.run(&InterceptContext::new(InterceptPoint::Ingress), parcel) {
+                Intercepted::Continue(parcel) => break parcel,
+                Intercepted::Rerouted(parcel) => parcel,
+                Intercepted::Rejected { interceptor, reason } => {
+                    debug!("Interceptor {} rejected parcel: {}", interceptor, reason);
+                    self.statistics.record_dropped(target);
+                    return;
+                }
+            };
+        };
+
         let mut messages = match self.messages.lock() {
             Ok(messages) => messages,
             Err(e) => {
@@ -98,11 +285,100 @@ impl Handler<Parcel> for Node {
     }
 }
 
+impl Handler<GetStatistics> for Node {
+    type Result = Result<StatisticsSnapshot, Error>;
+
+    fn handle(&mut self, msg: GetStatistics, _ctx: &mut Context<Self>) -> Self::Result {
+        let queued = match self.messages.lock() {
+            Ok(messages) => messages.iter()
+                .map(|(target, parcels)| (target.as_string(), parcels.len() as u64))
+                .collect(),
+            Err(e) => {
+                error!("Error to access messages {:?}", e);
+                HashMap::new()
+            }
+        };
+
+        let registrations: Vec<(&String, &ServiceRegistration)> = match &msg.service {
+            Some(name) => {
+                let registration = self.services.get(name)
+                    .ok_or_else(|| TopologyError::ServiceNotFound(name.clone()))?;
+                vec![(name, registration)]
+            }
+            None => self.services.iter().collect(),
+        };
+
+        let mut services = HashMap::with_capacity(registrations.len());
+        for (name, registration) in registrations {
+            match registration.statistics.lock() {
+                Ok(mut statistics) => {
+                    services.insert(name.clone(), statistics.snapshot());
+                }
+                Err(e) => error!("Error to access statistics of {} {:?}", name, e),
+            }
+        }
+
+        Ok(StatisticsSnapshot {
+            node: self.statistics.snapshot(queued),
+            services,
+        })
+    }
+}
+
 impl Handler<Heartbeat> for Node {
-    type Result = ();
+    type Result = Result<(), Error>;
 
-    fn handle(&mut self, _msg: Heartbeat, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, _msg: Heartbeat, ctx: &mut Context<Self>) -> Self::Result {
         trace!("Heartbeat accepted");
+        let mut missed = vec![];
+
+        for (name, registration) in self.services.iter_mut() {
+            if registration.awaiting_heartbeat {
+                missed.push(name.clone());
+                continue;
+            }
+
+            registration.awaiting_heartbeat = true;
+            let name = name.clone();
+            let request = registration.heartbeat.send(Heartbeat {});
+            ctx.spawn(wrap_future(request).map(move |result, node: &mut Node, _ctx| {
+                node.heartbeat_answered(&name, matches!(result, Ok(Ok(()))));
+            }));
+        }
+
+        for name in missed {
+            self.heartbeat_missed(&name);
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<ReportReadiness> for Node {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReportReadiness, _ctx: &mut Context<Self>) -> Self::Result {
+        trace!("Service {} readiness {:?}", msg.service, msg.readiness);
+        self.readiness.insert(msg.service, msg.readiness);
+    }
+}
+
+impl Handler<GetHealth> for Node {
+    type Result = actix::MessageResult<GetHealth>;
+
+    fn handle(&mut self, _msg: GetHealth, _ctx: &mut Context<Self>) -> Self::Result {
+        let services = self.services.iter()
+            .map(|(name, registration)| {
+                (name.clone(), ServiceHealth {
+                    healthy: registration.healthy,
+                    readiness: self.readiness.get(name).cloned().unwrap_or_default(),
+                    missed_heartbeats: registration.missed_heartbeats,
+                    since_last_heartbeat: registration.last_heartbeat.elapsed(),
+                })
+            })
+            .collect();
+
+        actix::MessageResult(HealthReport { services })
     }
 }
 
@@ -122,6 +398,7 @@ impl Handler<Tick> for Node {
             }
         };
 
+        let mut rerouted = vec![];
         for (target, parcels) in messages.iter_mut() {
             if parcels.len() == 0 {
                 continue;
@@ -132,7 +409,18 @@ impl Handler<Tick> for Node {
                     match self.topology.find_transport_for_route(route) {
                         Some(transport) => {
                             for parcel in parcels.drain(..) {
-                                transport.send_parcel(parcel);
+                                let queued_for = parcel.age();
+                                let parcel = match before_dispatch(&self.interceptors, &mut self.statistics, target, parcel, &mut rerouted) {
+                                    Some(parcel) => parcel,
+                                    None => continue,
+                                };
+                                match transport.try_send_parcel(parcel) {
+                                    Ok(()) => self.statistics.record_out(target.as_string(), queued_for),
+                                    Err((e, parcel)) => {
+                                        error!("Can`t send parcel to {}: {}", route.as_string(), e);
+                                        delivery_failed(&self.interceptors, &mut self.statistics, target, *parcel, &e.to_string(), &mut rerouted);
+                                    }
+                                }
                             }
                         }
                         None => {
@@ -145,8 +433,19 @@ impl Handler<Tick> for Node {
                     match self.topology.find_consumers_for_message(&target.as_string()) {
                         Some(transports) => {
                             for parcel in parcels.drain(..) {
+                                let queued_for = parcel.age();
+                                let parcel = match before_dispatch(&self.interceptors, &mut self.statistics, target, parcel, &mut rerouted) {
+                                    Some(parcel) => parcel,
+                                    None => continue,
+                                };
                                 for transport in transports {
-                                    transport.send_parcel(parcel.clone());
+                                    match transport.try_send_parcel(parcel.clone()) {
+                                        Ok(()) => self.statistics.record_out(target.as_string(), queued_for),
+                                        Err((e, parcel)) => {
+                                            error!("Can`t send parcel to {}: {}", message_type, e);
+                                            delivery_failed(&self.interceptors, &mut self.statistics, target, *parcel, &e.to_string(), &mut rerouted);
+                                        }
+                                    }
                                 }
                             }
                         }
@@ -158,5 +457,37 @@ impl Handler<Tick> for Node {
                 }
             }
         }
+
+        messages.retain(|_target, parcels| !parcels.is_empty());
+        drop(messages);
+        for parcel in rerouted {
+            self.accept(parcel);
+        }
+    }
+}
+
+/// Runs `BeforeDispatch`, returns the parcel when it should be sent now
+fn before_dispatch(interceptors: &InterceptorChain, statistics: &mut NodeStatistics, target: &Target, parcel: Parcel,
+                   rerouted: &mut Vec<Parcel>) -> Option<Parcel> {
+    match interceptors.run(&InterceptContext::new(InterceptPoint::BeforeDispatch), parcel) {
+        Intercepted::Continue(parcel) => Some(parcel),
+        Intercepted::Rerouted(parcel) => {
+            rerouted.push(parcel);
+            None
+        }
+        Intercepted::Rejected { interceptor, reason } => {
+            debug!("Interceptor {} rejected parcel to {}: {}", interceptor, target.as_string(), reason);
+            statistics.record_dropped(target.as_string());
+            None
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Runs `DeliveryFailure`, the parcel is dropped unless an interceptor reroutes it
+fn delivery_failed(interceptors: &InterceptorChain, statistics: &mut NodeStatistics, target: &Target, parcel: Parcel,
+                   error: &str, rerouted: &mut Vec<Parcel>) {
+    match interceptors.run(&InterceptContext::new(InterceptPoint::DeliveryFailure).error(error), parcel) {
+        Intercepted::Rerouted(parcel) => rerouted.push(parcel),
+        Intercepted::Continue(_) | Intercepted::Rejected { .. } => statistics.record_dropped(target.as_string()),
+    }
+}
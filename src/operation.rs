@@ -1,4 +1,6 @@
 use semver::Version;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 
 
 #[derive(Clone, Debug, Hash, Eq)]
@@ -22,12 +24,25 @@ impl Operation {
     }
 }
 
+#[derive(Debug)]
 pub enum OperationError {
     OperationNotFound,
     OperationNotFoundInNode,
     OperationNotFoundInService,
 }
 
+impl Error for OperationError {}
+
+impl Display for OperationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OperationError::OperationNotFound => write!(f, "operation not found"),
+            OperationError::OperationNotFoundInNode => write!(f, "operation not found in node"),
+            OperationError::OperationNotFoundInService => write!(f, "operation not found in service"),
+        }
+    }
+}
+
 impl PartialEq for Operation {
     fn eq(&self, other: &Self) -> bool {
         other.name == self.name && other.version == self.version
@@ -1,93 +0,0 @@
-use std::any::Any;
-use libloading::{Library, Symbol};
-use std::ffi::OsStr;
-use log::{info, debug, trace};
-use std::error::Error;
-use crate::core::Core;
-
-pub static CORE_VERSION: &str = "1";
-pub static RUSTC_VERSION: &str = "1";
-
-pub trait Plugin: Any + Send + Sync {
-    fn name(&self) -> &'static str;
-    fn on_load(&self, core: &mut Core) {}
-    fn on_unload(&self) {}
-}
-
-#[macro_export]
-macro_rules! declare_plugin {
-   ($plugin_type:ty, $constructor:path) => {
-        #[no_mangle]
-        pub extern "C" fn _plugin_create() -> *mut $crate::plugin::Plugin {
-            // make sure the constructor is the correct type.
-            let constructor: fn() -> $plugin_type = $constructor;
-
-            let object = constructor();
-            let boxed: Box<$crate::plugin::Plugin> = Box::new(object);
-            Box::into_raw(boxed)
-        }
-    };
-}
-
-pub struct PluginManager {
-    plugins: Vec<Box<dyn Plugin>>,
-    loaded_libraries: Vec<Library>,
-}
-
-impl PluginManager {
-    pub fn new() -> Self {
-        Self {
-            plugins: vec![],
-            loaded_libraries: vec![]
-        }
-    }
-
-    pub unsafe fn load_plugin<P: AsRef<OsStr>>(&mut self, filename: P, core: &mut Core) -> Result<(), Box<dyn Error>> {
-        info!("Loading plugin");
-        type PluginCreate = unsafe fn() -> *mut dyn Plugin;
-
-        let lib = Library::new(filename.as_ref()).map_err(|e| "Unable to load the plugin")?;
-
-        // We need to keep the library around otherwise our plugin's vtable will
-        // point to garbage. We do this little dance to make sure the library
-        // doesn't end up getting moved.
-        self.loaded_libraries.push(lib);
-
-        let lib = self.loaded_libraries.last().unwrap();
-
-        let constructor: Symbol<PluginCreate> = lib.get(b"_plugin_create")
-            .map_err(|e| "The `_plugin_create` symbol wasn't found.")?;
-        let boxed_raw = constructor();
-
-        let plugin = Box::from_raw(boxed_raw);
-        debug!("Loaded plugin: {}", plugin.name());
-        plugin.on_load(core);
-        self.plugins.push(plugin);
-
-
-        Ok(())
-    }
-
-    /// Unload all plugins and loaded plugin libraries, making sure to fire
-    /// their `on_plugin_unload()` methods so they can do any necessary cleanup.
-    pub fn unload(&mut self) {
-        debug!("Unloading plugins");
-
-        for plugin in self.plugins.drain(..) {
-            trace!("Firing on_plugin_unload for {:?}", plugin.name());
-            plugin.on_unload();
-        }
-
-        for lib in self.loaded_libraries.drain(..) {
-            drop(lib);
-        }
-    }
-}
-
-impl Drop for PluginManager {
-    fn drop(&mut self) {
-        if !self.plugins.is_empty() || !self.loaded_libraries.is_empty() {
-            self.unload();
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,264 @@
+//! Plugins found in directories. Every library `<stem>.<so|dylib|dll>` is described by
+//! a sidecar manifest `<stem>.plugin.yaml`:
+//!
+//! ```yaml
+//! name: "telnet"
+//! version: "0.1.0"
+//! provides: ["TelnetService"]
+//! depends_on: []
+//! min_core_version: "0.1.0"
+//! enabled: true
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use semver::Version;
+use serde::Deserialize;
+use log::{debug, trace};
+use crate::plugin::{PluginError, CORE_VERSION};
+
+pub const MANIFEST_SUFFIX: &str = ".plugin.yaml";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: Version,
+    /// Service types the plugin registers
+    #[serde(default)]
+    pub provides: Vec<String>,
+    /// Names of plugins that have to be loaded first
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    pub min_core_version: Option<Version>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl PluginManifest {
+    pub fn from_file(path: &Path) -> Result<Self, PluginError> {
+        let manifest_error = |reason: String| PluginError::Manifest { path: path.display().to_string(), reason };
+
+        let string = std::fs::read_to_string(path).map_err(|e| manifest_error(e.to_string()))?;
+        serde_yaml::from_str(&string).map_err(|e| manifest_error(e.to_string()))
+    }
+
+    /// The manifest allows the plugin to be loaded into this core
+    pub fn check(&self, path: &str) -> Result<(), PluginError> {
+        if !self.enabled {
+            return Err(PluginError::Disabled { path: path.to_string() });
+        }
+
+        if let Some(min_core_version) = &self.min_core_version {
+            let host = Version::parse(CORE_VERSION)
+                .map_err(|source| PluginError::InvalidVersion { path: path.to_string(), version: CORE_VERSION.to_string(), source })?;
+            if host < *min_core_version {
+                return Err(PluginError::CoreTooOld {
+                    path: path.to_string(),
+                    required: min_core_version.to_string(),
+                    host: CORE_VERSION.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sidecar manifest of the library
+pub fn manifest_path(library: &Path) -> PathBuf {
+    let stem = library.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    library.with_file_name(format!("{}{}", stem, MANIFEST_SUFFIX))
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredPlugin {
+    pub library: PathBuf,
+    pub manifest: PluginManifest,
+}
+
+impl DiscoveredPlugin {
+    pub fn path(&self) -> String {
+        self.library.display().to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct SkippedPlugin {
+    pub path: String,
+    pub manifest: Option<PluginManifest>,
+    pub error: PluginError,
+}
+
+/// Plugins of the scanned directories, `plugins` are in load order.
+#[derive(Debug, Default)]
+pub struct PluginDiscovery {
+    pub plugins: Vec<DiscoveredPlugin>,
+    pub skipped: Vec<SkippedPlugin>,
+}
+
+impl PluginDiscovery {
+    pub fn scan(directories: &[PathBuf]) -> Result<Self, PluginError> {
+        let mut discovery = Self::default();
+
+        for directory in directories {
+            discovery.scan_directory(directory)?;
+        }
+        discovery.sort();
+
+        Ok(discovery)
+    }
+
+    fn scan_directory(&mut self, directory: &Path) -> Result<(), PluginError> {
+        debug!("Scanning plugin directory {}", directory.display());
+        let directory_error = |e: std::io::Error| PluginError::Directory { path: directory.display().to_string(), source: e };
+
+        let mut manifests = vec![];
+        for entry in std::fs::read_dir(directory).map_err(directory_error)? {
+            let path = entry.map_err(directory_error)?.path();
+            match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) if name.ends_with(MANIFEST_SUFFIX) => manifests.push(path.clone()),
+                _ => trace!("Ignoring {} in plugin directory", path.display()),
+            }
+        }
+        manifests.sort();
+
+        for manifest_path in manifests {
+            let file_name = manifest_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            let stem = file_name.trim_end_matches(MANIFEST_SUFFIX);
+            let library = directory.join(format!("{}.{}", stem, std::env::consts::DLL_EXTENSION));
+            let path = library.display().to_string();
+
+            let manifest = match PluginManifest::from_file(&manifest_path) {
+                Ok(manifest) => manifest,
+                Err(error) => {
+                    self.skipped.push(SkippedPlugin { path, manifest: None, error });
+                    continue;
+                }
+            };
+
+            if !library.is_file() {
+                let error = PluginError::Manifest {
+                    path: manifest_path.display().to_string(),
+                    reason: format!("library {} doesn`t exist", path),
+                };
+                self.skipped.push(SkippedPlugin { path, manifest: Some(manifest), error });
+                continue;
+            }
+
+            match manifest.check(&path) {
+                Ok(()) => self.plugins.push(DiscoveredPlugin { library, manifest }),
+                Err(error) => self.skipped.push(SkippedPlugin { path, manifest: Some(manifest), error }),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Orders plugins so that dependencies come first. Plugins with a duplicate name,
+    /// unavailable dependencies or dependency cycles are moved to `skipped`.
+    fn sort(&mut self) {
+        let mut pending: HashMap<String, DiscoveredPlugin> = HashMap::new();
+        for plugin in self.plugins.drain(..) {
+            if pending.contains_key(&plugin.manifest.name) {
+                let error = PluginError::DuplicateName { path: plugin.path(), name: plugin.manifest.name.clone() };
+                self.skipped.push(SkippedPlugin { path: plugin.path(), manifest: Some(plugin.manifest), error });
+                continue;
+            }
+            pending.insert(plugin.manifest.name.clone(), plugin);
+        }
+
+        let mut ordered: HashSet<String> = HashSet::new();
+        loop {
+            let mut names: Vec<String> = pending.keys().cloned().collect();
+            names.sort();
+
+            let mut progress = false;
+            for name in names {
+                let plugin = &pending[&name];
+                let unavailable = plugin.manifest.depends_on.iter()
+                    .find(|dependency| !ordered.contains(*dependency) && !pending.contains_key(*dependency))
+                    .cloned();
+
+                if let Some(dependency) = unavailable {
+                    let plugin = pending.remove(&name).unwrap();
+                    let error = PluginError::DependencyUnavailable { path: plugin.path(), dependency };
+                    self.skipped.push(SkippedPlugin { path: plugin.path(), manifest: Some(plugin.manifest), error });
+                    progress = true;
+                } else if plugin.manifest.depends_on.iter().all(|dependency| ordered.contains(dependency)) {
+                    let plugin = pending.remove(&name).unwrap();
+                    ordered.insert(name);
+                    self.plugins.push(plugin);
+                    progress = true;
+                }
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        let mut names: Vec<String> = pending.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            let plugin = pending.remove(&name).unwrap();
+            let error = PluginError::DependencyCycle { path: plugin.path() };
+            self.skipped.push(SkippedPlugin { path: plugin.path(), manifest: Some(plugin.manifest), error });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plugin::manifest::{DiscoveredPlugin, PluginDiscovery, PluginManifest};
+    use crate::plugin::PluginError;
+    use std::path::PathBuf;
+
+    fn plugin(name: &str, depends_on: &[&str]) -> DiscoveredPlugin {
+        let manifest = format!("name: \"{}\"\nversion: \"1.0.0\"\ndepends_on: [{}]\n", name, depends_on.join(", "));
+        DiscoveredPlugin {
+            library: PathBuf::from(format!("lib{}.so", name)),
+            manifest: serde_yaml::from_str::<PluginManifest>(&manifest).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_load_order() {
+        let mut discovery = PluginDiscovery {
+            plugins: vec![
+                plugin("ami", &["telnet"]),
+                plugin("telnet", &["codec"]),
+                plugin("codec", &[]),
+                plugin("webhook", &["http"]),
+                plugin("left", &["right"]),
+                plugin("right", &["left"]),
+            ],
+            skipped: vec![],
+        };
+        discovery.sort();
+
+        let loaded: Vec<&str> = discovery.plugins.iter().map(|plugin| plugin.manifest.name.as_str()).collect();
+        assert_eq!(loaded, vec!["codec", "telnet", "ami"]);
+
+        assert_eq!(discovery.skipped.len(), 3);
+        assert!(matches!(&discovery.skipped[0].error, PluginError::DependencyUnavailable { dependency, .. } if dependency == "http"));
+        assert!(matches!(discovery.skipped[1].error, PluginError::DependencyCycle { .. }));
+    }
+
+    #[test]
+    fn test_manifest_check() {
+        let manifest: PluginManifest = serde_yaml::from_str("name: \"telnet\"\nversion: \"0.1.0\"\nprovides: [\"TelnetService\"]\n").unwrap();
+        assert!(manifest.enabled);
+        assert!(manifest.check("libtelnet.so").is_ok());
+
+        let disabled = PluginManifest { enabled: false, ..manifest.clone() };
+        assert!(matches!(disabled.check("libtelnet.so"), Err(PluginError::Disabled { .. })));
+
+        let newer_core = PluginManifest { min_core_version: Some(semver::Version::new(99, 0, 0)), ..manifest };
+        assert!(matches!(newer_core.check("libtelnet.so"), Err(PluginError::CoreTooOld { .. })));
+    }
+}
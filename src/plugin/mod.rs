@@ -0,0 +1,567 @@
+use std::any::Any;
+use libloading::{Library, Symbol};
+use std::ffi::{CStr, OsStr};
+use std::os::raw::c_char;
+use log::{info, debug, trace, error};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use crate::core::Core;
+use std::sync::{Arc, Mutex};
+use crate::plugin::manifest::{DiscoveredPlugin, PluginManifest, SkippedPlugin};
+use crate::config::ServiceConfig;
+use crate::service::ServiceFunctions;
+
+pub mod manifest;
+pub mod process;
+pub mod wasm;
+
+pub static CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub static RUSTC_VERSION: &str = env!("RUSTC_VERSION");
+
+/// Layout version of `PluginDeclaration`, raised whenever its fields change
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+// The declaration carries C strings, `&str` has no layout a library of another compiler agrees on
+const CORE_VERSION_NUL: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+const RUSTC_VERSION_NUL: &str = concat!(env!("RUSTC_VERSION"), "\0");
+
+#[derive(Debug)]
+pub enum PluginError {
+    Load { path: String, source: libloading::Error },
+    SymbolNotFound { path: String, symbol: String },
+    AbiMismatch { path: String, host: u32, plugin: u32 },
+    RustcMismatch { path: String, host: String, plugin: String },
+    IncompatibleCore { path: String, host: String, plugin: String },
+    InvalidVersion { path: String, version: String, source: semver::Error },
+    Directory { path: String, source: std::io::Error },
+    Manifest { path: String, reason: String },
+    Disabled { path: String },
+    CoreTooOld { path: String, required: String, host: String },
+    DuplicateName { path: String, name: String },
+    DependencyUnavailable { path: String, dependency: String },
+    DependencyCycle { path: String },
+    NotLoaded { name: String },
+}
+
+impl Error for PluginError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PluginError::Load { source, .. } => Some(source),
+            PluginError::InvalidVersion { source, .. } => Some(source),
+            PluginError::Directory { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PluginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginError::Load { path, source } => write!(f, "unable to load the plugin {}: {}", path, source),
+            PluginError::SymbolNotFound { path, symbol } => write!(f, "the `{}` symbol wasn't found in {}", symbol, path),
+            PluginError::AbiMismatch { path, host, plugin } => {
+                write!(f, "the plugin {} has the declaration ABI {}, but the host has {}", path, plugin, host)
+            }
+            PluginError::RustcMismatch { path, host, plugin } => {
+                write!(f, "the plugin {} was built with rustc {}, but the host was built with rustc {}", path, plugin, host)
+            }
+            PluginError::IncompatibleCore { path, host, plugin } => {
+                write!(f, "the plugin {} was built against any_message {}, which is incompatible with the host version {}", path, plugin, host)
+            }
+            PluginError::InvalidVersion { path, version, source } => {
+                write!(f, "the plugin {} declares an invalid version \"{}\": {}", path, version, source)
+            }
+            PluginError::Directory { path, source } => write!(f, "can`t scan plugin directory {}: {}", path, source),
+            PluginError::Manifest { path, reason } => write!(f, "invalid plugin manifest {}: {}", path, reason),
+            PluginError::Disabled { path } => write!(f, "the plugin {} is disabled in its manifest", path),
+            PluginError::CoreTooOld { path, required, host } => {
+                write!(f, "the plugin {} requires any_message {} or newer, the host version is {}", path, required, host)
+            }
+            PluginError::DuplicateName { path, name } => write!(f, "the plugin {} has the name {} of another plugin", path, name),
+            PluginError::DependencyUnavailable { path, dependency } => {
+                write!(f, "the plugin {} depends on the plugin {}, which is not available", path, dependency)
+            }
+            PluginError::DependencyCycle { path } => write!(f, "the plugin {} has a dependency cycle", path),
+            PluginError::NotLoaded { name } => write!(f, "the plugin {} is not loaded", name),
+        }
+    }
+}
+
+pub trait Plugin: Any + Send + Sync {
+    fn name(&self) -> &'static str;
+    fn on_load(&self, core: &mut Core) {}
+    fn on_unload(&self) {}
+}
+
+/// Versions a plugin was built with, exported by `declare_plugin!` as `_plugin_declaration`.
+/// The host checks it before calling anything else in the library. Only `abi_version` is
+/// read before it matches, the versions are NUL terminated strings.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct PluginDeclaration {
+    pub abi_version: u32,
+    pub core_version: *const c_char,
+    pub rustc_version: *const c_char,
+}
+
+// The strings are static and never written
+unsafe impl Sync for PluginDeclaration {}
+
+impl PluginDeclaration {
+    /// The declaration of a library built against this crate
+    pub const fn current() -> Self {
+        Self {
+            abi_version: PLUGIN_ABI_VERSION,
+            core_version: CORE_VERSION_NUL.as_ptr() as *const c_char,
+            rustc_version: RUSTC_VERSION_NUL.as_ptr() as *const c_char,
+        }
+    }
+
+    /// The compiler must be the same, there is no stable Rust ABI.
+    /// The crate must be semver compatible with the host, so the traits have the same layout.
+    ///
+    /// # Safety
+    /// The versions must be null or point to NUL terminated strings once `abi_version` matches.
+    pub unsafe fn check(&self, path: &str) -> Result<(), PluginError> {
+        if self.abi_version != PLUGIN_ABI_VERSION {
+            return Err(PluginError::AbiMismatch { path: path.to_string(), host: PLUGIN_ABI_VERSION, plugin: self.abi_version });
+        }
+
+        let rustc_version = read_version(self.rustc_version);
+        if rustc_version != RUSTC_VERSION {
+            return Err(PluginError::RustcMismatch {
+                path: path.to_string(),
+                host: RUSTC_VERSION.to_string(),
+                plugin: rustc_version,
+            });
+        }
+
+        let core_version = read_version(self.core_version);
+        let requirement = semver::VersionReq::parse(&format!("^{}", core_version))
+            .map_err(|source| PluginError::InvalidVersion { path: path.to_string(), version: core_version.clone(), source })?;
+        let host = semver::Version::parse(CORE_VERSION)
+            .map_err(|source| PluginError::InvalidVersion { path: path.to_string(), version: CORE_VERSION.to_string(), source })?;
+
+        if !requirement.matches(&host) {
+            return Err(PluginError::IncompatibleCore {
+                path: path.to_string(),
+                host: CORE_VERSION.to_string(),
+                plugin: core_version,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+unsafe fn read_version(version: *const c_char) -> String {
+    match version.is_null() {
+        true => String::new(),
+        false => CStr::from_ptr(version).to_string_lossy().into_owned(),
+    }
+}
+
+/// Exports the plugin from a `cdylib` crate together with the versions it was built with.
+///
+/// ```ignore
+/// declare_plugin!(EchoPlugin, EchoPlugin::default);
+/// ```
+#[macro_export]
+macro_rules! declare_plugin {
+    ($plugin_type:ty, $constructor:path) => {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static _plugin_declaration: $crate::plugin::PluginDeclaration = $crate::plugin::PluginDeclaration::current();
+
+        #[no_mangle]
+        #[allow(improper_ctypes_definitions)]
+        pub extern "C" fn _plugin_create() -> *mut dyn $crate::plugin::Plugin {
+            // make sure the constructor is the correct type.
+            let constructor: fn() -> $plugin_type = $constructor;
+
+            let object = constructor();
+            let boxed: Box<dyn $crate::plugin::Plugin> = Box::new(object);
+            Box::into_raw(boxed)
+        }
+    };
+}
+
+/// A service factory as `declare_service!` exports it under the factory's name. The host
+/// checks `declaration` before `create` is called.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ServiceFactory {
+    pub declaration: PluginDeclaration,
+    pub create: extern "C" fn(&ServiceConfig) -> ServiceFactoryResult,
+}
+
+impl ServiceFactory {
+    pub const fn new(create: extern "C" fn(&ServiceConfig) -> ServiceFactoryResult) -> Self {
+        Self { declaration: PluginDeclaration::current(), create }
+    }
+
+    pub fn create(&self, config: &ServiceConfig) -> Result<ServiceFunctions, Box<dyn Error>> {
+        (self.create)(config).into_result()
+    }
+}
+
+/// The result of a service factory with a C layout, either `functions` or `error` is set
+#[repr(C)]
+pub struct ServiceFactoryResult {
+    pub functions: Option<Box<ServiceFunctions>>,
+    pub error: Option<Box<String>>,
+}
+
+impl ServiceFactoryResult {
+    pub fn into_result(self) -> Result<ServiceFunctions, Box<dyn Error>> {
+        match (self.functions, self.error) {
+            (Some(functions), _) => Ok(*functions),
+            (None, Some(error)) => Err((*error).into()),
+            (None, None) => Err("service factory returned nothing".into()),
+        }
+    }
+}
+
+impl From<Result<ServiceFunctions, Box<dyn Error>>> for ServiceFactoryResult {
+    fn from(result: Result<ServiceFunctions, Box<dyn Error>>) -> Self {
+        match result {
+            Ok(functions) => Self { functions: Some(Box::new(functions)), error: None },
+            Err(e) => Self { functions: None, error: Some(Box::new(e.to_string())) },
+        }
+    }
+}
+
+/// Exports service factories from the services' `on_start` functions, each as a
+/// `ServiceFactory` static named like the factory. The plugin registers them in
+/// `Plugin::on_load`.
+///
+/// ```ignore
+/// declare_service!(echo_service => EchoService::on_start);
+///
+/// fn on_load(&self, core: &mut Core) {
+///     if let Err(e) = core.service_config("EchoService".to_string(), echo_service) {
+///         error!("EchoService is not available: {}", e);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! declare_service {
+    ($($factory:ident => $on_start:path),+ $(,)?) => {
+        $(
+            #[no_mangle]
+            #[allow(non_upper_case_globals)]
+            pub static $factory: $crate::plugin::ServiceFactory = $crate::service_factory!($on_start);
+        )+
+    };
+}
+
+/// The `ServiceFactory` of an `on_start` function, for `declare_service!` and `builtin_service!`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! service_factory {
+    ($on_start:path) => {{
+        extern "C" fn create(_config: &$crate::config::ServiceConfig) -> $crate::plugin::ServiceFactoryResult {
+            let on_start: fn($crate::config::ServiceConfig)
+                -> Result<Box<dyn $crate::service::Service>, Box<dyn std::error::Error>> = $on_start;
+
+            let functions = $crate::service::ServiceFunctions {
+                on_start: Box::new(on_start),
+            };
+            $crate::plugin::ServiceFactoryResult::from(Ok(functions))
+        }
+
+        $crate::plugin::ServiceFactory::new(create)
+    }};
+}
+
+/// `declare_service!` for the services built into this crate. Their factories are plain
+/// statics: unmangled ones would be exported by every binary and plugin linking the crate
+/// and could clash with the symbols of a plugin.
+macro_rules! builtin_service {
+    ($($factory:ident => $on_start:path),+ $(,)?) => {
+        $(
+            #[allow(non_upper_case_globals)]
+            pub static $factory: $crate::plugin::ServiceFactory = $crate::service_factory!($on_start);
+        )+
+    };
+}
+
+pub(crate) use builtin_service;
+
+/// Outcome of the last attempt to load a plugin library
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginStatus {
+    pub path: String,
+    pub name: Option<String>,
+    pub loaded: bool,
+    pub error: Option<String>,
+    /// Set for plugins discovered in a plugin directory
+    pub manifest: Option<PluginManifest>,
+}
+
+/// A plugin together with the library its code lives in
+pub struct LoadedPlugin {
+    name: String,
+    path: String,
+    manifest: Option<PluginManifest>,
+    // Declared before `library`, the plugin has to be dropped while its code is still loaded
+    plugin: Box<dyn Plugin>,
+    library: Library,
+}
+
+impl LoadedPlugin {
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn manifest(&self) -> Option<&PluginManifest> {
+        self.manifest.as_ref()
+    }
+
+    fn unload(self) {
+        trace!("Firing on_plugin_unload for {:?}", self.name);
+        self.plugin.on_unload();
+
+        let LoadedPlugin { plugin, library, .. } = self;
+        drop(plugin);
+        drop(library);
+    }
+}
+
+pub struct PluginManager {
+    plugins: Vec<LoadedPlugin>,
+    statuses: Arc<Mutex<Vec<PluginStatus>>>,
+}
+
+impl PluginManager {
+    pub fn new() -> Self {
+        Self {
+            plugins: vec![],
+            statuses: Default::default(),
+        }
+    }
+
+    /// Shared list of plugin statuses, it stays up to date while plugins are loaded and unloaded.
+    pub fn statuses(&self) -> Arc<Mutex<Vec<PluginStatus>>> {
+        self.statuses.clone()
+    }
+
+    fn set_status(&self, status: PluginStatus) {
+        match self.statuses.lock() {
+            Ok(mut statuses) => {
+                statuses.retain(|known| known.path != status.path);
+                statuses.push(status);
+            }
+            Err(e) => error!("Error to access plugin statuses {:?}", e),
+        }
+    }
+
+    fn set_unloaded(&self, path: &str) {
+        match self.statuses.lock() {
+            Ok(mut statuses) => {
+                for status in statuses.iter_mut().filter(|status| status.path == path) {
+                    status.loaded = false;
+                }
+            }
+            Err(e) => error!("Error to access plugin statuses {:?}", e),
+        }
+    }
+
+    pub fn is_loaded<P: AsRef<OsStr>>(&self, filename: P) -> bool {
+        let filename = filename.as_ref().to_string_lossy();
+        self.plugins.iter().any(|plugin| plugin.path == filename)
+    }
+
+    /// Names of the loaded plugins in load order
+    pub fn names(&self) -> Vec<String> {
+        self.plugins.iter().map(|plugin| plugin.name.clone()).collect()
+    }
+
+    pub fn loaded(&self, name: &str) -> Option<&LoadedPlugin> {
+        self.plugins.iter().find(|plugin| plugin.name == name)
+    }
+
+    pub fn loaded_from<P: AsRef<OsStr>>(&self, filename: P) -> Option<&LoadedPlugin> {
+        let filename = filename.as_ref().to_string_lossy();
+        self.plugins.iter().find(|plugin| plugin.path == filename)
+    }
+
+    /// Loads the library at `filename` and lets its plugin register its services.
+    ///
+    /// # Safety
+    /// The library runs in this process: it must be trusted and built for the ABI of this core,
+    /// only its declared versions are checked. Nothing of it may be referenced anymore when it is
+    /// unloaded, i.e. the services it built are stopped before the plugin manager is dropped.
+    pub unsafe fn load_plugin<P: AsRef<OsStr>>(&mut self, filename: P, core: &mut Core) -> Result<(), PluginError> {
+        let path = filename.as_ref().to_string_lossy().to_string();
+        self.load(path, None, core)
+    }
+
+    /// # Safety
+    /// The library is only checked for its declared versions, its code is trusted after that.
+    pub unsafe fn load_discovered(&mut self, plugin: &DiscoveredPlugin, core: &mut Core) -> Result<(), PluginError> {
+        self.load(plugin.path(), Some(plugin.manifest.clone()), core)
+    }
+
+    /// Records a plugin that was found but not loaded
+    pub fn skip(&self, plugin: &SkippedPlugin) {
+        info!("Skipping plugin: {}", plugin.error);
+        self.set_status(PluginStatus {
+            path: plugin.path.clone(),
+            name: None,
+            loaded: false,
+            error: Some(plugin.error.to_string()),
+            manifest: plugin.manifest.clone(),
+        });
+    }
+
+    unsafe fn load(&mut self, path: String, manifest: Option<PluginManifest>, core: &mut Core) -> Result<(), PluginError> {
+        info!("Loading plugin {}", path);
+
+        match self.create_plugin(&path, manifest.clone(), core) {
+            Ok(name) => {
+                self.set_status(PluginStatus { path, name: Some(name), loaded: true, error: None, manifest });
+                Ok(())
+            }
+            Err(e) => {
+                self.set_status(PluginStatus { path, name: None, loaded: false, error: Some(e.to_string()), manifest });
+                Err(e)
+            }
+        }
+    }
+
+    unsafe fn create_plugin(&mut self, path: &str, manifest: Option<PluginManifest>, core: &mut Core) -> Result<String, PluginError> {
+        #[allow(improper_ctypes_definitions)]
+        type PluginCreate = unsafe extern "C" fn() -> *mut dyn Plugin;
+
+        let library = Library::new(path)
+            .map_err(|source| PluginError::Load { path: path.to_string(), source })?;
+
+        // Nothing else in the library is trusted until the versions are checked,
+        // a mismatched library is dropped right here.
+        let declaration: Symbol<*const PluginDeclaration> = library.get(b"_plugin_declaration")
+            .map_err(|_e| PluginError::SymbolNotFound { path: path.to_string(), symbol: "_plugin_declaration".to_string() })?;
+        let declaration = **declaration;
+        declaration.check(path)?;
+        debug!("Plugin {} declares core {}", path, read_version(declaration.core_version));
+
+        let constructor: Symbol<PluginCreate> = library.get(b"_plugin_create")
+            .map_err(|_e| PluginError::SymbolNotFound { path: path.to_string(), symbol: "_plugin_create".to_string() })?;
+        let plugin = Box::from_raw(constructor());
+        let name = plugin.name().to_string();
+
+        if self.loaded(&name).is_some() {
+            return Err(PluginError::DuplicateName { path: path.to_string(), name });
+        }
+
+        debug!("Loaded plugin: {}", name);
+        core.set_loading_plugin(Some(name.clone()));
+        plugin.on_load(core);
+        core.set_loading_plugin(None);
+
+        // The library is kept next to the plugin, otherwise the plugin's vtable would point to garbage
+        self.plugins.push(LoadedPlugin {
+            name: name.clone(),
+            path: path.to_string(),
+            manifest,
+            plugin,
+            library,
+        });
+
+        Ok(name)
+    }
+
+    /// Calls `on_unload` of the plugin and drops its library.
+    /// Nothing built from the plugin may be running anymore.
+    pub fn unload_plugin(&mut self, name: &str) -> Result<String, PluginError> {
+        let index = self.plugins.iter().position(|plugin| plugin.name == name)
+            .ok_or_else(|| PluginError::NotLoaded { name: name.to_string() })?;
+
+        let plugin = self.plugins.remove(index);
+        let path = plugin.path.clone();
+        debug!("Unloading plugin {}", name);
+        plugin.unload();
+        self.set_unloaded(&path);
+
+        Ok(path)
+    }
+
+    /// Unload all plugins and loaded plugin libraries, making sure to fire
+    /// their `on_plugin_unload()` methods so they can do any necessary cleanup.
+    pub fn unload(&mut self) {
+        debug!("Unloading plugins");
+
+        for plugin in self.plugins.drain(..) {
+            plugin.unload();
+        }
+
+        if let Ok(mut statuses) = self.statuses.lock() {
+            for status in statuses.iter_mut() {
+                status.loaded = false;
+            }
+        }
+    }
+}
+
+impl Default for PluginManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PluginManager {
+    fn drop(&mut self) {
+        if !self.plugins.is_empty() {
+            self.unload();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ServiceConfig;
+    use crate::core::Core;
+    use crate::plugin::{PluginDeclaration, PluginError, ServiceFactory, PLUGIN_ABI_VERSION};
+    use crate::services::tcp::tcp_service;
+    use std::os::raw::c_char;
+
+    #[test]
+    fn test_declaration_check() {
+        let check = |declaration: PluginDeclaration| unsafe { declaration.check("libplugin.so") };
+        assert!(check(PluginDeclaration::current()).is_ok());
+
+        let declaration = PluginDeclaration { abi_version: PLUGIN_ABI_VERSION + 1, ..PluginDeclaration::current() };
+        assert!(matches!(check(declaration), Err(PluginError::AbiMismatch { .. })));
+
+        let declaration = PluginDeclaration { rustc_version: b"1.0.0\0".as_ptr() as *const c_char, ..PluginDeclaration::current() };
+        assert!(matches!(check(declaration), Err(PluginError::RustcMismatch { .. })));
+
+        let declaration = PluginDeclaration { core_version: b"99.0.0\0".as_ptr() as *const c_char, ..PluginDeclaration::current() };
+        assert!(matches!(check(declaration), Err(PluginError::IncompatibleCore { .. })));
+
+        let declaration = PluginDeclaration { core_version: b"one\0".as_ptr() as *const c_char, ..PluginDeclaration::current() };
+        assert!(matches!(check(declaration), Err(PluginError::InvalidVersion { .. })));
+
+        let declaration = PluginDeclaration { rustc_version: std::ptr::null(), ..PluginDeclaration::current() };
+        assert!(matches!(check(declaration), Err(PluginError::RustcMismatch { .. })));
+    }
+
+    #[actix_rt::test]
+    async fn test_service_factory() {
+        let mut core = Core::new("Node01".to_string());
+        assert!(core.service_config("OtherTcpService".to_string(), tcp_service).is_ok());
+
+        let declaration = PluginDeclaration { abi_version: PLUGIN_ABI_VERSION + 1, ..PluginDeclaration::current() };
+        let mismatched = ServiceFactory { declaration, ..tcp_service };
+        assert!(matches!(core.service_config("OldTcpService".to_string(), mismatched), Err(PluginError::AbiMismatch { .. })));
+
+        // The functions come back through the C layout, the config is checked by on_start
+        let functions = tcp_service.create(&ServiceConfig::default()).unwrap();
+        let error = (functions.on_start)(ServiceConfig::default()).err().unwrap();
+        assert_eq!(error.to_string(), "parameter address of service  is missing");
+    }
+}
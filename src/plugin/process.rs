@@ -0,0 +1,604 @@
+//! Plugins running as external processes. The core spawns the executable and talks to it
+//! over stdin/stdout with frames of `[u32 header length][JSON header][u32 body length][body]`,
+//! lengths are big endian. Headers are tagged by `type`:
+//!
+//! - `{"type": "register", "operations": [{"name": "Originate", "version": "1.0.0"}], "consume": ["Hangup"]}`
+//!   from the process: routes of the service, in addition to the ones from its config
+//! - `{"type": "parcel", "message_type": "Hangup"}` or `{"type": "parcel", "operation": "Originate"}`
+//!   in both directions, the body is the message data
+//! - `{"type": "log", "level": "info", "message": "..."}` from the process
+//!
+//! Stderr of the process goes to the core log. A process that exits is started again with backoff.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use actix::{Actor, Addr, ArbiterHandle, AsyncContext, Context, Handler, Message, ActorFutureExt};
+use actix::fut::wrap_future;
+use log::{debug, error, info, trace, warn};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use crate::backoff::Backoff;
+use crate::config::{ConfigError, ServiceConfig};
+use crate::plugin::builtin_service;
+use crate::health::Readiness;
+use crate::message::{BaseMessage, Parcel};
+use crate::node::Node;
+use crate::operation::Operation;
+use crate::route::{Route, RouteSheet, Target};
+use crate::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use crate::signal::{ReportReadiness, Tick, UpdateServiceRoutesSignal};
+
+pub const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum ProcessError {
+    Io(std::io::Error),
+    Header(serde_json::Error),
+    FrameTooLarge { size: usize, limit: usize },
+}
+
+impl Error for ProcessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProcessError::Io(e) => Some(e),
+            ProcessError::Header(e) => Some(e),
+            ProcessError::FrameTooLarge { .. } => None,
+        }
+    }
+}
+
+impl Display for ProcessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessError::Io(e) => write!(f, "{}", e),
+            ProcessError::Header(e) => write!(f, "invalid frame header: {}", e),
+            ProcessError::FrameTooLarge { size, limit } => write!(f, "frame part of {} bytes is larger than {} bytes", size, limit),
+        }
+    }
+}
+
+impl From<std::io::Error> for ProcessError {
+    fn from(e: std::io::Error) -> Self {
+        ProcessError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationHeader {
+    pub name: String,
+    #[serde(default = "default_version")]
+    pub version: Version,
+    #[serde(default)]
+    pub description: String,
+}
+
+fn default_version() -> Version {
+    Version::new(1, 0, 0)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Header {
+    Register {
+        #[serde(default)]
+        operations: Vec<OperationHeader>,
+        #[serde(default)]
+        consume: Vec<String>,
+    },
+    Parcel {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message_type: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        operation: Option<String>,
+    },
+    Log {
+        level: String,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub header: Header,
+    pub body: Vec<u8>,
+}
+
+/// Reads the next frame, `None` when the stream ends between frames
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, max_body_size: usize) -> Result<Option<Frame>, ProcessError> {
+    let header_length = match reader.read_u32().await {
+        Ok(length) => length as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if header_length > MAX_HEADER_SIZE {
+        return Err(ProcessError::FrameTooLarge { size: header_length, limit: MAX_HEADER_SIZE });
+    }
+    let mut header = vec![0u8; header_length];
+    reader.read_exact(&mut header).await?;
+    let header = serde_json::from_slice(&header).map_err(ProcessError::Header)?;
+
+    let body_length = reader.read_u32().await? as usize;
+    if body_length > max_body_size {
+        return Err(ProcessError::FrameTooLarge { size: body_length, limit: max_body_size });
+    }
+    let mut body = vec![0u8; body_length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Some(Frame { header, body }))
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &Frame) -> Result<(), ProcessError> {
+    let header = serde_json::to_vec(&frame.header).map_err(ProcessError::Header)?;
+
+    writer.write_u32(header.len() as u32).await?;
+    writer.write_all(&header).await?;
+    writer.write_u32(frame.body.len() as u32).await?;
+    writer.write_all(&frame.body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+struct ChildFrame {
+    generation: u64,
+    frame: Frame,
+}
+
+impl Message for ChildFrame {
+    type Result = ();
+}
+
+/// Runs an external executable as a service, see the module docs for the protocol.
+///
+/// Parameters: `command`, `args` (split like a shell does, with '...', "..." and `\`),
+/// `restart_delay_in_millis`, `max_restart_delay_in_millis`, `max_frame_size` and
+/// `buffer_size` (parcels kept while the process is down, including those it didn`t read
+/// before it exited). The restart delay starts over once the process stays up for
+/// `max_restart_delay_in_millis`.
+pub struct ProcessService {
+    name: String,
+    command: String,
+    args: Vec<String>,
+    max_frame_size: usize,
+    buffer_size: usize,
+    operations: Vec<Operation>,
+    consume_messages: Vec<String>,
+    route: Route,
+    node: Option<Addr<Node>>,
+    child: Option<UnboundedSender<Frame>>,
+    /// Events of processes that were already replaced are ignored
+    generation: u64,
+    pending: VecDeque<Frame>,
+    backoff: Backoff,
+    max_restart_delay: Duration,
+    started: Option<Instant>,
+}
+
+impl ProcessService {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> {
+        let restart_delay: u64 = config.parse_parameter_or("restart_delay_in_millis", 100)?;
+        let max_restart_delay: u64 = config.parse_parameter_or("max_restart_delay_in_millis", 30_000)?;
+        let args = config.parse_parameter_or("args", String::new())?;
+        let args = split_args(&args).map_err(|reason| ConfigError::InvalidParameter {
+            service: config.name.clone(),
+            parameter: "args".to_string(),
+            value: args.clone(),
+            reason,
+        })?;
+
+        Ok(Box::new(ProcessService {
+            name: config.name.clone(),
+            command: config.parameter("command")?.clone(),
+            args,
+            max_frame_size: config.parse_parameter_or("max_frame_size", 16 * 1024 * 1024)?,
+            buffer_size: config.parse_parameter_or("buffer_size", 1000)?,
+            operations: config.operations(),
+            consume_messages: config.subscribe_on_messages.clone(),
+            route: Route::new(),
+            node: None,
+            child: None,
+            generation: 0,
+            pending: VecDeque::new(),
+            backoff: Backoff::new(Duration::from_millis(restart_delay), Duration::from_millis(max_restart_delay)),
+            max_restart_delay: Duration::from_millis(max_restart_delay),
+            started: None,
+        }))
+    }
+
+    fn start_child(&mut self, ctx: &mut Context<Self>) {
+        self.generation += 1;
+        let generation = self.generation;
+        self.started = Some(Instant::now());
+        info!("Starting process {} {:?} for service {}", self.command, self.args, self.name);
+
+        let mut child = match tokio::process::Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                self.child_exited(generation, format!("can`t start {}: {}", self.command, e), ctx);
+                return;
+            }
+        };
+
+        let (stdin, mut stdout, stderr) = match (child.stdin.take(), child.stdout.take(), child.stderr.take()) {
+            (Some(stdin), Some(stdout), Some(stderr)) => (stdin, stdout, stderr),
+            _ => {
+                self.child_exited(generation, "process pipes are not available".to_string(), ctx);
+                return;
+            }
+        };
+
+        let (sender, receiver) = unbounded_channel();
+        for frame in self.pending.drain(..) {
+            let _ = sender.send(frame);
+        }
+        self.child = Some(sender);
+
+        ctx.spawn(wrap_future(write_frames(stdin, receiver)).map(|unwritten, this: &mut Self, _ctx| this.requeue(unwritten)));
+
+        let name = self.name.clone();
+        ctx.spawn(wrap_future(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                info!("[{}] {}", name, line);
+            }
+        }));
+
+        let addr = ctx.address();
+        let max_frame_size = self.max_frame_size;
+        let exited = async move {
+            let reason = loop {
+                match read_frame(&mut stdout, max_frame_size).await {
+                    Ok(Some(frame)) => addr.do_send(ChildFrame { generation, frame }),
+                    Ok(None) => break "stdout closed".to_string(),
+                    Err(e) => break e.to_string(),
+                }
+            };
+
+            // A process that closed stdout or broke the protocol is of no use anymore
+            let _ = child.start_kill();
+            match child.wait().await {
+                Ok(status) => format!("{}, {}", reason, status),
+                Err(e) => format!("{}, {}", reason, e),
+            }
+        };
+        ctx.spawn(wrap_future(exited).map(move |reason, this: &mut Self, ctx| {
+            this.child_exited(generation, reason, ctx);
+        }));
+    }
+
+    fn child_exited(&mut self, generation: u64, reason: String, ctx: &mut Context<Self>) {
+        if generation != self.generation {
+            return;
+        }
+
+        self.child = None;
+        self.report_readiness(Readiness::not_ready(format!("process exited: {}", reason)));
+
+        // A process counts as started once it ran long enough, a registration is no proof it keeps running
+        if self.started.take().is_some_and(|started| started.elapsed() >= self.max_restart_delay) {
+            self.backoff.reset();
+        }
+        let delay = self.backoff.next_delay();
+        warn!("Process of service {} exited ({}), restart {} in {:?}", self.name, reason, self.backoff.attempts(), delay);
+        ctx.run_later(delay, |this, ctx| this.start_child(ctx));
+    }
+
+    fn report_readiness(&self, readiness: Readiness) {
+        if let Some(node) = &self.node {
+            node.do_send(ReportReadiness { service: self.name.clone(), readiness });
+        }
+    }
+
+    fn send_to_child(&mut self, frame: Frame) {
+        let frame = match &self.child {
+            Some(child) => match child.send(frame) {
+                Ok(()) => return,
+                Err(e) => e.0,
+            },
+            None => frame,
+        };
+
+        if self.pending.len() >= self.buffer_size {
+            warn!("Buffer of service {} is full, dropping the oldest parcel", self.name);
+            self.pending.pop_front();
+        }
+        self.pending.push_back(frame);
+    }
+
+    /// Puts frames the writer of an exited process hands back before the frames buffered since
+    fn requeue(&mut self, frames: Vec<Frame>) {
+        if self.child.is_some() {
+            for frame in frames {
+                self.send_to_child(frame);
+            }
+            return;
+        }
+
+        for frame in frames.into_iter().rev() {
+            self.pending.push_front(frame);
+        }
+        if self.pending.len() > self.buffer_size {
+            let excess = self.pending.len() - self.buffer_size;
+            warn!("Buffer of service {} is full, dropping the {} oldest parcels", self.name, excess);
+            self.pending.drain(..excess);
+        }
+    }
+
+    fn register(&mut self, operations: Vec<OperationHeader>, consume: Vec<String>) {
+        let mut all_operations = self.operations.clone();
+        for operation in operations {
+            let operation = Operation::new(operation.name, operation.version, operation.description);
+            if !all_operations.contains(&operation) {
+                all_operations.push(operation);
+            }
+        }
+
+        let mut consume_messages = self.consume_messages.clone();
+        for message_type in consume {
+            if !consume_messages.contains(&message_type) {
+                consume_messages.push(message_type);
+            }
+        }
+
+        debug!("Process of service {} registered {:?} {:?}", self.name, all_operations, consume_messages);
+        if let Some(node) = &self.node {
+            node.do_send(UpdateServiceRoutesSignal {
+                name: self.name.clone(),
+                operations: all_operations,
+                consume_messages,
+            });
+        }
+
+        self.report_readiness(Readiness::ready());
+    }
+
+    fn publish(&self, message_type: Option<String>, operation: Option<String>, body: Vec<u8>) {
+        let target = match (message_type, operation) {
+            (Some(message_type), _) => Target::Consumer(message_type),
+            (None, Some(operation)) => Target::Route(Route::new().set_operation_name(operation).clone()),
+            (None, None) => {
+                warn!("Parcel from process of service {} has no message type or operation", self.name);
+                return;
+            }
+        };
+
+        if let Some(node) = &self.node {
+            let route_sheet = RouteSheet::new(target, self.route.clone());
+            node.do_send(Parcel::new(vec![BaseMessage::new(body, None)], route_sheet));
+        }
+    }
+}
+
+/// Splits `args` on whitespace outside of '...' and "...". `\` escapes the next character,
+/// inside "..." only `"` and `\`.
+fn split_args(args: &str) -> Result<Vec<String>, String> {
+    let mut split = vec![];
+    let mut arg: Option<String> = None;
+    let mut chars = args.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated '".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err("unterminated \"".to_string()),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated \"".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => return Err("nothing to escape after \\".to_string()),
+            },
+            c if c.is_whitespace() => split.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    split.extend(arg);
+    Ok(split)
+}
+
+/// Returns the frames that were not written, once the process can`t take them anymore
+async fn write_frames<W: AsyncWrite + Unpin>(mut stdin: W, mut receiver: UnboundedReceiver<Frame>) -> Vec<Frame> {
+    while let Some(frame) = receiver.recv().await {
+        if let Err(e) = write_frame(&mut stdin, &frame).await {
+            debug!("Can`t write to process {}", e);
+            let mut unwritten = vec![frame];
+            receiver.close();
+            while let Ok(frame) = receiver.try_recv() {
+                unwritten.push(frame);
+            }
+            return unwritten;
+        }
+    }
+    vec![]
+}
+
+builtin_service!(process_service => ProcessService::on_start);
+
+impl Actor for ProcessService {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.start_child(ctx);
+    }
+}
+
+impl Service for ProcessService {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Sending message to process {:?}", message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl Handler<Parcel> for ProcessService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        let header = match msg.target() {
+            Target::Consumer(message_type) => Header::Parcel { message_type: Some(message_type.clone()), operation: None },
+            Target::Route(route) => Header::Parcel { message_type: None, operation: Some(route.operation_name().clone()) },
+        };
+
+        for message in msg.unpack() {
+            self.handle_message(message);
+            self.send_to_child(Frame { header: header.clone(), body: message.data().clone() });
+        }
+    }
+}
+
+impl Handler<ChildFrame> for ProcessService {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChildFrame, _ctx: &mut Self::Context) -> Self::Result {
+        if msg.generation != self.generation {
+            return;
+        }
+
+        match msg.frame.header {
+            Header::Register { operations, consume } => self.register(operations, consume),
+            Header::Parcel { message_type, operation } => self.publish(message_type, operation, msg.frame.body),
+            Header::Log { level, message } => match level.as_str() {
+                "error" => error!("[{}] {}", self.name, message),
+                "warn" => warn!("[{}] {}", self.name, message),
+                "debug" => debug!("[{}] {}", self.name, message),
+                "trace" => trace!("[{}] {}", self.name, message),
+                _ => info!("[{}] {}", self.name, message),
+            },
+        }
+    }
+}
+
+impl Handler<Tick> for ProcessService {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plugin::process::{read_frame, split_args, write_frame, Frame, Header, ProcessError};
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::route::Target;
+    use crate::signal::GetStatistics;
+    use std::time::{Duration, Instant};
+
+    /// `printf` argument writing a frame
+    fn printf_frame(header: &str, body: &str) -> String {
+        let length = |length: usize| (length as u32).to_be_bytes().iter().map(|byte| format!("\\{:03o}", byte)).collect::<String>();
+        format!("{}{}{}{}", length(header.len()), header.replace('"', "\\\""), length(body.len()), body)
+    }
+
+    #[actix_rt::test]
+    async fn test_frames() {
+        let frame = Frame {
+            header: Header::Parcel { message_type: Some("Hangup".to_string()), operation: None },
+            body: b"channel=SIP/100".to_vec(),
+        };
+
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &frame).await.unwrap();
+        assert_eq!(&buffer[4..4 + 41], br#"{"type":"parcel","message_type":"Hangup"}"#.as_ref());
+
+        let mut reader: &[u8] = &buffer;
+        assert_eq!(read_frame(&mut reader, 1024).await.unwrap(), Some(frame));
+        assert_eq!(read_frame(&mut reader, 1024).await.unwrap(), None);
+
+        let mut reader: &[u8] = &buffer;
+        assert!(matches!(read_frame(&mut reader, 4).await, Err(ProcessError::FrameTooLarge { size: 15, limit: 4 })));
+
+        let register: Header = serde_json::from_str(r#"{"type":"register","operations":[{"name":"Originate"}]}"#).unwrap();
+        assert!(matches!(register, Header::Register { operations, consume } if operations[0].version.major == 1 && consume.is_empty()));
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args(r#" -c  'echo "a b"' "x \"y\" \n" z\ w '' "#).unwrap(), vec!["-c", r#"echo "a b""#, r#"x "y" \n"#, "z w", ""]);
+        assert!(split_args("'open").is_err());
+        assert!(split_args(r#""open\""#).is_err());
+        assert!(split_args("").unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_restart() {
+        // The script path has a space, it is passed as one quoted argument
+        let directory = std::env::temp_dir().join(format!("any_message process {}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let script = directory.join("plugin.sh");
+        std::fs::write(&script, format!(
+            "printf \"{}\"\nprintf \"{}\"\necho started >&2\n",
+            printf_frame(r#"{"type":"register","consume":["Ping"]}"#, ""),
+            printf_frame(r#"{"type":"parcel","message_type":"Pong"}"#, "pong"),
+        )).unwrap();
+
+        let mut core = Core::new("Node01".to_string());
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "ProcessCore"
+Node:
+  name: "Node01"
+  external:
+    type: "ProcessService"
+    parameters:
+      command: "/bin/sh"
+      args: "'{}'"
+      restart_delay_in_millis: 10
+"#, script.display())).build().unwrap();
+        core.apply_config(config).await.unwrap();
+
+        // Every run of the script publishes one Pong, the second one comes from a restarted process
+        let pong = Target::Consumer("Pong".to_string()).as_string();
+        let started = Instant::now();
+        loop {
+            let snapshot = core.node().send(GetStatistics { service: None }).await.unwrap().unwrap();
+            if snapshot.node.targets.get(&pong).map_or(0, |target| target.parcels_in) >= 2 {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10), "process is not restarted");
+            actix_rt::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        core.stop_service("external").await;
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
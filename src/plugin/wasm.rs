@@ -0,0 +1,462 @@
+//! Services implemented by WebAssembly modules. A module runs in its own store with a limit on
+//! linear memory and on fuel for every call, so it can only reach the node through the host ABI.
+//!
+//! Imports of the module, namespace `any_message`, strings are UTF-8 `(ptr, len)` pairs:
+//!
+//! - `declare_operation(name_ptr, name_len, version_ptr, version_len)`, an empty version is `1.0.0`
+//! - `consume(message_type_ptr, message_type_len)`
+//! - `publish(message_type_ptr, message_type_len, data_ptr, data_len)` to the consumers of the type
+//! - `call(operation_ptr, operation_len, data_ptr, data_len)` to the service of the operation
+//! - `log(level, message_ptr, message_len)`, levels 0 error to 4 trace
+//!
+//! Exports of the module:
+//!
+//! - `memory`
+//! - `alloc(len) -> ptr`, buffers for the host; they belong to the module afterwards
+//! - `init()`, optional, called once after instantiation to declare operations and consumed types
+//! - `handle(kind, name_ptr, name_len, data_ptr, data_len)` for every message, `kind` is 0 for a
+//!   consumed message type and 1 for an operation
+//!
+//! Parcels published by a call that traps are dropped.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use actix::{Actor, Addr, ArbiterHandle, Context, Handler};
+use log::{debug, error, info, trace, warn};
+use semver::Version;
+use wasmi::{Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+use crate::config::ServiceConfig;
+use crate::plugin::builtin_service;
+use crate::message::{BaseMessage, Parcel};
+use crate::node::Node;
+use crate::operation::Operation;
+use crate::route::{Route, RouteSheet, Target};
+use crate::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use crate::signal::{Tick, UpdateServiceRoutesSignal};
+
+pub const HOST_MODULE: &str = "any_message";
+pub const DEFAULT_FUEL: u64 = 10_000_000;
+pub const DEFAULT_MAX_MEMORY_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+const KIND_MESSAGE_TYPE: i32 = 0;
+const KIND_OPERATION: i32 = 1;
+
+#[derive(Debug)]
+pub enum WasmError {
+    Io { path: String, source: std::io::Error },
+    /// Invalid module, failed instantiation or a trap
+    Runtime(wasmi::Error),
+    MissingExport(String),
+}
+
+impl Error for WasmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WasmError::Io { source, .. } => Some(source),
+            WasmError::Runtime(e) => Some(e),
+            WasmError::MissingExport(_) => None,
+        }
+    }
+}
+
+impl Display for WasmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WasmError::Io { path, source } => write!(f, "can`t read module {}: {}", path, source),
+            WasmError::Runtime(e) => write!(f, "{}", e),
+            WasmError::MissingExport(name) => write!(f, "module doesn`t export {}", name),
+        }
+    }
+}
+
+impl From<wasmi::Error> for WasmError {
+    fn from(e: wasmi::Error) -> Self {
+        WasmError::Runtime(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WasmLimits {
+    /// Fuel of a single call into the module, roughly one unit per instruction
+    pub fuel: u64,
+    pub max_memory_size: usize,
+    /// Largest string or data the module can pass to the host
+    pub max_message_size: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self { fuel: DEFAULT_FUEL, max_memory_size: DEFAULT_MAX_MEMORY_SIZE, max_message_size: DEFAULT_MAX_MESSAGE_SIZE }
+    }
+}
+
+struct HostState {
+    name: String,
+    limits: StoreLimits,
+    max_message_size: usize,
+    operations: Vec<Operation>,
+    consume_messages: Vec<String>,
+    routes_changed: bool,
+    published: Vec<(Target, Vec<u8>)>,
+}
+
+fn memory(caller: &Caller<'_, HostState>) -> Result<Memory, wasmi::Error> {
+    caller.get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("module doesn`t export memory"))
+}
+
+/// Copies `len` bytes at `ptr` out of the module, the range is checked before anything is allocated
+fn read_bytes(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, wasmi::Error> {
+    let memory = memory(caller)?;
+    let (start, size) = (ptr as u32 as usize, len as u32 as usize);
+    let limit = caller.data().max_message_size;
+    if size > limit {
+        return Err(wasmi::Error::new(format!("{} bytes at {} are more than the limit of {} bytes", size, ptr, limit)));
+    }
+
+    start.checked_add(size)
+        .and_then(|end| memory.data(caller).get(start..end))
+        .map(|bytes| bytes.to_vec())
+        .ok_or_else(|| wasmi::Error::new(format!("{} bytes at {} are outside of the memory", size, ptr)))
+}
+
+fn read_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<String, wasmi::Error> {
+    String::from_utf8(read_bytes(caller, ptr, len)?).map_err(|e| wasmi::Error::new(e.to_string()))
+}
+
+fn link_host(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    linker.func_wrap(HOST_MODULE, "declare_operation",
+                     |mut caller: Caller<'_, HostState>, name_ptr: i32, name_len: i32, version_ptr: i32, version_len: i32| {
+        let name = read_string(&caller, name_ptr, name_len)?;
+        let version = match read_string(&caller, version_ptr, version_len)? {
+            version if version.is_empty() => Version::new(1, 0, 0),
+            version => Version::parse(&version).map_err(|e| wasmi::Error::new(e.to_string()))?,
+        };
+
+        let operation = Operation::new(name, version, String::new());
+        let state = caller.data_mut();
+        if !state.operations.contains(&operation) {
+            state.operations.push(operation);
+            state.routes_changed = true;
+        }
+        Ok(())
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "consume", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+        let message_type = read_string(&caller, ptr, len)?;
+        let state = caller.data_mut();
+        if !state.consume_messages.contains(&message_type) {
+            state.consume_messages.push(message_type);
+            state.routes_changed = true;
+        }
+        Ok(())
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "publish",
+                     |mut caller: Caller<'_, HostState>, type_ptr: i32, type_len: i32, data_ptr: i32, data_len: i32| {
+        let message_type = read_string(&caller, type_ptr, type_len)?;
+        let data = read_bytes(&caller, data_ptr, data_len)?;
+        caller.data_mut().published.push((Target::Consumer(message_type), data));
+        Ok(())
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "call",
+                     |mut caller: Caller<'_, HostState>, operation_ptr: i32, operation_len: i32, data_ptr: i32, data_len: i32| {
+        let operation = read_string(&caller, operation_ptr, operation_len)?;
+        let data = read_bytes(&caller, data_ptr, data_len)?;
+        let target = Target::Route(Route::new().set_operation_name(operation).clone());
+        caller.data_mut().published.push((target, data));
+        Ok(())
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "log", |caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32| {
+        let message = read_string(&caller, ptr, len)?;
+        let name = &caller.data().name;
+        match level {
+            0 => error!("[{}] {}", name, message),
+            1 => warn!("[{}] {}", name, message),
+            2 => info!("[{}] {}", name, message),
+            3 => debug!("[{}] {}", name, message),
+            _ => trace!("[{}] {}", name, message),
+        }
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+/// Instantiated module with its store
+pub struct WasmModule {
+    store: Store<HostState>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    handle: TypedFunc<(i32, i32, i32, i32, i32), ()>,
+    limits: WasmLimits,
+}
+
+impl WasmModule {
+    pub fn from_file(name: &str, path: &Path, limits: WasmLimits) -> Result<Self, WasmError> {
+        let bytes = std::fs::read(path).map_err(|source| WasmError::Io { path: path.display().to_string(), source })?;
+        Self::new(name, &bytes, limits)
+    }
+
+    /// Instantiates the module and runs its `init`
+    pub fn new(name: &str, wasm: &[u8], limits: WasmLimits) -> Result<Self, WasmError> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)?;
+
+        let state = HostState {
+            name: name.to_string(),
+            limits: StoreLimitsBuilder::new().memory_size(limits.max_memory_size).instances(1).build(),
+            max_message_size: limits.max_message_size,
+            operations: vec![],
+            consume_messages: vec![],
+            routes_changed: false,
+            published: vec![],
+        };
+        let mut store = Store::new(&engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(limits.fuel).map_err(wasmi::Error::from)?;
+
+        let mut linker = Linker::new(&engine);
+        link_host(&mut linker)?;
+        let instance: Instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+
+        let memory = instance.get_memory(&store, "memory").ok_or_else(|| WasmError::MissingExport("memory".to_string()))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|_e| WasmError::MissingExport("alloc".to_string()))?;
+        let handle = instance.get_typed_func::<(i32, i32, i32, i32, i32), ()>(&store, "handle")
+            .map_err(|_e| WasmError::MissingExport("handle".to_string()))?;
+
+        if let Some(init) = instance.get_func(&store, "init") {
+            let init = init.typed::<(), ()>(&store)?;
+            store.set_fuel(limits.fuel).map_err(wasmi::Error::from)?;
+            init.call(&mut store, ())?;
+        }
+        store.data_mut().routes_changed = false;
+        store.data_mut().published.clear();
+
+        Ok(Self { store, memory, alloc, handle, limits })
+    }
+
+    pub fn operations(&self) -> &Vec<Operation> {
+        &self.store.data().operations
+    }
+
+    pub fn consume_messages(&self) -> &Vec<String> {
+        &self.store.data().consume_messages
+    }
+
+    /// Routes declared since the last call
+    pub fn take_routes_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.store.data_mut().routes_changed, false)
+    }
+
+    /// Passes the message to `handle`, returns the published parcels
+    pub fn handle(&mut self, target: &Target, data: &[u8]) -> Result<Vec<(Target, Vec<u8>)>, WasmError> {
+        let (kind, name) = match target {
+            Target::Consumer(message_type) => (KIND_MESSAGE_TYPE, message_type.as_bytes()),
+            Target::Route(route) => (KIND_OPERATION, route.operation_name().as_bytes()),
+        };
+
+        self.store.set_fuel(self.limits.fuel).map_err(wasmi::Error::from)?;
+        let result = self.copy_in(name)
+            .and_then(|name_ptr| Ok((name_ptr, self.copy_in(data)?)))
+            .and_then(|(name_ptr, data_ptr)| {
+                self.handle.call(&mut self.store, (kind, name_ptr, name.len() as i32, data_ptr, data.len() as i32))
+                    .map_err(WasmError::from)
+            });
+
+        let published = std::mem::take(&mut self.store.data_mut().published);
+        result.map(|()| published)
+    }
+
+    fn copy_in(&mut self, bytes: &[u8]) -> Result<i32, WasmError> {
+        let ptr = self.alloc.call(&mut self.store, bytes.len() as i32)?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, bytes)
+            .map_err(|e| wasmi::Error::new(format!("can`t write {} bytes at {}: {}", bytes.len(), ptr, e)))?;
+        Ok(ptr)
+    }
+}
+
+/// Runs a WebAssembly module as a service, see the module docs for the ABI.
+///
+/// Parameters: `module` (path of the `.wasm` file), `fuel` (per call), `max_memory_size` and
+/// `max_message_size` in bytes.
+pub struct WasmService {
+    name: String,
+    module: WasmModule,
+    operations: Vec<Operation>,
+    consume_messages: Vec<String>,
+    route: Route,
+    node: Option<Addr<Node>>,
+}
+
+impl WasmService {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> {
+        let limits = WasmLimits {
+            fuel: config.parse_parameter_or("fuel", DEFAULT_FUEL)?,
+            max_memory_size: config.parse_parameter_or("max_memory_size", DEFAULT_MAX_MEMORY_SIZE)?,
+            max_message_size: config.parse_parameter_or("max_message_size", DEFAULT_MAX_MESSAGE_SIZE)?,
+        };
+        let path = config.parameter("module")?.clone();
+        let module = WasmModule::from_file(&config.name, Path::new(&path), limits)?;
+        debug!("Module {} of service {} declared {:?} {:?}", path, config.name, module.operations(), module.consume_messages());
+
+        Ok(Box::new(WasmService {
+            name: config.name.clone(),
+            module,
+            operations: config.operations(),
+            consume_messages: config.subscribe_on_messages.clone(),
+            route: Route::new(),
+            node: None,
+        }))
+    }
+
+    /// Routes declared by the module after `init`, merged with the ones from the config
+    fn update_routes(&self) {
+        let mut operations = self.operations.clone();
+        for operation in self.module.operations() {
+            if !operations.contains(operation) {
+                operations.push(operation.clone());
+            }
+        }
+
+        let mut consume_messages = self.consume_messages.clone();
+        for message_type in self.module.consume_messages() {
+            if !consume_messages.contains(message_type) {
+                consume_messages.push(message_type.clone());
+            }
+        }
+
+        if let Some(node) = &self.node {
+            node.do_send(UpdateServiceRoutesSignal { name: self.name.clone(), operations, consume_messages });
+        }
+    }
+}
+
+builtin_service!(wasm_service => WasmService::on_start);
+
+impl Actor for WasmService {
+    type Context = Context<Self>;
+}
+
+impl Service for WasmService {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        for operation in self.module.operations() {
+            service_core.add_operation(operation.clone());
+        }
+        service_core.set_consuming_messages_types(self.module.consume_messages().clone());
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Passing message to module {:?}", message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl Handler<Parcel> for WasmService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        for message in msg.unpack() {
+            self.handle_message(message);
+
+            let published = match self.module.handle(msg.target(), message.data()) {
+                Ok(published) => published,
+                Err(e) => {
+                    error!("Module of service {} failed on {}: {}", self.name, msg.target().as_string(), e);
+                    continue;
+                }
+            };
+
+            if let Some(node) = &self.node {
+                for (target, data) in published {
+                    let route_sheet = RouteSheet::new(target, self.route.clone());
+                    node.do_send(Parcel::new(vec![BaseMessage::new(data, None)], route_sheet));
+                }
+            }
+        }
+
+        if self.module.take_routes_changed() {
+            self.update_routes();
+        }
+    }
+}
+
+impl Handler<Tick> for WasmService {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plugin::wasm::{WasmError, WasmLimits, WasmModule};
+    use crate::route::{Route, Target};
+
+    /// Consumes `Ping` and publishes its data as `Pong`; the `Spin` operation never returns,
+    /// `Huge` publishes 4 GiB and `Grow` asks for another 64 KiB page
+    const MODULE: &str = r#"
+        (module
+          (import "any_message" "consume" (func $consume (param i32 i32)))
+          (import "any_message" "publish" (func $publish (param i32 i32 i32 i32)))
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (data (i32.const 0) "PingPong")
+          (func (export "alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+          (func (export "init")
+            (call $consume (i32.const 0) (i32.const 4)))
+          (func (export "handle") (param $kind i32) (param $name i32) (param $name_len i32) (param $data i32) (param $data_len i32)
+            (if (i32.eq (local.get $kind) (i32.const 1))
+              (then
+                (if (i32.eq (i32.load8_u (local.get $name)) (i32.const 83))
+                  (then (loop $spin (br $spin))))
+                (if (i32.eq (i32.load8_u (local.get $name)) (i32.const 72))
+                  (then (call $publish (i32.const 4) (i32.const 4) (i32.const 0) (i32.const -1)) (return)))
+                (if (i32.lt_s (memory.grow (i32.const 1)) (i32.const 0))
+                  (then unreachable))
+                (return)))
+            (call $publish (i32.const 4) (i32.const 4) (local.get $data) (local.get $data_len))))
+    "#;
+
+    fn operation(name: &str) -> Target {
+        Target::Route(Route::new().set_operation_name(name.to_string()).clone())
+    }
+
+    #[test]
+    fn test_wasm_module() {
+        let wasm = wat::parse_str(MODULE).unwrap();
+        let limits = WasmLimits { fuel: 100_000, max_memory_size: 64 * 1024, max_message_size: 16 };
+        let mut module = WasmModule::new("transform", &wasm, limits).unwrap();
+        assert_eq!(module.consume_messages(), &vec!["Ping".to_string()]);
+        assert!(!module.take_routes_changed());
+
+        let published = module.handle(&Target::Consumer("Ping".to_string()), b"hello").unwrap();
+        assert_eq!(published, vec![(Target::Consumer("Pong".to_string()), b"hello".to_vec())]);
+
+        // Out of fuel, out of memory and data over the limit trap, the module stays usable
+        assert!(matches!(module.handle(&operation("Spin"), b""), Err(WasmError::Runtime(_))));
+        assert!(matches!(module.handle(&operation("Huge"), b""), Err(WasmError::Runtime(_))));
+        assert!(matches!(module.handle(&Target::Consumer("Ping".to_string()), &[0u8; 17]), Err(WasmError::Runtime(_))));
+        assert!(matches!(module.handle(&operation("Grow"), b""), Err(WasmError::Runtime(_))));
+        assert_eq!(module.handle(&Target::Consumer("Ping".to_string()), b"again").unwrap().len(), 1);
+
+        let roomy = WasmLimits { fuel: 100_000, max_memory_size: 128 * 1024, max_message_size: usize::MAX };
+        let mut module = WasmModule::new("transform", &wasm, roomy).unwrap();
+        assert!(module.handle(&operation("Grow"), b"").is_ok());
+        assert!(matches!(module.handle(&operation("Huge"), b""), Err(WasmError::Runtime(_))));
+    }
+}
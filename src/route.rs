@@ -1,4 +1,20 @@
 use semver::Version;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteParseError {
+    route: String,
+}
+
+impl Error for RouteParseError {}
+
+impl Display for RouteParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} is neither a route like \"@node::service/operation:id\" nor \"Consumer(message_type)\"", self.route)
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Route {
@@ -81,6 +97,46 @@ impl Route {
     }
 }
 
+/// Parses what `as_string` prints
+impl FromStr for Route {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || RouteParseError { route: s.to_string() };
+        let part = |rest: &str, separators: &[char]| {
+            let end = rest.find(|c| separators.contains(&c)).unwrap_or(rest.len());
+            (rest[..end].to_string(), rest[end..].to_string())
+        };
+
+        let mut route = Route::new();
+        let mut rest = s.trim().to_string();
+        if let Some(node) = rest.strip_prefix('@') {
+            let (node_name, tail) = part(node, &[':', '/']);
+            route.set_node_name(node_name);
+            rest = tail;
+        }
+        if let Some(service) = rest.strip_prefix("::") {
+            let (service_name, tail) = part(service, &[':', '/']);
+            route.set_service_name(service_name);
+            rest = tail;
+        }
+        if let Some(operation) = rest.strip_prefix('/') {
+            let (operation_name, tail) = part(operation, &[':']);
+            route.set_operation_name(operation_name);
+            rest = tail;
+        }
+        if let Some(inner_id) = rest.strip_prefix(':') {
+            route.set_inner_id(inner_id.to_string());
+            rest = String::new();
+        }
+
+        match rest.is_empty() && !route.as_string().is_empty() {
+            true => Ok(route),
+            false => Err(error()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RouteSheet {
     target: Target,
@@ -106,6 +162,19 @@ impl Target {
     }
 }
 
+impl FromStr for Target {
+    type Err = RouteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.strip_prefix("Consumer(").and_then(|rest| rest.strip_suffix(')')) {
+            Some(message_type) if !message_type.is_empty() => Ok(Target::Consumer(message_type.to_string())),
+            Some(_) => Err(RouteParseError { route: s.to_string() }),
+            None => s.parse().map(Target::Route),
+        }
+    }
+}
+
 impl RouteSheet {
     pub fn new(target: Target, from: Route) -> Self {
         Self {
@@ -117,7 +186,31 @@ impl RouteSheet {
     pub fn target(&self) -> &Target {
         &self.target
     }
+
+    pub fn set_target(&mut self, target: Target) {
+        self.target = target;
+    }
     pub fn from(&self) -> &Route {
         &self.from
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::route::{Route, Target};
+
+    #[test]
+    fn test_parse_target() {
+        let route: Route = "@Node01::telnet/Originate:42".parse().unwrap();
+        assert_eq!(route.node_name(), "Node01");
+        assert_eq!(route.service_name(), "telnet");
+        assert_eq!(route.operation_name(), "Originate");
+        assert_eq!(route.inner_id(), "42");
+        assert_eq!(route.as_string(), "@Node01::telnet/Originate:42");
+
+        assert_eq!("/Originate".parse::<Target>().unwrap(), Target::Route(Route::new().set_operation_name("Originate".to_string()).clone()));
+        assert_eq!("Consumer(Hangup)".parse::<Target>().unwrap(), Target::Consumer("Hangup".to_string()));
+        assert!("Hangup".parse::<Target>().is_err());
+        assert!("Consumer()".parse::<Target>().is_err());
+    }
+}
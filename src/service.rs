@@ -1,6 +1,7 @@
-use crate::route::{Route};
-use actix::{Recipient, Handler, Addr, Actor, Context};
-use crate::signal::{Tick, LinkService};
+use crate::route::{Route, Target};
+use actix::{Recipient, Handler, Addr, Actor, Context, ArbiterHandle, AsyncContext, ResponseFuture};
+use actix::fut::wrap_future;
+use crate::signal::{Tick, LinkService, Heartbeat};
 use crate::message::{BaseMessage, Parcel, Request};
 use crate::operation::{Operation};
 use std::collections::HashMap;
@@ -9,12 +10,86 @@ use crate::node::{Node};
 use crate::transport::Transport;
 use crate::core::Core;
 use actix::dev::ToEnvelope;
-use log::{trace, error};
+use log::{trace, debug, error};
 use crate::config::ServiceConfig;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::statistics::{Counters, Histogram, SlidingWindow};
+use crate::interceptor::{InterceptContext, Intercepted, InterceptPoint, InterceptorChain};
+
+#[derive(Debug)]
+pub enum ServiceError {
+    Connection { service: String, reason: String },
+    NotRunning(String),
+    Spawn(String),
+}
+
+impl Error for ServiceError {}
+
+impl Display for ServiceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceError::Connection { service, reason } => write!(f, "service {} can`t connect: {}", service, reason),
+            ServiceError::NotRunning(service) => write!(f, "service {} is not running", service),
+            ServiceError::Spawn(reason) => write!(f, "can`t spawn service: {}", reason),
+        }
+    }
+}
 
 pub trait Service {
     fn config_system(&mut self, system_core: &mut ServiceCore, node: Addr<Node>);
     fn handle_message(&self, message: &BaseMessage);
+    /// Starts the service in the arbiter, returns where its `ServiceCore` should deliver parcels.
+    /// Actor based services usually just call `spawn_service_actor(*self, arbiter)`.
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError>;
+}
+
+pub fn spawn_service_actor<A>(actor: A, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError>
+    where A: Actor<Context = Context<A>> + Handler<Tick> + Handler<Parcel> + Send
+{
+    if actix_rt::System::try_current().is_some() {
+        return Ok(A::start_in_arbiter(arbiter, |_ctx| actor).recipients());
+    }
+
+    // Called from a plugin library: it has its own copy of the runtime, which is not running
+    // in the core's threads. The actor gets a thread with its own system, the thread lives
+    // as long as the service's arbiter.
+    let (recipients_sender, recipients_receiver) = std::sync::mpsc::channel();
+    let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        actix_rt::System::new().block_on(async move {
+            let _ = recipients_sender.send(actor.start().recipients());
+            let _ = stop_receiver.await;
+        });
+    });
+
+    let guard = ServiceThread { stop: Some(stop_sender), thread: Some(thread) };
+    arbiter.spawn(async move {
+        let _guard = guard;
+        std::future::pending::<()>().await
+    });
+
+    recipients_receiver.recv()
+        .map_err(|_e| ServiceError::Spawn("service thread stopped before the actor started".to_string()))
+}
+
+/// Stops and joins the thread of a plugin service when the service's arbiter drops it
+struct ServiceThread {
+    stop: Option<tokio::sync::oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for ServiceThread {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Service thread panicked");
+            }
+        }
+    }
 }
 
 pub trait ServiceRecipient<T: Actor + Handler<Tick> + Handler<Parcel>>
@@ -53,9 +128,11 @@ impl ServiceRecipients {
     }
 }
 
-pub struct ServiceFunctions {
-    pub on_start: Box<dyn Fn(ServiceConfig) -> Result<Box<dyn Service>, Box<dyn std::error::Error>> + Send>,
+/// Builds the service from its config
+pub type OnStart = Box<dyn Fn(ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> + Send>;
 
+pub struct ServiceFunctions {
+    pub on_start: OnStart,
 }
 
 
@@ -64,12 +141,13 @@ pub struct ServiceCore {
     operations: Vec<Operation>,
     consume_message_types: Vec<String>,
     requests_awaits: HashMap<String, Request>,
-    statistics: ServiceStatistics,
+    statistics: Arc<Mutex<ServiceStatistics>>,
     node: Addr<Node>,
     next: Option<Recipient<Parcel>>,
     recipients: Option<ServiceRecipients>,
     transport: Option<Transport>,
     functions: Option<ServiceFunctions>,
+    interceptors: InterceptorChain,
 }
 
 impl ServiceCore {
@@ -81,12 +159,13 @@ impl ServiceCore {
             consume_message_types: vec![],
             //   operation_handlers: Default::default(),
             requests_awaits: Default::default(),
-            statistics: ServiceStatistics::new(),
+            statistics: Default::default(),
             node,
             next: None,
             recipients: None,
             transport: None,
             functions: None,
+            interceptors: InterceptorChain::new(),
         }
     }
 
@@ -98,6 +177,10 @@ impl ServiceCore {
         &self.route
     }
 
+    pub fn set_interceptors(&mut self, interceptors: InterceptorChain) {
+        self.interceptors = interceptors;
+    }
+
     pub fn add_operation(&mut self, operation: Operation) {
         self.operations.push(operation);
     }
@@ -124,6 +207,10 @@ impl ServiceCore {
     pub fn node(&self) -> Addr<Node> {
         self.node.clone()
     }
+
+    pub fn statistics(&self) -> Arc<Mutex<ServiceStatistics>> {
+        self.statistics.clone()
+    }
 }
 
 impl Actor for ServiceCore {
@@ -133,19 +220,76 @@ impl Actor for ServiceCore {
 impl Handler<Parcel> for ServiceCore {
     type Result = ();
 
-    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: Parcel, ctx: &mut Self::Context) -> Self::Result {
         trace!("[{:?}] Consuming in system",std::thread::current().id());
         match &self.recipients {
             None => {
                 self.node.do_send(msg);
             }
             Some(recepients) => {
-                match recepients.parcel.do_send(msg) {
-                    Err(e) => {
+                let service = self.route.service_name().clone();
+                let msg = match self.interceptors.run(&InterceptContext::new(InterceptPoint::BeforeDelivery).service(&service), msg) {
+                    Intercepted::Continue(msg) => msg,
+                    Intercepted::Rerouted(msg) => {
+                        self.node.do_send(msg);
+                        return;
+                    }
+                    Intercepted::Rejected { interceptor, reason } => {
+                        debug!("Interceptor {} rejected parcel for {}: {}", interceptor, service, reason);
+                        return;
+                    }
+                };
+
+                let target = msg.target().clone();
+                let messages = msg.unpack().len() as u64;
+                let bytes = msg.unpack().iter().map(|message| message.data().len() as u64).sum();
+                let statistics = self.statistics.clone();
+                let started = Instant::now();
+                // Only kept for the interceptors, the actor gets the parcel itself
+                let failed = match self.interceptors.intercepts(InterceptPoint::DeliveryFailure) {
+                    true => Some((msg.clone(), self.interceptors.clone(), self.node.clone())),
+                    false => None,
+                };
+                let delivery = recepients.parcel.send(msg);
+
+                ctx.spawn(wrap_future(async move {
+                    let result = delivery.await;
+                    if let Err(e) = &result {
                         error!("Error {:?}", e);
+                        if let Some((parcel, interceptors, node)) = failed {
+                            let error = e.to_string();
+                            let context = InterceptContext::new(InterceptPoint::DeliveryFailure).service(&service).error(&error);
+                            if let Intercepted::Rerouted(parcel) = interceptors.run(&context, parcel) {
+                                node.do_send(parcel);
+                            }
+                        }
+                    }
+
+                    match statistics.lock() {
+                        Ok(mut statistics) => {
+                            statistics.record_delivery(&target, messages, bytes, started.elapsed(), result.is_ok());
+                        }
+                        Err(e) => error!("Error to access statistics {:?}", e),
                     }
-                    _ => {}
-                }
+                }));
+            }
+        }
+    }
+}
+
+/// The service is alive as long as its actor handles ticks
+impl Handler<Heartbeat> for ServiceCore {
+    type Result = ResponseFuture<Result<(), crate::error::Error>>;
+
+    fn handle(&mut self, _msg: Heartbeat, _ctx: &mut Self::Context) -> Self::Result {
+        match &self.recipients {
+            None => {
+                let name = self.route.service_name().clone();
+                Box::pin(async move { Err(ServiceError::NotRunning(name).into()) })
+            }
+            Some(recipients) => {
+                let tick = recipients.tick.send(Tick::new());
+                Box::pin(async move { tick.await.map_err(|e| e.into()) })
             }
         }
     }
@@ -159,13 +303,20 @@ impl Handler<LinkService> for ServiceCore {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ServiceStatistics {
     start_time: NaiveDateTime,
     messages_handled: u64,
     requests_handled: u64,
-    messages_per_second: u64,
-    requests_per_second: u64,
+    messages_per_second: f64,
+    requests_per_second: f64,
+    bytes_handled: u64,
+    errors: u64,
+    latency: Histogram,
+    operations: HashMap<String, Counters>,
+    targets: HashMap<String, Counters>,
+    message_rate: SlidingWindow,
+    request_rate: SlidingWindow,
 }
 
 impl ServiceStatistics {
@@ -174,8 +325,80 @@ impl ServiceStatistics {
             start_time: Utc::now().naive_local(),
             messages_handled: 0,
             requests_handled: 0,
-            messages_per_second: 0,
-            requests_per_second: 0,
+            messages_per_second: 0.0,
+            requests_per_second: 0.0,
+            bytes_handled: 0,
+            errors: 0,
+            latency: Histogram::new(),
+            operations: Default::default(),
+            targets: Default::default(),
+            message_rate: SlidingWindow::default(),
+            request_rate: SlidingWindow::default(),
+        }
+    }
+
+    /// Records a parcel delivered to the service, `latency` is the time the service took to accept it.
+    pub fn record_delivery(&mut self, target: &Target, messages: u64, bytes: u64, latency: Duration, ok: bool) {
+        self.messages_handled += messages;
+        self.message_rate.record(messages);
+        self.bytes_handled += bytes;
+        self.latency.record(latency);
+        if !ok {
+            self.errors += 1;
         }
+
+        if let Target::Route(route) = target {
+            self.requests_handled += 1;
+            self.request_rate.record(1);
+            if !route.operation_name().is_empty() {
+                self.operations.entry(route.operation_name().clone()).or_default().record(bytes, latency, ok);
+            }
+        }
+
+        self.targets.entry(target.as_string()).or_default().record(bytes, latency, ok);
+    }
+
+    /// Copy of the statistics with rates calculated for this moment.
+    pub fn snapshot(&mut self) -> ServiceStatistics {
+        self.messages_per_second = self.message_rate.rate();
+        self.requests_per_second = self.request_rate.rate();
+        self.clone()
+    }
+
+    pub fn start_time(&self) -> &NaiveDateTime {
+        &self.start_time
+    }
+    pub fn messages_handled(&self) -> u64 {
+        self.messages_handled
+    }
+    pub fn requests_handled(&self) -> u64 {
+        self.requests_handled
+    }
+    pub fn messages_per_second(&self) -> f64 {
+        self.messages_per_second
+    }
+    pub fn requests_per_second(&self) -> f64 {
+        self.requests_per_second
+    }
+    pub fn bytes_handled(&self) -> u64 {
+        self.bytes_handled
+    }
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+    pub fn operations(&self) -> &HashMap<String, Counters> {
+        &self.operations
+    }
+    pub fn targets(&self) -> &HashMap<String, Counters> {
+        &self.targets
+    }
+}
+
+impl Default for ServiceStatistics {
+    fn default() -> Self {
+        Self::new()
     }
 }
\ No newline at end of file
@@ -0,0 +1,236 @@
+//! Framing shared by the stream services. A length prefixed frame is a big endian `u32`
+//! length followed by that many bytes of message data, a delimited frame ends with the
+//! delimiter, e.g. a newline.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::config::{ConfigError, ServiceConfig};
+
+/// Connection a message came from, parcels for a connection carry it as well
+pub const CONNECTION_ID_HEADER: &str = "connection_id";
+pub const PEER_ADDRESS_HEADER: &str = "peer_address";
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Framing {
+    LengthPrefixed,
+    Delimited(Vec<u8>),
+}
+
+impl Framing {
+    /// From the `framing` parameter: "length_prefixed" (default), "newline" or "delimiter"
+    /// with the `delimiter` parameter, which understands `\r`, `\n`, `\t`, `\0` and `\\`.
+    pub fn from_config(config: &ServiceConfig) -> Result<Self, ConfigError> {
+        let framing: String = config.parse_parameter_or("framing", "length_prefixed".to_string())?;
+        let invalid = |parameter: &str, value: String, reason: &str| ConfigError::InvalidParameter {
+            service: config.name.clone(),
+            parameter: parameter.to_string(),
+            value,
+            reason: reason.to_string(),
+        };
+
+        match framing.as_str() {
+            "length_prefixed" => Ok(Framing::LengthPrefixed),
+            "newline" => Ok(Framing::Delimited(b"\n".to_vec())),
+            "delimiter" => {
+                let delimiter = config.parameter("delimiter")?;
+                match unescape(delimiter) {
+                    Some(bytes) if !bytes.is_empty() => Ok(Framing::Delimited(bytes)),
+                    _ => Err(invalid("delimiter", delimiter.clone(), "expected a non empty delimiter")),
+                }
+            }
+            _ => Err(invalid("framing", framing.clone(), "expected length_prefixed, newline or delimiter")),
+        }
+    }
+
+    /// Data containing the delimiter can`t be framed, the peer would read it as several frames
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>, FrameError> {
+        match self {
+            Framing::LengthPrefixed => {
+                let mut frame = Vec::with_capacity(4 + data.len());
+                frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+                frame.extend_from_slice(data);
+                Ok(frame)
+            }
+            Framing::Delimited(delimiter) => {
+                if data.windows(delimiter.len()).any(|window| window == delimiter.as_slice()) {
+                    return Err(FrameError::ContainsDelimiter { size: data.len() });
+                }
+                Ok([data, delimiter.as_slice()].concat())
+            }
+        }
+    }
+}
+
+/// Understands `\r`, `\n`, `\t`, `\0` and `\\`, `None` for any other escape
+pub(crate) fn unescape(value: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next()? {
+                'r' => '\r',
+                'n' => '\n',
+                't' => '\t',
+                '0' => '\0',
+                '\\' => '\\',
+                _ => return None,
+            },
+            c => c,
+        };
+        let mut buffer = [0u8; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+    }
+    Some(bytes)
+}
+
+/// Reads frames of a stream, keeping what is read past the end of a delimited frame
+pub struct FrameReader<R> {
+    reader: R,
+    framing: Framing,
+    max_frame_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R, framing: Framing, max_frame_size: usize) -> Self {
+        Self { reader, framing, max_frame_size, buffer: vec![] }
+    }
+
+    /// The next frame, `None` when the stream ends. Data after the last delimiter is
+    /// returned as a frame of its own.
+    pub async fn next_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let delimiter = match &self.framing {
+            Framing::LengthPrefixed => return read_length_prefixed(&mut self.reader, self.max_frame_size).await,
+            Framing::Delimited(delimiter) => delimiter.clone(),
+        };
+
+        let mut searched = 0;
+        loop {
+            if let Some(position) = self.buffer[searched..].windows(delimiter.len()).position(|window| window == delimiter.as_slice()) {
+                let end = searched + position;
+                let frame = self.buffer[..end].to_vec();
+                self.buffer.drain(..end + delimiter.len());
+                return Ok(Some(frame));
+            }
+            if self.buffer.len() > self.max_frame_size {
+                return Err(FrameError::TooLarge { size: self.buffer.len(), limit: self.max_frame_size });
+            }
+            searched = self.buffer.len().saturating_sub(delimiter.len() - 1);
+
+            let mut chunk = [0u8; 4096];
+            let read = self.reader.read(&mut chunk).await?;
+            if read == 0 {
+                return match self.buffer.is_empty() {
+                    true => Ok(None),
+                    false => Ok(Some(std::mem::take(&mut self.buffer))),
+                };
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(std::io::Error),
+    TooLarge { size: usize, limit: usize },
+    ContainsDelimiter { size: usize },
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FrameError::Io(e) => Some(e),
+            FrameError::TooLarge { .. } | FrameError::ContainsDelimiter { .. } => None,
+        }
+    }
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "{}", e),
+            FrameError::TooLarge { size, limit } => write!(f, "frame of {} bytes is larger than {} bytes", size, limit),
+            FrameError::ContainsDelimiter { size } => write!(f, "data of {} bytes contains the delimiter", size),
+        }
+    }
+}
+
+impl From<std::io::Error> for FrameError {
+    fn from(e: std::io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+/// Reads the next frame, `None` when the stream ends between frames
+pub async fn read_length_prefixed<R: AsyncRead + Unpin>(reader: &mut R, max_frame_size: usize) -> Result<Option<Vec<u8>>, FrameError> {
+    let length = match reader.read_u32().await {
+        Ok(length) => length as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if length > max_frame_size {
+        return Err(FrameError::TooLarge { size: length, limit: max_frame_size });
+    }
+
+    let mut frame = vec![0u8; length];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+pub async fn write_length_prefixed<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> Result<(), FrameError> {
+    writer.write_u32(frame.len() as u32).await?;
+    writer.write_all(frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::codec::{read_length_prefixed, write_length_prefixed, FrameError, FrameReader, Framing};
+    use crate::config::ServiceConfig;
+
+    #[actix_rt::test]
+    async fn test_length_prefixed() {
+        let mut buffer = Vec::new();
+        write_length_prefixed(&mut buffer, b"Action: Ping").await.unwrap();
+        write_length_prefixed(&mut buffer, b"").await.unwrap();
+        assert_eq!(&buffer[..4], &[0, 0, 0, 12]);
+
+        let mut reader: &[u8] = &buffer;
+        assert_eq!(read_length_prefixed(&mut reader, 1024).await.unwrap(), Some(b"Action: Ping".to_vec()));
+        assert_eq!(read_length_prefixed(&mut reader, 1024).await.unwrap(), Some(vec![]));
+        assert_eq!(read_length_prefixed(&mut reader, 1024).await.unwrap(), None);
+
+        let mut reader: &[u8] = &buffer;
+        assert!(matches!(read_length_prefixed(&mut reader, 4).await, Err(FrameError::TooLarge { size: 12, limit: 4 })));
+    }
+
+    #[actix_rt::test]
+    async fn test_delimited() {
+        let framing = Framing::Delimited(b"\r\n".to_vec());
+        assert_eq!(framing.encode(b"Action: Ping").unwrap(), b"Action: Ping\r\n".to_vec());
+        assert!(matches!(framing.encode(b"Action: Ping\r\n\r\n"), Err(FrameError::ContainsDelimiter { size: 16 })));
+
+        let data: &[u8] = b"first\r\nsecond\r\n\r\nrest";
+        let mut reader = FrameReader::new(data, framing, 16);
+        assert_eq!(reader.next_frame().await.unwrap(), Some(b"first".to_vec()));
+        assert_eq!(reader.next_frame().await.unwrap(), Some(b"second".to_vec()));
+        assert_eq!(reader.next_frame().await.unwrap(), Some(vec![]));
+        assert_eq!(reader.next_frame().await.unwrap(), Some(b"rest".to_vec()));
+        assert_eq!(reader.next_frame().await.unwrap(), None);
+
+        let mut reader = FrameReader::new(&[b'x'; 64][..], Framing::Delimited(b"\n".to_vec()), 16);
+        assert!(matches!(reader.next_frame().await, Err(FrameError::TooLarge { .. })));
+
+        let mut config = ServiceConfig { name: "client".to_string(), ..Default::default() };
+        config.parameters.insert("framing".to_string(), "delimiter".to_string());
+        config.parameters.insert("delimiter".to_string(), "\\r\\n".to_string());
+        assert_eq!(Framing::from_config(&config).unwrap(), Framing::Delimited(b"\r\n".to_vec()));
+        config.parameters.insert("framing".to_string(), "lines".to_string());
+        assert!(Framing::from_config(&config).is_err());
+    }
+}
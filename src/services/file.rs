@@ -0,0 +1,731 @@
+//! Files as a source or a sink of messages. Sources poll their files, so rotation and
+//! truncation are noticed on the next poll; read offsets are kept in a JSON file, so a
+//! restarted service continues where it stopped.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use actix::{Actor, Addr, ArbiterHandle, AsyncContext, Context, Handler};
+use chrono::Utc;
+use actix::{ActorFutureExt, fut::wrap_future};
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use crate::config::{ConfigError, ServiceConfig};
+use crate::plugin::builtin_service;
+use crate::message::{BaseMessage, Parcel};
+use crate::node::Node;
+use crate::route::{Route, RouteSheet, Target};
+use crate::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use crate::services::codec::unescape;
+use crate::signal::Tick;
+
+/// File a message was read from
+pub const FILE_PATH_HEADER: &str = "file_path";
+
+pub const DEFAULT_MAX_READ_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unit {
+    /// Records ending with the delimiter, a line is a record ending with "\n"
+    Record(Vec<u8>),
+    /// The whole file once its size stopped changing, directories only
+    File,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct StoredOffset {
+    identity: u64,
+    offset: u64,
+}
+
+/// Read offsets by path, saved after a poll that moved one
+#[derive(Debug, Default)]
+struct Offsets {
+    path: Option<PathBuf>,
+    offsets: HashMap<String, StoredOffset>,
+    changed: bool,
+}
+
+impl Offsets {
+    fn load(path: Option<PathBuf>) -> Self {
+        let offsets = path.as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| match serde_json::from_slice(&bytes) {
+                Ok(offsets) => Some(offsets),
+                Err(e) => {
+                    warn!("Ignoring invalid offsets file: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self { path, offsets, changed: false }
+    }
+
+    fn get(&self, path: &Path) -> Option<StoredOffset> {
+        self.offsets.get(&path.display().to_string()).copied()
+    }
+
+    fn set(&mut self, path: &Path, offset: StoredOffset) {
+        if self.offsets.insert(path.display().to_string(), offset) != Some(offset) {
+            self.changed = true;
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if self.offsets.remove(&path.display().to_string()).is_some() {
+            self.changed = true;
+        }
+    }
+
+    fn save(&mut self) -> std::io::Result<()> {
+        let path = match &self.path {
+            Some(path) if self.changed => path,
+            _ => return Ok(()),
+        };
+
+        // Written aside and renamed, a crash never leaves half an offsets file
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec(&self.offsets)?)?;
+        std::fs::rename(&temporary, path)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+/// Distinguishes a rotated file from the one that had its path before
+#[cfg(unix)]
+fn identity(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn identity(_metadata: &std::fs::Metadata) -> u64 {
+    0
+}
+
+struct TailedFile {
+    file: File,
+    identity: u64,
+    /// Position of the handle, `partial` is read but not emitted yet
+    position: u64,
+    partial: Vec<u8>,
+}
+
+impl TailedFile {
+    fn open(path: &Path, offset: u64) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let identity = identity(&file.metadata()?);
+        let position = file.seek(SeekFrom::Start(offset))?;
+        Ok(Self { file, identity, position, partial: vec![] })
+    }
+
+    fn stored(&self) -> StoredOffset {
+        StoredOffset { identity: self.identity, offset: self.position - self.partial.len() as u64 }
+    }
+
+    /// Reads up to `max_read_size` bytes of what was appended, returns the complete records
+    fn read_records(&mut self, delimiter: &[u8], max_read_size: u64) -> std::io::Result<Vec<Vec<u8>>> {
+        let read = (&mut self.file).take(max_read_size).read_to_end(&mut self.partial)?;
+        self.position += read as u64;
+
+        let mut records = vec![];
+        let mut start = 0;
+        while let Some(position) = self.partial[start..].windows(delimiter.len()).position(|window| window == delimiter) {
+            records.push(self.partial[start..start + position].to_vec());
+            start += position + delimiter.len();
+        }
+        self.partial.drain(..start);
+        Ok(records)
+    }
+}
+
+/// Reads records of one file (`tail`) or of the files in a directory (`directory`)
+pub struct FileSource {
+    path: PathBuf,
+    directory: bool,
+    pattern: Option<String>,
+    unit: Unit,
+    /// Where a file without a stored offset is read from, only for `tail`
+    from_end: bool,
+    /// Bytes read of a file per poll, larger whole files are skipped
+    max_read_size: u64,
+    files: HashMap<PathBuf, TailedFile>,
+    /// Sizes of whole files seen on the last poll, they are read once the size is stable
+    sizes: HashMap<PathBuf, u64>,
+    offsets: Offsets,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf, directory: bool, pattern: Option<String>, unit: Unit, from_end: bool, offsets_path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            directory,
+            pattern,
+            unit,
+            from_end,
+            max_read_size: DEFAULT_MAX_READ_SIZE,
+            files: HashMap::new(),
+            sizes: HashMap::new(),
+            offsets: Offsets::load(offsets_path),
+        }
+    }
+
+    pub fn with_max_read_size(mut self, max_read_size: u64) -> Self {
+        self.max_read_size = max_read_size;
+        self
+    }
+
+    /// Messages of what was written since the last poll
+    pub fn poll(&mut self) -> std::io::Result<Vec<BaseMessage>> {
+        let mut messages = vec![];
+        let paths = match self.directory {
+            true => self.list()?,
+            false => vec![self.path.clone()],
+        };
+
+        for path in &paths {
+            let read = match &self.unit {
+                Unit::Record(delimiter) => {
+                    let delimiter = delimiter.clone();
+                    self.poll_records(path, &delimiter)
+                }
+                Unit::File => self.poll_whole(path).map(|data| data.into_iter().collect()),
+            };
+            match read {
+                Ok(records) => {
+                    for record in records {
+                        messages.push(BaseMessage::new(record, None).with_header(FILE_PATH_HEADER, path.display().to_string()));
+                    }
+                }
+                // The records of the other files are kept, this one is tried again on the next poll
+                Err(e) if self.directory => warn!("Can`t read {}: {}", path.display(), e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Files removed from the directory
+        if self.directory {
+            self.files.retain(|path, _| paths.contains(path));
+            self.sizes.retain(|path, _| paths.contains(path));
+            let removed: Vec<PathBuf> = self.offsets.offsets.keys().map(PathBuf::from).filter(|path| !paths.contains(path)).collect();
+            for path in removed {
+                self.offsets.remove(&path);
+            }
+        }
+
+        self.offsets.save()?;
+        Ok(messages)
+    }
+
+    fn list(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            let offsets = self.offsets.path.as_ref().is_some_and(|offsets| offsets == &path || offsets.with_extension("tmp") == path);
+            if path.is_file() && !offsets && self.pattern.as_deref().is_none_or(|pattern| matches_pattern(pattern, name)) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn poll_records(&mut self, path: &Path, delimiter: &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => Some(metadata),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        if !self.files.contains_key(path) {
+            let metadata = match &metadata {
+                Some(metadata) => metadata,
+                None => return Ok(vec![]),
+            };
+            let offset = match self.offsets.get(path) {
+                Some(stored) if stored.identity == identity(metadata) && stored.offset <= metadata.len() => stored.offset,
+                // Rotated while the service was down
+                Some(_) => 0,
+                None if self.from_end && !self.directory => metadata.len(),
+                None => 0,
+            };
+            debug!("Reading {} from offset {}", path.display(), offset);
+            self.files.insert(path.to_path_buf(), TailedFile::open(path, offset)?);
+        }
+
+        let tailed = self.files.get_mut(path).expect("File is opened above");
+        let mut records = vec![];
+        match metadata {
+            Some(metadata) if identity(&metadata) != tailed.identity => {
+                records = tailed.read_records(delimiter, self.max_read_size)?;
+                // The rest of the old file is read on the next polls
+                if tailed.position < tailed.file.metadata()?.len() {
+                    return Ok(records);
+                }
+
+                info!("{} was rotated", path.display());
+                // The rest of the old file has no delimiter anymore
+                if !tailed.partial.is_empty() {
+                    records.push(std::mem::take(&mut tailed.partial));
+                }
+                *tailed = TailedFile::open(path, 0)?;
+            }
+            Some(metadata) if metadata.len() < tailed.position => {
+                info!("{} was truncated", path.display());
+                *tailed = TailedFile::open(path, 0)?;
+            }
+            _ => {}
+        }
+        let left = self.max_read_size.saturating_sub(records.iter().map(|record| record.len() as u64).sum());
+        records.extend(tailed.read_records(delimiter, left)?);
+        if tailed.partial.len() as u64 >= self.max_read_size {
+            warn!("Record in {} has more than {} bytes without a delimiter, emitting it as is", path.display(), self.max_read_size);
+            records.push(std::mem::take(&mut tailed.partial));
+        }
+
+        self.offsets.set(path, tailed.stored());
+        Ok(records)
+    }
+
+    fn poll_whole(&mut self, path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+        let metadata = std::fs::metadata(path)?;
+        let stored = StoredOffset { identity: identity(&metadata), offset: metadata.len() };
+        if self.offsets.get(path) == Some(stored) {
+            return Ok(None);
+        }
+
+        // Still being written
+        if self.sizes.insert(path.to_path_buf(), metadata.len()) != Some(metadata.len()) {
+            return Ok(None);
+        }
+
+        self.offsets.set(path, stored);
+        if metadata.len() > self.max_read_size {
+            warn!("Skipping {}, its {} bytes are more than {} bytes", path.display(), metadata.len(), self.max_read_size);
+            return Ok(None);
+        }
+        Ok(Some(std::fs::read(path)?))
+    }
+}
+
+/// `*` matches any part of the name, e.g. "*.log" or "access-*.csv"
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    if !name.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &name[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    for (index, part) in parts.iter().enumerate() {
+        if index == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+/// Appends records to a file, the file is renamed to `<path>.<timestamp>` once it is larger
+/// than `max_size` or older than `max_age`
+pub struct FileSink {
+    path: PathBuf,
+    delimiter: Vec<u8>,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    file: Option<File>,
+    size: u64,
+    opened: Instant,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf, delimiter: Vec<u8>, max_size: Option<u64>, max_age: Option<Duration>) -> Self {
+        Self { path, delimiter, max_size, max_age, file: None, size: 0, opened: Instant::now() }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.opened = Instant::now();
+            self.file = Some(file);
+        }
+
+        let file = self.file.as_mut().expect("File is opened above");
+        file.write_all(&[data, self.delimiter.as_slice()].concat())?;
+        self.size += (data.len() + self.delimiter.len()) as u64;
+
+        if self.max_size.is_some_and(|max_size| self.size >= max_size) {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Called on every poll, rotates by age
+    pub fn check_age(&mut self) -> std::io::Result<()> {
+        if self.file.is_some() && self.max_age.is_some_and(|max_age| self.opened.elapsed() >= max_age) {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.flush()?;
+        self.file = None;
+
+        let timestamp = Utc::now().format("%Y%m%d%H%M%S").to_string();
+        let mut rotated = PathBuf::from(format!("{}.{}", self.path.display(), timestamp));
+        let mut index = 1;
+        while rotated.exists() {
+            rotated = PathBuf::from(format!("{}.{}.{}", self.path.display(), timestamp, index));
+            index += 1;
+        }
+
+        debug!("Rotating {} to {}", self.path.display(), rotated.display());
+        std::fs::rename(&self.path, rotated)
+    }
+}
+
+enum FileMode {
+    /// Taken while a poll reads in a blocking task
+    Source(Option<FileSource>),
+    Sink(FileSink),
+}
+
+/// Parameters: `mode` ("tail", "directory" or "sink") and `path`.
+///
+/// Sources publish to `Consumer(message_type)` and take `unit` ("line", "record" with
+/// `delimiter`, or "file" for directories), `pattern` (e.g. "*.log"), `start_at` ("end" or
+/// "beginning" for a tailed file without offset), `offsets_file`, `poll_interval_in_millis` and
+/// `max_read_size` in bytes read of a file per poll.
+/// Sinks write the parcels they consume, one record per message ending with `delimiter`
+/// (a newline by default), and take `max_file_size` in bytes and `rotate_every_in_secs`.
+pub struct FileService {
+    name: String,
+    mode: FileMode,
+    target: Option<Target>,
+    poll_interval: Duration,
+    route: Route,
+    node: Option<Addr<Node>>,
+}
+
+impl FileService {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> {
+        let mode = config.parameter("mode")?.clone();
+        let path = PathBuf::from(config.parameter("path")?);
+        let poll_interval: u64 = config.parse_parameter_or("poll_interval_in_millis", 500)?;
+        let invalid = |parameter: &str, value: String, reason: &str| ConfigError::InvalidParameter {
+            service: config.name.clone(),
+            parameter: parameter.to_string(),
+            value,
+            reason: reason.to_string(),
+        };
+
+        let delimiter = match config.parameters.get("delimiter") {
+            Some(delimiter) => match unescape(delimiter) {
+                Some(bytes) if !bytes.is_empty() => bytes,
+                _ => return Err(invalid("delimiter", delimiter.clone(), "expected a non empty delimiter").into()),
+            },
+            None => b"\n".to_vec(),
+        };
+
+        let (mode, target) = match mode.as_str() {
+            "tail" | "directory" => {
+                let directory = mode == "directory";
+                let unit = match config.parse_parameter_or("unit", "line".to_string())?.as_str() {
+                    "line" => Unit::Record(b"\n".to_vec()),
+                    "record" => Unit::Record(delimiter),
+                    "file" if directory => Unit::File,
+                    unit => return Err(invalid("unit", unit.to_string(), "expected line, record or file (directories only)").into()),
+                };
+                let from_end = match config.parse_parameter_or("start_at", "end".to_string())?.as_str() {
+                    "end" => true,
+                    "beginning" => false,
+                    start_at => return Err(invalid("start_at", start_at.to_string(), "expected end or beginning").into()),
+                };
+                let offsets_path = config.parameters.get("offsets_file").map(PathBuf::from);
+                let pattern = config.parameters.get("pattern").cloned();
+                let target = Target::Consumer(config.parameter("message_type")?.clone());
+                let max_read_size = config.parse_parameter_or("max_read_size", DEFAULT_MAX_READ_SIZE)?;
+
+                let source = FileSource::new(path, directory, pattern, unit, from_end, offsets_path).with_max_read_size(max_read_size);
+                (FileMode::Source(Some(source)), Some(target))
+            }
+            "sink" => {
+                let max_size: u64 = config.parse_parameter_or("max_file_size", 0)?;
+                let max_age: u64 = config.parse_parameter_or("rotate_every_in_secs", 0)?;
+                let sink = FileSink::new(
+                    path,
+                    delimiter,
+                    Some(max_size).filter(|size| *size > 0),
+                    Some(Duration::from_secs(max_age)).filter(|age| !age.is_zero()),
+                );
+                (FileMode::Sink(sink), None)
+            }
+            mode => return Err(invalid("mode", mode.to_string(), "expected tail, directory or sink").into()),
+        };
+
+        Ok(Box::new(FileService {
+            name: config.name.clone(),
+            mode,
+            target,
+            poll_interval: Duration::from_millis(poll_interval),
+            route: Route::new(),
+            node: None,
+        }))
+    }
+
+    fn poll(&mut self, ctx: &mut Context<Self>) {
+        match &mut self.mode {
+            FileMode::Source(source) => {
+                // The previous poll is still reading
+                let mut source = match source.take() {
+                    Some(source) => source,
+                    None => return,
+                };
+
+                let read = tokio::task::spawn_blocking(move || {
+                    let result = source.poll();
+                    (source, result)
+                });
+                ctx.spawn(wrap_future(read).map(|joined, this: &mut Self, _ctx| {
+                    let (source, result) = match joined {
+                        Ok(joined) => joined,
+                        Err(e) => {
+                            error!("Service {} lost its file reader: {}", this.name, e);
+                            return;
+                        }
+                    };
+                    this.mode = FileMode::Source(Some(source));
+                    this.publish(result);
+                }));
+            }
+            FileMode::Sink(sink) => {
+                if let Err(e) = sink.flush().and_then(|()| sink.check_age()) {
+                    error!("Service {} can`t rotate its file: {}", self.name, e);
+                }
+            }
+        }
+    }
+
+    fn publish(&self, result: std::io::Result<Vec<BaseMessage>>) {
+        let messages = match result {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("Service {} can`t read files: {}", self.name, e);
+                return;
+            }
+        };
+        if messages.is_empty() {
+            return;
+        }
+
+        trace!("Service {} read {} messages", self.name, messages.len());
+        if let (Some(node), Some(target)) = (&self.node, &self.target) {
+            let route_sheet = RouteSheet::new(target.clone(), self.route.clone());
+            node.do_send(Parcel::new(messages, route_sheet));
+        }
+    }
+}
+
+builtin_service!(file_service => FileService::on_start);
+
+impl Actor for FileService {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.poll(ctx);
+        ctx.run_interval(self.poll_interval, |this, ctx| this.poll(ctx));
+    }
+}
+
+impl Service for FileService {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Writing message to file {:?}", message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl Handler<Parcel> for FileService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        for message in msg.unpack() {
+            self.handle_message(message);
+        }
+
+        let sink = match &mut self.mode {
+            FileMode::Sink(sink) => sink,
+            FileMode::Source(_) => {
+                warn!("Service {} reads files, dropping parcel to {}", self.name, msg.target().as_string());
+                return;
+            }
+        };
+
+        for message in msg.unpack() {
+            if let Err(e) = sink.write(message.data()) {
+                error!("Service {} can`t write to its file: {}", self.name, e);
+            }
+        }
+    }
+}
+
+impl Handler<Tick> for FileService {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ServiceConfig;
+    use crate::services::file::{matches_pattern, FileService, FileSink, FileSource, Unit, FILE_PATH_HEADER};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("any_message_file_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn append(path: &PathBuf, data: &str) {
+        std::fs::OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(data.as_bytes()).unwrap();
+    }
+
+    fn lines(source: &mut FileSource) -> Vec<String> {
+        source.poll().unwrap().iter().map(|message| message.text().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_tail() {
+        let directory = directory("tail");
+        let log = directory.join("app.log");
+        let offsets = directory.join("offsets.json");
+        append(&log, "old\n");
+
+        let tail = || FileSource::new(log.clone(), false, None, Unit::Record(b"\n".to_vec()), true, Some(offsets.clone()));
+        let mut source = tail();
+        assert!(lines(&mut source).is_empty());
+
+        append(&log, "first\nsec");
+        assert_eq!(lines(&mut source), vec!["first"]);
+        append(&log, "ond\n");
+        assert_eq!(lines(&mut source), vec!["second"]);
+
+        // A restarted source continues at the stored offset
+        append(&log, "third\n");
+        let mut source = tail();
+        assert_eq!(lines(&mut source), vec!["third"]);
+
+        // The rest of the rotated file is read before the new file
+        append(&log, "fourth\nlast");
+        std::fs::rename(&log, directory.join("app.log.1")).unwrap();
+        append(&log, "new\n");
+        assert_eq!(lines(&mut source), vec!["fourth", "last", "new"]);
+
+        std::fs::write(&log, "ok\n").unwrap();
+        assert_eq!(lines(&mut source), vec!["ok"]);
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_read_limit() {
+        let directory = directory("limit");
+        let log = directory.join("app.log");
+        append(&log, "first\nsecond\nthird\n");
+
+        // A poll reads up to 8 bytes, the rest on the next polls
+        let mut source = FileSource::new(log.clone(), false, None, Unit::Record(b"\n".to_vec()), false, None).with_max_read_size(8);
+        assert_eq!(lines(&mut source), vec!["first"]);
+        assert_eq!(lines(&mut source), vec!["second"]);
+        assert_eq!(lines(&mut source), vec!["third"]);
+
+        // A record without a delimiter is cut at the limit
+        append(&log, "0123456789");
+        assert_eq!(lines(&mut source), vec!["01234567"]);
+        append(&log, "\n");
+        assert_eq!(lines(&mut source), vec!["89"]);
+
+        // A record without a delimiter is cut at the limit
+        append(&log, "0123456789");
+        assert_eq!(lines(&mut source), vec!["01234567"]);
+        append(&log, "\n");
+        assert_eq!(lines(&mut source), vec!["89"]);
+
+        let mut config = ServiceConfig { name: "file".to_string(), ..Default::default() };
+        config.parameters.insert("mode".to_string(), "sink".to_string());
+        config.parameters.insert("path".to_string(), log.display().to_string());
+        config.parameters.insert("delimiter".to_string(), "\\r\\n".to_string());
+        assert!(FileService::on_start(config.clone()).is_ok());
+        config.parameters.insert("delimiter".to_string(), "\\x".to_string());
+        assert!(FileService::on_start(config).is_err());
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_directory() {
+        let directory = directory("directory");
+        std::fs::write(directory.join("a.csv"), "1,2").unwrap();
+        std::fs::write(directory.join("b.tmp"), "skipped").unwrap();
+
+        let mut source = FileSource::new(directory.clone(), true, Some("*.csv".to_string()), Unit::File, false, None);
+        // Files are read once their size didn`t change between two polls
+        assert!(source.poll().unwrap().is_empty());
+        let messages = source.poll().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data(), b"1,2");
+        assert_eq!(messages[0].header(FILE_PATH_HEADER), Some(&directory.join("a.csv").display().to_string()));
+        assert!(source.poll().unwrap().is_empty());
+
+        assert!(matches_pattern("access-*.log", "access-2021.log"));
+        assert!(!matches_pattern("*.log", "app.log.1"));
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_sink_rotation() {
+        let directory = directory("sink");
+        let path = directory.join("out.log");
+        let mut sink = FileSink::new(path.clone(), b"\n".to_vec(), Some(10), None);
+
+        sink.write(b"12345").unwrap();
+        sink.write(b"67890").unwrap();
+        sink.write(b"next").unwrap();
+        sink.flush().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "next\n");
+        let rotated: Vec<PathBuf> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path())
+            .filter(|rotated| rotated != &path)
+            .collect();
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(std::fs::read_to_string(&rotated[0]).unwrap(), "12345\n67890\n");
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
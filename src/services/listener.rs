@@ -0,0 +1,104 @@
+//! What the listening services share. The socket is bound while the service is created, so a
+//! taken address fails the start of the service, and accepted on its arbiter once it runs.
+
+use std::time::Duration;
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use actix::fut::wrap_future;
+use actix_rt::net::{TcpListener, TcpStream};
+use futures_util::future::LocalBoxFuture;
+use log::{error, info, warn};
+use tokio::io::{AsyncRead, AsyncWrite};
+use crate::backoff::Backoff;
+use crate::health::Readiness;
+use crate::node::Node;
+use crate::service::ServiceError;
+use crate::signal::ReportReadiness;
+
+/// Delays between accepts that fail, e.g. while the process is out of file descriptors
+const MIN_ACCEPT_DELAY: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_DELAY: Duration = Duration::from_secs(1);
+
+pub fn bind_tcp(service: &str, address: &str) -> Result<std::net::TcpListener, ServiceError> {
+    std::net::TcpListener::bind(address)
+        .and_then(|listener| listener.set_nonblocking(true).map(|()| listener))
+        .map_err(|e| ServiceError::Connection { service: service.to_string(), reason: format!("can`t listen on {}: {}", address, e) })
+}
+
+pub fn bind_udp(service: &str, address: &str) -> Result<std::net::UdpSocket, ServiceError> {
+    std::net::UdpSocket::bind(address)
+        .and_then(|socket| socket.set_nonblocking(true).map(|()| socket))
+        .map_err(|e| ServiceError::Connection { service: service.to_string(), reason: format!("can`t listen on {}: {}", address, e) })
+}
+
+pub fn report_readiness(node: Option<&Addr<Node>>, service: &str, readiness: Readiness) {
+    if let Some(node) = node {
+        node.do_send(ReportReadiness { service: service.to_string(), readiness });
+    }
+}
+
+/// A socket that connections are accepted on
+pub trait Listener: 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    type Peer: Send + 'static;
+
+    fn accept(&self) -> LocalBoxFuture<'_, std::io::Result<(Self::Stream, Self::Peer)>>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+    type Peer = std::net::SocketAddr;
+
+    fn accept(&self) -> LocalBoxFuture<'_, std::io::Result<(Self::Stream, Self::Peer)>> {
+        Box::pin(TcpListener::accept(self))
+    }
+}
+
+#[cfg(unix)]
+impl Listener for actix_rt::net::UnixListener {
+    type Stream = actix_rt::net::UnixStream;
+    type Peer = tokio::net::unix::SocketAddr;
+
+    fn accept(&self) -> LocalBoxFuture<'_, std::io::Result<(Self::Stream, Self::Peer)>> {
+        Box::pin(actix_rt::net::UnixListener::accept(self))
+    }
+}
+
+/// Reports the service ready and sends it what `connected` makes of every accepted connection,
+/// until the actor stops. `listener` is the result of registering the bound socket.
+pub(crate) fn listen<A, L, M, F>(ctx: &mut Context<A>, service: &str, address: &str, node: Option<&Addr<Node>>,
+                                 listener: std::io::Result<L>, connected: F)
+    where A: Actor<Context = Context<A>> + Handler<M>,
+          L: Listener,
+          M: Message<Result = ()> + Send + 'static,
+          F: Fn(L::Stream, L::Peer) -> M + 'static,
+{
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Service {} can`t listen on {}: {}", service, address, e);
+            report_readiness(node, service, Readiness::not_ready(e.to_string()));
+            return;
+        }
+    };
+    info!("Service {} listens on {}", service, address);
+    report_readiness(node, service, Readiness::ready());
+
+    let addr = ctx.address();
+    let service = service.to_string();
+    ctx.spawn(wrap_future(async move {
+        let mut backoff = Backoff::new(MIN_ACCEPT_DELAY, MAX_ACCEPT_DELAY);
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    backoff.reset();
+                    addr.do_send(connected(stream, peer));
+                }
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    warn!("Service {} can`t accept a connection, retrying in {:?}: {}", service, delay, e);
+                    actix_rt::time::sleep(delay).await;
+                }
+            }
+        }
+    }));
+}
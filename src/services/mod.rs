@@ -1,2 +1,15 @@
-mod tcp;
-mod file;
\ No newline at end of file
+pub mod codec;
+pub mod listener;
+pub mod stream;
+pub mod tcp;
+pub mod stream_client;
+pub mod tcp_client;
+pub mod file;
+pub mod webhook;
+pub mod websocket;
+pub mod syslog;
+pub mod udp;
+#[cfg(unix)]
+pub mod unix;
+#[cfg(unix)]
+pub mod unix_client;
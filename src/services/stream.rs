@@ -0,0 +1,294 @@
+//! The listening side of the stream services. An endpoint binds the socket and tells what
+//! is known of a peer, the service frames and routes the connections the same way for every
+//! kind of stream.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+use actix::{Actor, Addr, ArbiterHandle, AsyncContext, Context, Handler, Message, SpawnHandle};
+use actix::fut::wrap_future;
+use log::{debug, trace, warn};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use crate::config::ServiceConfig;
+use crate::message::{BaseMessage, Parcel};
+use crate::node::Node;
+use crate::route::{Route, RouteSheet, Target};
+use crate::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use crate::services::codec::{FrameReader, Framing, CONNECTION_ID_HEADER, DEFAULT_MAX_FRAME_SIZE};
+use crate::services::listener::{listen, Listener};
+use crate::signal::Tick;
+
+/// A connection that can`t take a frame for this long is closed
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The socket a `StreamService` listens on
+pub trait Endpoint: Sized + Unpin + Send + 'static {
+    type Listener: Listener;
+
+    /// Binds the socket, so a taken address fails the start of the service
+    fn bind(config: &ServiceConfig) -> Result<Self, Box<dyn Error>>;
+
+    fn address(&self) -> String;
+
+    /// Registers the bound socket with the runtime of the service, once
+    fn listener(&mut self) -> Option<std::io::Result<Self::Listener>>;
+
+    /// The headers of the messages of a connection, or why the connection is refused
+    fn peer(&self, stream: &<Self::Listener as Listener>::Stream, peer: &<Self::Listener as Listener>::Peer)
+        -> Result<Peer, String>;
+
+    /// Cleans up after the service stopped
+    fn close(&mut self, _service: &str) {}
+}
+
+/// A peer as an endpoint sees it
+pub struct Peer {
+    /// For the log
+    pub name: String,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+struct Connected<L: Listener> {
+    stream: L::Stream,
+    peer: L::Peer,
+}
+
+impl<L: Listener> Message for Connected<L> {
+    type Result = ();
+}
+
+struct Received {
+    connection_id: String,
+    data: Vec<u8>,
+}
+
+impl Message for Received {
+    type Result = ();
+}
+
+struct Disconnected {
+    connection_id: String,
+    reason: String,
+}
+
+impl Message for Disconnected {
+    type Result = ();
+}
+
+struct Connection {
+    peer: Peer,
+    writer: Sender<Vec<u8>>,
+    /// The reader and the writer, cancelled to close the connection
+    tasks: Vec<SpawnHandle>,
+}
+
+/// Listens on its endpoint and publishes every frame of its connections to `target`, with the
+/// `connection_id` header and the headers the endpoint has for the peer. Parcels delivered to
+/// the service are written to the connection of their `connection_id` header, a connection
+/// that has `max_queued_frames` frames waiting to be written is closed.
+///
+/// Parameters besides those of the endpoint: `target` (e.g. "Consumer(TcpFrame)" or
+/// "/Originate"), `framing` and `delimiter` (see `Framing::from_config`), `max_frame_size`,
+/// `max_queued_frames` and `max_connections`.
+pub struct StreamService<E: Endpoint> {
+    name: String,
+    endpoint: E,
+    target: Target,
+    framing: Framing,
+    max_frame_size: usize,
+    max_queued_frames: usize,
+    max_connections: usize,
+    connections: HashMap<String, Connection>,
+    next_connection_id: u64,
+    route: Route,
+    node: Option<Addr<Node>>,
+}
+
+impl<E: Endpoint> StreamService<E> {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> {
+        Ok(Box::new(StreamService {
+            name: config.name.clone(),
+            endpoint: E::bind(&config)?,
+            target: config.parse_parameter("target")?,
+            framing: Framing::from_config(&config)?,
+            max_frame_size: config.parse_parameter_or("max_frame_size", DEFAULT_MAX_FRAME_SIZE)?,
+            max_queued_frames: config.parse_parameter_or("max_queued_frames", 1024)?,
+            max_connections: config.parse_parameter_or("max_connections", 1024)?,
+            connections: HashMap::new(),
+            next_connection_id: 0,
+            route: Route::new(),
+            node: None,
+        }))
+    }
+
+    fn close(&mut self, connection_id: &str, ctx: &mut Context<Self>) {
+        if let Some(connection) = self.connections.remove(connection_id) {
+            for task in connection.tasks {
+                ctx.cancel_future(task);
+            }
+        }
+    }
+}
+
+async fn write_frames<W: AsyncWrite + Unpin>(mut writer: W, mut receiver: Receiver<Vec<u8>>) {
+    while let Some(frame) = receiver.recv().await {
+        match actix_rt::time::timeout(WRITE_TIMEOUT, writer.write_all(&frame)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                debug!("Can`t write to connection {}", e);
+                break;
+            }
+            Err(_elapsed) => {
+                debug!("Writing to connection timed out");
+                break;
+            }
+        }
+    }
+}
+
+impl<E: Endpoint> Actor for StreamService<E> {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(listener) = self.endpoint.listener() {
+            let address = self.endpoint.address();
+            listen(ctx, &self.name, &address, self.node.as_ref(), listener, |stream, peer| Connected::<E::Listener> { stream, peer });
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.endpoint.close(&self.name);
+    }
+}
+
+impl<E: Endpoint> Service for StreamService<E> {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Writing message to connection {:?}", message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl<E: Endpoint> Handler<Connected<E::Listener>> for StreamService<E> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Connected<E::Listener>, ctx: &mut Self::Context) -> Self::Result {
+        let peer = match self.endpoint.peer(&msg.stream, &msg.peer) {
+            Ok(peer) => peer,
+            Err(reason) => {
+                warn!("Service {} refuses a connection: {}", self.name, reason);
+                return;
+            }
+        };
+        if self.connections.len() >= self.max_connections {
+            warn!("Service {} has {} connections, closing the one from {}", self.name, self.connections.len(), peer.name);
+            return;
+        }
+
+        self.next_connection_id += 1;
+        let connection_id = self.next_connection_id.to_string();
+        debug!("Service {} accepted connection {} from {}", self.name, connection_id, peer.name);
+
+        let (reader, writer) = tokio::io::split(msg.stream);
+        let (sender, receiver) = channel(self.max_queued_frames.max(1));
+        let writing = ctx.spawn(wrap_future(write_frames(writer, receiver)));
+
+        let addr = ctx.address();
+        let mut frames = FrameReader::new(reader, self.framing.clone(), self.max_frame_size);
+        let id = connection_id.clone();
+        let reading = ctx.spawn(wrap_future(async move {
+            let reason = loop {
+                match frames.next_frame().await {
+                    Ok(Some(data)) => addr.do_send(Received { connection_id: id.clone(), data }),
+                    Ok(None) => break "closed by peer".to_string(),
+                    Err(e) => break e.to_string(),
+                }
+            };
+            addr.do_send(Disconnected { connection_id: id, reason });
+        }));
+        self.connections.insert(connection_id, Connection { peer, writer: sender, tasks: vec![writing, reading] });
+    }
+}
+
+impl<E: Endpoint> Handler<Received> for StreamService<E> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Received, _ctx: &mut Self::Context) -> Self::Result {
+        let connection = match self.connections.get(&msg.connection_id) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        if let Some(node) = &self.node {
+            let mut message = BaseMessage::new(msg.data, None).with_header(CONNECTION_ID_HEADER, msg.connection_id);
+            for (header, value) in &connection.peer.headers {
+                message.headers_mut().insert(header.to_string(), value.clone());
+            }
+            let route_sheet = RouteSheet::new(self.target.clone(), self.route.clone());
+            node.do_send(Parcel::new(vec![message], route_sheet));
+        }
+    }
+}
+
+impl<E: Endpoint> Handler<Disconnected> for StreamService<E> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnected, _ctx: &mut Self::Context) -> Self::Result {
+        // Dropping the sender ends the writer of the connection
+        if let Some(connection) = self.connections.remove(&msg.connection_id) {
+            debug!("Connection {} from {} of service {} closed: {}", msg.connection_id, connection.peer.name, self.name, msg.reason);
+        }
+    }
+}
+
+impl<E: Endpoint> Handler<Parcel> for StreamService<E> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, ctx: &mut Self::Context) -> Self::Result {
+        for message in msg.unpack() {
+            self.handle_message(message);
+
+            let (connection_id, connection) = match message.header(CONNECTION_ID_HEADER) {
+                Some(connection_id) => match self.connections.get(connection_id) {
+                    Some(connection) => (connection_id, connection),
+                    None => {
+                        warn!("Service {} has no connection {}, dropping message", self.name, connection_id);
+                        continue;
+                    }
+                },
+                None => {
+                    warn!("Message to service {} has no {} header, dropping it", self.name, CONNECTION_ID_HEADER);
+                    continue;
+                }
+            };
+
+            let frame = match self.framing.encode(message.data()) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    warn!("Service {} can`t write message: {}", self.name, e);
+                    continue;
+                }
+            };
+            if let Err(TrySendError::Full(_frame)) = connection.writer.try_send(frame) {
+                warn!("Connection {} from {} has {} frames waiting, closing it", connection_id, connection.peer.name, self.max_queued_frames);
+                let connection_id = connection_id.clone();
+                self.close(&connection_id, ctx);
+            }
+        }
+    }
+}
+
+impl<E: Endpoint> Handler<Tick> for StreamService<E> {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
@@ -0,0 +1,295 @@
+//! The client side of the stream services. A connector opens the connection, the service
+//! frames, buffers and reconnects the same way for every kind of stream.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::time::Duration;
+use actix::{Actor, ActorFutureExt, Addr, ArbiterHandle, AsyncContext, Context, Handler, Message};
+use actix::fut::wrap_future;
+use futures_util::future::LocalBoxFuture;
+use log::{debug, info, trace, warn};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use crate::backoff::Backoff;
+use crate::config::ServiceConfig;
+use crate::health::Readiness;
+use crate::message::{BaseMessage, Parcel};
+use crate::node::Node;
+use crate::route::{Route, RouteSheet, Target};
+use crate::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use crate::services::codec::{FrameReader, Framing, DEFAULT_MAX_FRAME_SIZE, PEER_ADDRESS_HEADER};
+use crate::signal::{ReportReadiness, Tick};
+
+/// Opens the connections of a `StreamClientService`
+pub trait Connector: Sized + Unpin + Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + 'static;
+
+    fn from_config(config: &ServiceConfig) -> Result<Self, Box<dyn Error>>;
+
+    fn connect(&self) -> LocalBoxFuture<'static, std::io::Result<Self::Stream>>;
+
+    /// The other end, for the log and the `peer_address` header
+    fn peer(&self) -> String;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClientState {
+    Connecting,
+    Connected,
+    Backoff,
+}
+
+struct Received {
+    generation: u64,
+    data: Vec<u8>,
+}
+
+impl Message for Received {
+    type Result = ();
+}
+
+struct Disconnected {
+    generation: u64,
+    reason: String,
+    /// Frames the writer took from its channel but could not write
+    unwritten: Vec<Vec<u8>>,
+}
+
+impl Message for Disconnected {
+    type Result = ();
+}
+
+/// Connects to the peer of its connector, writes the data of every parcel it gets as a frame
+/// and publishes the frames it reads to `target`. While the connection is down frames are
+/// buffered and the service reconnects with backoff.
+///
+/// Parameters besides those of the connector: `target`, `framing` and `delimiter` (see
+/// `Framing::from_config`), `connect_timeout_in_millis`, `reconnect_delay_in_millis`,
+/// `max_reconnect_delay_in_millis`, `max_frame_size` and `buffer_size`.
+pub struct StreamClientService<C: Connector> {
+    name: String,
+    connector: C,
+    target: Target,
+    framing: Framing,
+    connect_timeout: Duration,
+    max_frame_size: usize,
+    buffer_size: usize,
+    state: ClientState,
+    writer: Option<UnboundedSender<Vec<u8>>>,
+    /// Events of connections that were already replaced are ignored
+    generation: u64,
+    pending: VecDeque<Vec<u8>>,
+    backoff: Backoff,
+    route: Route,
+    node: Option<Addr<Node>>,
+}
+
+impl<C: Connector> StreamClientService<C> {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> {
+        let connect_timeout: u64 = config.parse_parameter_or("connect_timeout_in_millis", 5000)?;
+        let reconnect_delay: u64 = config.parse_parameter_or("reconnect_delay_in_millis", 100)?;
+        let max_reconnect_delay: u64 = config.parse_parameter_or("max_reconnect_delay_in_millis", 30_000)?;
+
+        Ok(Box::new(StreamClientService {
+            name: config.name.clone(),
+            connector: C::from_config(&config)?,
+            target: config.parse_parameter("target")?,
+            framing: Framing::from_config(&config)?,
+            connect_timeout: Duration::from_millis(connect_timeout),
+            max_frame_size: config.parse_parameter_or("max_frame_size", DEFAULT_MAX_FRAME_SIZE)?,
+            buffer_size: config.parse_parameter_or("buffer_size", 1000)?,
+            state: ClientState::Connecting,
+            writer: None,
+            generation: 0,
+            pending: VecDeque::new(),
+            backoff: Backoff::new(Duration::from_millis(reconnect_delay), Duration::from_millis(max_reconnect_delay)),
+            route: Route::new(),
+            node: None,
+        }))
+    }
+
+    fn connect(&mut self, ctx: &mut Context<Self>) {
+        self.generation += 1;
+        self.state = ClientState::Connecting;
+        let generation = self.generation;
+        debug!("Service {} connecting to {}", self.name, self.connector.peer());
+
+        let connect = actix_rt::time::timeout(self.connect_timeout, self.connector.connect());
+        ctx.spawn(wrap_future(connect).map(move |result, this: &mut Self, ctx| {
+            match result {
+                Ok(Ok(stream)) => this.connected(stream, ctx),
+                Ok(Err(e)) => this.disconnected(generation, e.to_string(), ctx),
+                Err(_elapsed) => this.disconnected(generation, "connect timed out".to_string(), ctx),
+            }
+        }));
+    }
+
+    fn connected(&mut self, stream: C::Stream, ctx: &mut Context<Self>) {
+        let generation = self.generation;
+        info!("Service {} connected to {}", self.name, self.connector.peer());
+
+        let (reader, writer) = tokio::io::split(stream);
+        let (sender, receiver) = unbounded_channel();
+        for frame in self.pending.drain(..) {
+            let _ = sender.send(frame);
+        }
+        self.writer = Some(sender);
+        self.state = ClientState::Connected;
+        self.backoff.reset();
+        self.report_readiness(Readiness::ready());
+
+        let addr = ctx.address();
+        ctx.spawn(wrap_future(write_frames(writer, receiver, addr.clone(), generation)));
+
+        let mut frames = FrameReader::new(reader, self.framing.clone(), self.max_frame_size);
+        ctx.spawn(wrap_future(async move {
+            let reason = loop {
+                match frames.next_frame().await {
+                    Ok(Some(data)) => addr.do_send(Received { generation, data }),
+                    Ok(None) => break "closed by peer".to_string(),
+                    Err(e) => break e.to_string(),
+                }
+            };
+            addr.do_send(Disconnected { generation, reason, unwritten: vec![] });
+        }));
+    }
+
+    fn disconnected(&mut self, generation: u64, reason: String, ctx: &mut Context<Self>) {
+        if generation != self.generation || self.state == ClientState::Backoff {
+            return;
+        }
+
+        // Dropping the sender ends the writer
+        self.writer = None;
+        self.state = ClientState::Backoff;
+        self.report_readiness(Readiness::not_ready(format!("disconnected from {}: {}", self.connector.peer(), reason)));
+
+        let delay = self.backoff.next_delay();
+        warn!("Service {} lost {} ({}), reconnect {} in {:?}", self.name, self.connector.peer(), reason, self.backoff.attempts(), delay);
+        ctx.run_later(delay, |this, ctx| this.connect(ctx));
+    }
+
+    fn report_readiness(&self, readiness: Readiness) {
+        if let Some(node) = &self.node {
+            node.do_send(ReportReadiness { service: self.name.clone(), readiness });
+        }
+    }
+
+    fn send(&mut self, frame: Vec<u8>) {
+        let frame = match &self.writer {
+            Some(writer) => match writer.send(frame) {
+                Ok(()) => return,
+                Err(e) => e.0,
+            },
+            None => frame,
+        };
+
+        if self.pending.len() >= self.buffer_size {
+            warn!("Buffer of service {} is full, dropping the oldest frame", self.name);
+            self.pending.pop_front();
+        }
+        self.pending.push_back(frame);
+    }
+
+    /// Puts frames a failed writer hands back before the frames buffered since
+    fn requeue(&mut self, frames: Vec<Vec<u8>>) {
+        if self.writer.is_some() {
+            for frame in frames {
+                self.send(frame);
+            }
+            return;
+        }
+
+        for frame in frames.into_iter().rev() {
+            self.pending.push_front(frame);
+        }
+        if self.pending.len() > self.buffer_size {
+            warn!("Buffer of service {} is full, dropping the oldest frames", self.name);
+            let excess = self.pending.len() - self.buffer_size;
+            self.pending.drain(..excess);
+        }
+    }
+}
+
+async fn write_frames<W: AsyncWrite + Unpin, C: Connector>(mut writer: W, mut receiver: UnboundedReceiver<Vec<u8>>,
+                                                           addr: Addr<StreamClientService<C>>, generation: u64) {
+    while let Some(frame) = receiver.recv().await {
+        if let Err(e) = writer.write_all(&frame).await {
+            let mut unwritten = vec![frame];
+            receiver.close();
+            while let Ok(frame) = receiver.try_recv() {
+                unwritten.push(frame);
+            }
+            addr.do_send(Disconnected { generation, reason: e.to_string(), unwritten });
+            break;
+        }
+    }
+}
+
+impl<C: Connector> Actor for StreamClientService<C> {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.connect(ctx);
+    }
+}
+
+impl<C: Connector> Service for StreamClientService<C> {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Writing message to {} {:?}", self.connector.peer(), message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl<C: Connector> Handler<Parcel> for StreamClientService<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        for message in msg.unpack() {
+            self.handle_message(message);
+            match self.framing.encode(message.data()) {
+                Ok(frame) => self.send(frame),
+                Err(e) => warn!("Service {} can`t write message: {}", self.name, e),
+            }
+        }
+    }
+}
+
+impl<C: Connector> Handler<Received> for StreamClientService<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Received, _ctx: &mut Self::Context) -> Self::Result {
+        if msg.generation != self.generation {
+            return;
+        }
+
+        if let Some(node) = &self.node {
+            let message = BaseMessage::new(msg.data, None).with_header(PEER_ADDRESS_HEADER, self.connector.peer());
+            let route_sheet = RouteSheet::new(self.target.clone(), self.route.clone());
+            node.do_send(Parcel::new(vec![message], route_sheet));
+        }
+    }
+}
+
+impl<C: Connector> Handler<Disconnected> for StreamClientService<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnected, ctx: &mut Self::Context) -> Self::Result {
+        self.disconnected(msg.generation, msg.reason, ctx);
+        self.requeue(msg.unwritten);
+    }
+}
+
+impl<C: Connector> Handler<Tick> for StreamClientService<C> {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
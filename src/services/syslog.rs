@@ -0,0 +1,208 @@
+//! Syslog records as sent over UDP, RFC 5424 (`<165>1 2003-10-11T22:14:15.003Z host app
+//! 1234 ID47 [id a="1"] text`) and the older BSD format of RFC 3164 (`<34>Oct 11 22:14:15 host
+//! su[21]: text`). Anything else is a record of facility user with severity notice, as RFC
+//! 3164 asks for.
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp",
+    "ntp", "security", "console", "solaris-cron", "local0", "local1", "local2", "local3", "local4", "local5",
+    "local6", "local7",
+];
+
+const SEVERITIES: [&str; 8] = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+/// Priority of records without one, user.notice
+const DEFAULT_PRIORITY: u8 = 13;
+
+pub fn facility_name(facility: u8) -> Option<&'static str> {
+    FACILITIES.get(facility as usize).copied()
+}
+
+/// A facility by name, e.g. "local0", or by number
+pub fn parse_facility(facility: &str) -> Option<u8> {
+    let facility = facility.trim();
+    match FACILITIES.iter().position(|name| name.eq_ignore_ascii_case(facility)) {
+        Some(code) => Some(code as u8),
+        None => facility.parse().ok().filter(|&code| (code as usize) < FACILITIES.len()),
+    }
+}
+
+pub fn severity_name(severity: u8) -> Option<&'static str> {
+    SEVERITIES.get(severity as usize).copied()
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SyslogRecord {
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: Option<String>,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+    pub proc_id: Option<String>,
+    pub msg_id: Option<String>,
+    /// The structured data elements as sent, e.g. `[id a="1"][other]`
+    pub structured_data: Option<String>,
+    pub message: String,
+}
+
+impl SyslogRecord {
+    pub fn parse(datagram: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(datagram);
+        let text = text.trim_end_matches(['\r', '\n', '\0']);
+
+        let (priority, rest) = match parse_priority(text) {
+            Some((priority, rest)) => (priority, rest),
+            None => (DEFAULT_PRIORITY, text),
+        };
+        let mut record = match rest.strip_prefix("1 ") {
+            Some(rest) => parse_5424(rest),
+            None => parse_3164(rest),
+        };
+        record.facility = priority >> 3;
+        record.severity = priority & 7;
+        record
+    }
+}
+
+/// "<N>" with N up to 191, and what follows it
+fn parse_priority(text: &str) -> Option<(u8, &str)> {
+    let rest = text.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    let digits = &rest[..end];
+    if digits.is_empty() || digits.len() > 3 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let priority: u8 = digits.parse().ok().filter(|&priority| priority <= 191)?;
+    Some((priority, &rest[end + 1..]))
+}
+
+/// The next space separated field, "-" is no value
+fn field(rest: &mut &str) -> Option<String> {
+    let (value, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+    *rest = tail;
+    match value {
+        "" | "-" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+fn parse_5424(mut rest: &str) -> SyslogRecord {
+    let timestamp = field(&mut rest);
+    let hostname = field(&mut rest);
+    let app_name = field(&mut rest);
+    let proc_id = field(&mut rest);
+    let msg_id = field(&mut rest);
+
+    let structured_data = match rest.strip_prefix('-') {
+        Some(tail) => {
+            rest = tail;
+            None
+        }
+        None => {
+            let end = structured_data_end(rest);
+            let elements = rest[..end].to_string();
+            rest = &rest[end..];
+            Some(elements).filter(|elements| !elements.is_empty())
+        }
+    };
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    let message = message.strip_prefix('\u{feff}').unwrap_or(message);
+
+    SyslogRecord {
+        timestamp,
+        hostname,
+        app_name,
+        proc_id,
+        msg_id,
+        structured_data,
+        message: message.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Length of the `[...]` elements at the start, `]` and `"` inside values are escaped with `\`
+fn structured_data_end(text: &str) -> usize {
+    let mut end = 0;
+    let mut in_element = false;
+    let mut in_value = false;
+    let mut escaped = false;
+    for (position, c) in text.char_indices() {
+        match (in_element, c) {
+            (false, '[') => in_element = true,
+            (false, _) => break,
+            (true, _) if escaped => escaped = false,
+            (true, '\\') if in_value => escaped = true,
+            (true, '"') => in_value = !in_value,
+            (true, ']') if !in_value => {
+                in_element = false;
+                end = position + 1;
+            }
+            (true, _) => {}
+        }
+    }
+    end
+}
+
+fn parse_3164(rest: &str) -> SyslogRecord {
+    let mut record = SyslogRecord::default();
+
+    // "Oct 11 22:14:15 ", the day is padded with a space
+    let rest = match rest.get(..16) {
+        Some(timestamp) if timestamp.is_ascii() && MONTHS.contains(&&timestamp[..3]) && timestamp.ends_with(' ') && &timestamp[9..10] == ":" => {
+            record.timestamp = Some(timestamp.trim_end().to_string());
+            let mut rest = &rest[16..];
+            record.hostname = field(&mut rest);
+            rest
+        }
+        _ => rest,
+    };
+
+    // The tag is the program, with the pid in brackets, ending with ':'
+    let tag_end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c))).unwrap_or(0);
+    let (tag, tail) = rest.split_at(tag_end);
+    let (proc_id, tail) = match tail.strip_prefix('[').and_then(|tail| tail.split_once(']')) {
+        Some((proc_id, tail)) => (Some(proc_id.to_string()), tail),
+        None => (None, tail),
+    };
+    match tail.strip_prefix(':') {
+        Some(message) if tag_end > 0 => {
+            record.app_name = Some(tag.to_string());
+            record.proc_id = proc_id;
+            record.message = message.trim_start().to_string();
+        }
+        _ => record.message = rest.to_string(),
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::syslog::{facility_name, parse_facility, SyslogRecord};
+
+    #[test]
+    fn test_parse() {
+        let record = SyslogRecord::parse(br#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="App\]"][x] An application event"#);
+        assert_eq!((facility_name(record.facility), record.severity), (Some("local4"), 5));
+        assert_eq!(record.timestamp.as_deref(), Some("2003-10-11T22:14:15.003Z"));
+        assert_eq!(record.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(record.proc_id, None);
+        assert_eq!(record.msg_id.as_deref(), Some("ID47"));
+        assert_eq!(record.structured_data.as_deref(), Some(r#"[exampleSDID@32473 iut="3" eventSource="App\]"][x]"#));
+        assert_eq!(record.message, "An application event");
+
+        let record = SyslogRecord::parse(b"<34>Oct  1 22:14:15 pbx01 asterisk[2112]: NOTICE: call rejected\n");
+        assert_eq!((record.facility, record.severity), (4, 2));
+        assert_eq!(record.timestamp.as_deref(), Some("Oct  1 22:14:15"));
+        assert_eq!(record.hostname.as_deref(), Some("pbx01"));
+        assert_eq!(record.app_name.as_deref(), Some("asterisk"));
+        assert_eq!(record.proc_id.as_deref(), Some("2112"));
+        assert_eq!(record.message, "NOTICE: call rejected");
+
+        let record = SyslogRecord::parse(b"link down on port 3");
+        assert_eq!((record.facility, record.severity, record.message.as_str()), (1, 5, "link down on port 3"));
+        assert_eq!(parse_facility("LOCAL7"), Some(23));
+        assert_eq!(parse_facility("24"), None);
+    }
+}
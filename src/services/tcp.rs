@@ -1,33 +1,125 @@
-use actix_rt::net::{TcpStream, TcpListener};
-use actix::{Actor, Addr, Context};
-use crate::service::{Service, ServiceCore};
-use crate::node::Node;
-use crate::core::Core;
-use crate::message::BaseMessage;
-
-pub struct TcpService {
-    listeners: TcpListener,
-    connections: TcpStream
-}
+use std::error::Error;
+use std::net::SocketAddr;
+use actix_rt::net::TcpListener;
+use crate::config::ServiceConfig;
+use crate::plugin::builtin_service;
+use crate::services::codec::PEER_ADDRESS_HEADER;
+use crate::services::listener::bind_tcp;
+use crate::services::stream::{Endpoint, Peer, StreamService};
 
-impl TcpService {
+/// Listens on `address`, messages get the `peer_address` header. See `StreamService` for the
+/// other parameters.
+pub type TcpService = StreamService<TcpEndpoint>;
 
+pub struct TcpEndpoint {
+    address: String,
+    listener: Option<std::net::TcpListener>,
 }
 
-impl Actor for TcpService {
-    type Context = Context<Self>;
+impl Endpoint for TcpEndpoint {
+    type Listener = TcpListener;
+
+    fn bind(config: &ServiceConfig) -> Result<Self, Box<dyn Error>> {
+        let address = config.parameter("address")?.clone();
+        let listener = bind_tcp(&config.name, &address)?;
+        Ok(TcpEndpoint { address, listener: Some(listener) })
+    }
+
+    fn address(&self) -> String {
+        self.address.clone()
+    }
 
-    fn started(&mut self, ctx: &mut Self::Context) {
-        todo!()
+    fn listener(&mut self) -> Option<std::io::Result<TcpListener>> {
+        self.listener.take().map(TcpListener::from_std)
+    }
+
+    fn peer(&self, _stream: &actix_rt::net::TcpStream, peer: &SocketAddr) -> Result<Peer, String> {
+        Ok(Peer { name: peer.to_string(), headers: vec![(PEER_ADDRESS_HEADER, peer.to_string())] })
     }
 }
 
-impl Service for TcpService {
-    fn config_system(&mut self, system_core: &mut ServiceCore, node: Addr<Node>) {
-        todo!()
+builtin_service!(tcp_service => TcpService::on_start);
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::message::{BaseMessage, Parcel};
+    use crate::route::{Route, RouteSheet, Target};
+    use crate::services::codec::{read_length_prefixed, write_length_prefixed, CONNECTION_ID_HEADER};
+    use actix_rt::net::TcpStream;
+    use tokio::io::AsyncReadExt;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn test_tcp_echo() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        // Frames go to TcpIn, which the service consumes itself, so they come back to the connection
+        let mut core = Core::new("Node01".to_string());
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "TcpCore"
+Node:
+  name: "Node01"
+  tcp:
+    type: "TcpService"
+    parameters:
+      address: "127.0.0.1:{}"
+      target: "Consumer(TcpIn)"
+    subscribe_on_messages:
+      - "TcpIn"
+"#, port)).build().unwrap();
+        core.apply_config(config).await.unwrap();
+
+        let mut first = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut second = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        write_length_prefixed(&mut first, b"first").await.unwrap();
+        write_length_prefixed(&mut second, b"second").await.unwrap();
+
+        let read = |stream| async move {
+            actix_rt::time::timeout(Duration::from_secs(2), read_length_prefixed(stream, 1024)).await
+                .expect("No echo").unwrap()
+        };
+        assert_eq!(read(&mut first).await, Some(b"first".to_vec()));
+        assert_eq!(read(&mut second).await, Some(b"second".to_vec()));
+
+        core.stop_service("tcp").await;
     }
 
-    fn handle_message(&self, message: &BaseMessage) {
-        todo!()
+    #[actix_rt::test]
+    async fn test_slow_client() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut core = Core::new("Node01".to_string());
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "TcpCore"
+Node:
+  name: "Node01"
+  tcp:
+    type: "TcpService"
+    parameters:
+      address: "127.0.0.1:{}"
+      target: "Consumer(TcpIn)"
+      max_queued_frames: 1
+    subscribe_on_messages:
+      - "TcpIn"
+"#, port)).build().unwrap();
+        core.apply_config(config).await.unwrap();
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        write_length_prefixed(&mut stream, b"ping").await.unwrap();
+        let echo = actix_rt::time::timeout(Duration::from_secs(2), read_length_prefixed(&mut stream, 1024)).await.expect("No echo").unwrap();
+        assert_eq!(echo, Some(b"ping".to_vec()));
+
+        // A client that doesn`t read is closed once the socket buffers and the queue are full
+        let frame = BaseMessage::new(vec![0; 1024 * 1024], None).with_header(CONNECTION_ID_HEADER, "1".to_string());
+        let route_sheet = RouteSheet::new(Target::Consumer("TcpIn".to_string()), Route::new());
+        core.node().do_send(Parcel::new(vec![frame; 64], route_sheet));
+        let mut rest = vec![];
+        let closed = actix_rt::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest)).await;
+        assert!(closed.is_ok(), "Slow client is not closed");
+
+        core.stop_service("tcp").await;
     }
-}
\ No newline at end of file
+}
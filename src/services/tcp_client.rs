@@ -0,0 +1,107 @@
+use std::error::Error;
+use actix_rt::net::TcpStream;
+use futures_util::future::LocalBoxFuture;
+use crate::config::ServiceConfig;
+use crate::plugin::builtin_service;
+use crate::services::stream_client::{Connector, StreamClientService};
+
+/// Connects to `host`:`port`, see `StreamClientService` for the other parameters
+pub type TcpClientService = StreamClientService<TcpConnector>;
+
+pub struct TcpConnector {
+    address: String,
+}
+
+impl Connector for TcpConnector {
+    type Stream = TcpStream;
+
+    fn from_config(config: &ServiceConfig) -> Result<Self, Box<dyn Error>> {
+        let host = config.parameter("host")?;
+        let port: u16 = config.parse_parameter("port")?;
+        Ok(TcpConnector { address: format!("{}:{}", host, port) })
+    }
+
+    fn connect(&self) -> LocalBoxFuture<'static, std::io::Result<Self::Stream>> {
+        Box::pin(TcpStream::connect(self.address.clone()))
+    }
+
+    fn peer(&self) -> String {
+        self.address.clone()
+    }
+}
+
+builtin_service!(tcp_client_service => TcpClientService::on_start);
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::message::{BaseMessage, Parcel};
+    use crate::route::{Route, RouteSheet, Target};
+    use crate::services::codec::{FrameReader, Framing};
+    use crate::signal::GetStatistics;
+    use actix_rt::net::TcpListener;
+    use tokio::io::AsyncWriteExt;
+    use actix_rt::time::timeout;
+    use std::time::{Duration, Instant};
+
+    fn send(core: &Core, data: &[u8]) {
+        let route_sheet = RouteSheet::new(Target::Consumer("ClientOut".to_string()), Route::new());
+        core.node().do_send(Parcel::new(vec![BaseMessage::new(data.to_vec(), None)], route_sheet));
+    }
+
+    #[actix_rt::test]
+    async fn test_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut core = Core::new("Node01".to_string());
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "ClientCore"
+Node:
+  name: "Node01"
+  client:
+    type: "TcpClientService"
+    parameters:
+      host: "127.0.0.1"
+      port: {}
+      target: "Consumer(ClientIn)"
+      framing: "newline"
+      reconnect_delay_in_millis: 10
+    subscribe_on_messages:
+      - "ClientOut"
+"#, port)).build().unwrap();
+        core.apply_config(config).await.unwrap();
+
+        let (mut stream, _) = timeout(Duration::from_secs(2), listener.accept()).await.expect("No connection").unwrap();
+        stream.write_all(b"hello\n").await.unwrap();
+        send(&core, b"ping");
+        let (reader, _writer) = stream.split();
+        let mut frames = FrameReader::new(reader, Framing::Delimited(b"\n".to_vec()), 1024);
+        assert_eq!(timeout(Duration::from_secs(2), frames.next_frame()).await.expect("No frame").unwrap(), Some(b"ping".to_vec()));
+
+        let client_in = Target::Consumer("ClientIn".to_string()).as_string();
+        let started = Instant::now();
+        loop {
+            let snapshot = core.node().send(GetStatistics { service: None }).await.unwrap().unwrap();
+            if snapshot.node.targets.get(&client_in).map_or(0, |target| target.parcels_in) == 1 {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(2), "Inbound frame is not published");
+            actix_rt::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Sent while the connection is down, written once the client is back
+        drop(frames);
+        drop(stream);
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+        send(&core, b"again");
+
+        let (mut stream, _) = timeout(Duration::from_secs(2), listener.accept()).await.expect("No reconnect").unwrap();
+        let mut frames = FrameReader::new(&mut stream, Framing::Delimited(b"\n".to_vec()), 1024);
+        assert_eq!(timeout(Duration::from_secs(2), frames.next_frame()).await.expect("No buffered frame").unwrap(), Some(b"again".to_vec()));
+
+        core.stop_service("client").await;
+    }
+}
@@ -0,0 +1,318 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+use actix::{Actor, Addr, ArbiterHandle, AsyncContext, Context, Handler, Message};
+use actix::fut::wrap_future;
+use actix_rt::net::UdpSocket;
+use log::{debug, error, info, trace, warn};
+use crate::backoff::Backoff;
+use crate::config::{ConfigError, ServiceConfig};
+use crate::plugin::builtin_service;
+use crate::health::Readiness;
+use crate::message::{BaseMessage, Parcel};
+use crate::node::Node;
+use crate::route::{Route, RouteSheet, Target};
+use crate::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use crate::services::codec::PEER_ADDRESS_HEADER;
+use crate::services::listener::{bind_udp, report_readiness};
+use crate::services::syslog::{facility_name, parse_facility, severity_name, SyslogRecord};
+use crate::signal::Tick;
+
+pub const FACILITY_HEADER: &str = "facility";
+pub const SEVERITY_HEADER: &str = "severity";
+pub const TIMESTAMP_HEADER: &str = "timestamp";
+pub const HOSTNAME_HEADER: &str = "hostname";
+pub const APP_NAME_HEADER: &str = "app_name";
+pub const PROC_ID_HEADER: &str = "proc_id";
+pub const MSG_ID_HEADER: &str = "msg_id";
+pub const STRUCTURED_DATA_HEADER: &str = "structured_data";
+
+/// Largest payload of a UDP datagram over IPv4
+pub const DEFAULT_MAX_DATAGRAM_SIZE: usize = 65_507;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Raw,
+    Syslog,
+}
+
+/// Which syslog records go to a target instead of the default one
+#[derive(Debug, Clone, PartialEq)]
+enum Rule {
+    Facility(u8),
+    AppName(String),
+}
+
+impl Rule {
+    fn matches(&self, record: &SyslogRecord) -> bool {
+        match self {
+            Rule::Facility(facility) => record.facility == *facility,
+            Rule::AppName(app_name) => record.app_name.as_deref() == Some(app_name.as_str()),
+        }
+    }
+}
+
+struct Received {
+    data: Vec<u8>,
+    peer: SocketAddr,
+}
+
+impl Message for Received {
+    type Result = ();
+}
+
+/// Listens on `address` and publishes every datagram to `target`, with the `peer_address`
+/// header. With `format: syslog` a datagram is a syslog record: its text is the data, the
+/// `facility` and `severity` (names, e.g. "local0" and "err"), `timestamp`, `hostname`,
+/// `app_name`, `proc_id`, `msg_id` and `structured_data` fields are headers.
+///
+/// `routes` sends records elsewhere by facility or app name, the first matching rule wins,
+/// e.g. "facility:local0=Consumer(PbxLog),app:sshd=Consumer(AuthLog)".
+///
+/// Parameters: `address`, `target`, `format` (raw or syslog), `routes` and `max_datagram_size`.
+pub struct UdpService {
+    name: String,
+    address: String,
+    socket: Option<std::net::UdpSocket>,
+    target: Target,
+    format: Format,
+    routes: Vec<(Rule, Target)>,
+    max_datagram_size: usize,
+    route: Route,
+    node: Option<Addr<Node>>,
+}
+
+impl UdpService {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> {
+        let invalid = |parameter: &str, value: &str, reason: String| ConfigError::InvalidParameter {
+            service: config.name.clone(),
+            parameter: parameter.to_string(),
+            value: value.to_string(),
+            reason,
+        };
+
+        let format = match config.parse_parameter_or("format", "raw".to_string())?.as_str() {
+            "raw" => Format::Raw,
+            "syslog" => Format::Syslog,
+            format => return Err(invalid("format", format, "expected raw or syslog".to_string()).into()),
+        };
+
+        let mut routes = vec![];
+        let value: String = config.parse_parameter_or("routes", String::new())?;
+        for entry in value.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (rule, target) = entry.split_once('=')
+                .ok_or_else(|| invalid("routes", &value, format!("expected rule=target, got \"{}\"", entry.trim())))?;
+            let rule = match rule.trim().split_once(':') {
+                Some(("facility", facility)) => match parse_facility(facility) {
+                    Some(facility) => Rule::Facility(facility),
+                    None => return Err(invalid("routes", &value, format!("unknown facility {}", facility.trim())).into()),
+                },
+                Some(("app", app_name)) => Rule::AppName(app_name.trim().to_string()),
+                _ => return Err(invalid("routes", &value, format!("expected facility:name or app:name, got \"{}\"", rule.trim())).into()),
+            };
+            let target: Target = target.parse().map_err(|e| invalid("routes", &value, format!("{}", e)))?;
+            routes.push((rule, target));
+        }
+        if !routes.is_empty() && format != Format::Syslog {
+            return Err(invalid("routes", &value, "routes need format syslog".to_string()).into());
+        }
+
+        let address = config.parameter("address")?.clone();
+        let socket = bind_udp(&config.name, &address)?;
+
+        Ok(Box::new(UdpService {
+            name: config.name.clone(),
+            address,
+            socket: Some(socket),
+            target: config.parse_parameter("target")?,
+            format,
+            routes,
+            max_datagram_size: config.parse_parameter_or("max_datagram_size", DEFAULT_MAX_DATAGRAM_SIZE)?,
+            route: Route::new(),
+            node: None,
+        }))
+    }
+
+    fn receive(&mut self, socket: std::net::UdpSocket, ctx: &mut Context<Self>) {
+        let socket = match UdpSocket::from_std(socket) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Service {} can`t listen on {}: {}", self.name, self.address, e);
+                report_readiness(self.node.as_ref(), &self.name, Readiness::not_ready(e.to_string()));
+                return;
+            }
+        };
+        info!("Service {} listens on {}", self.name, self.address);
+        report_readiness(self.node.as_ref(), &self.name, Readiness::ready());
+
+        let addr = ctx.address();
+        let name = self.name.clone();
+        let mut buffer = vec![0u8; self.max_datagram_size];
+        ctx.spawn(wrap_future(async move {
+            let mut backoff = Backoff::new(Duration::from_millis(10), Duration::from_secs(1));
+            loop {
+                match socket.recv_from(&mut buffer).await {
+                    Ok((size, peer)) => {
+                        backoff.reset();
+                        addr.do_send(Received { data: buffer[..size].to_vec(), peer });
+                    }
+                    Err(e) => {
+                        let delay = backoff.next_delay();
+                        warn!("Service {} can`t receive a datagram, retrying in {:?}: {}", name, delay, e);
+                        actix_rt::time::sleep(delay).await;
+                    }
+                }
+            }
+        }));
+    }
+
+    fn to_message(&self, data: Vec<u8>, peer: SocketAddr) -> (Target, BaseMessage) {
+        if self.format == Format::Raw {
+            return (self.target.clone(), BaseMessage::new(data, None).with_header(PEER_ADDRESS_HEADER, peer.to_string()));
+        }
+
+        let record = SyslogRecord::parse(&data);
+        let target = self.routes.iter()
+            .find(|(rule, _target)| rule.matches(&record))
+            .map_or(&self.target, |(_rule, target)| target)
+            .clone();
+
+        let mut message = BaseMessage::new(record.message.into_bytes(), None)
+            .with_header(PEER_ADDRESS_HEADER, peer.to_string())
+            .with_header(FACILITY_HEADER, facility_name(record.facility).unwrap_or_default().to_string())
+            .with_header(SEVERITY_HEADER, severity_name(record.severity).unwrap_or_default().to_string());
+        let fields = [
+            (TIMESTAMP_HEADER, record.timestamp),
+            (HOSTNAME_HEADER, record.hostname),
+            (APP_NAME_HEADER, record.app_name),
+            (PROC_ID_HEADER, record.proc_id),
+            (MSG_ID_HEADER, record.msg_id),
+            (STRUCTURED_DATA_HEADER, record.structured_data),
+        ];
+        for (header, value) in fields.iter() {
+            if let Some(value) = value {
+                message.headers_mut().insert(header.to_string(), value.clone());
+            }
+        }
+        (target, message)
+    }
+}
+
+builtin_service!(udp_service => UdpService::on_start);
+
+impl Actor for UdpService {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(socket) = self.socket.take() {
+            self.receive(socket, ctx);
+        }
+    }
+}
+
+impl Service for UdpService {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Message to UDP service {:?}", message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl Handler<Received> for UdpService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Received, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(node) = &self.node {
+            let (target, message) = self.to_message(msg.data, msg.peer);
+            debug!("Service {} publishes datagram from {} to {}", self.name, msg.peer, target.as_string());
+            node.do_send(Parcel::new(vec![message], RouteSheet::new(target, self.route.clone())));
+        }
+    }
+}
+
+impl Handler<Parcel> for UdpService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        for message in msg.unpack() {
+            self.handle_message(message);
+        }
+        warn!("Service {} only receives datagrams, dropping parcel to {}", self.name, msg.target().as_string());
+    }
+}
+
+impl Handler<Tick> for UdpService {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::tests::capture::Capture;
+
+    #[actix_rt::test]
+    async fn test_syslog() {
+        let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        // Keeps what the service publishes
+        let published = Capture::keep(|_target| true);
+        let mut core = Core::new("Node01".to_string());
+        core.add_interceptor(Box::new(published.clone())).unwrap();
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "UdpCore"
+Node:
+  name: "Node01"
+  syslog:
+    type: "UdpService"
+    parameters:
+      address: "127.0.0.1:{}"
+      target: "Consumer(Syslog)"
+      format: "syslog"
+      routes: "app:asterisk=Consumer(PbxLog), facility:local0=Consumer(SwitchLog)"
+"#, port)).build().unwrap();
+        core.apply_config(config).await.unwrap();
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let datagrams: [&[u8]; 3] = [
+            b"<131>Oct 11 22:14:15 sw1 %LINK-3-UPDOWN: Interface Gi0/3, changed state to down",
+            b"<13>1 2026-10-19T10:00:00Z pbx01 asterisk 2112 - - Call rejected",
+            b"kernel panic",
+        ];
+        for datagram in datagrams.iter() {
+            socket.send_to(datagram, ("127.0.0.1", port)).unwrap();
+        }
+
+        let published = published.wait_for(3).await;
+        assert_eq!(published.len(), 3);
+
+        let (target, message) = &published[0];
+        assert_eq!(target.as_string(), "Consumer(SwitchLog)");
+        assert_eq!(message.header("facility"), Some(&"local0".to_string()));
+        assert_eq!(message.header("severity"), Some(&"err".to_string()));
+        assert_eq!(message.header("hostname"), Some(&"sw1".to_string()));
+        assert_eq!(message.header("peer_address"), Some(&socket.local_addr().unwrap().to_string()));
+
+        let (target, message) = &published[1];
+        assert_eq!(target.as_string(), "Consumer(PbxLog)");
+        assert_eq!(message.data(), b"Call rejected");
+        assert_eq!(message.header("proc_id"), Some(&"2112".to_string()));
+
+        let (target, message) = &published[2];
+        assert_eq!(target.as_string(), "Consumer(Syslog)");
+        assert_eq!(message.header("facility"), Some(&"user".to_string()));
+        assert_eq!(message.header("severity"), Some(&"notice".to_string()));
+
+        core.stop_service("syslog").await;
+    }
+}
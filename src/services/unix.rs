@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use actix_rt::net::{UnixListener, UnixStream};
+use log::{debug, warn};
+use crate::config::{ConfigError, ServiceConfig};
+use crate::plugin::builtin_service;
+use crate::service::ServiceError;
+use crate::services::stream::{Endpoint, Peer, StreamService};
+
+/// Credentials of the process on the other end, as the kernel reports them
+pub const PEER_UID_HEADER: &str = "peer_uid";
+pub const PEER_GID_HEADER: &str = "peer_gid";
+pub const PEER_PID_HEADER: &str = "peer_pid";
+
+/// Listens on the socket file `path`, messages get the `peer_uid`, `peer_gid` and `peer_pid`
+/// headers. See `StreamService` for the other parameters.
+///
+/// The socket file gets the permissions of `mode` (octal, e.g. "660"); a socket file nobody
+/// listens on is replaced, and the file is removed when the service stops. With `allowed_uids`
+/// only processes of these users can connect.
+pub type UnixService = StreamService<UnixEndpoint>;
+
+pub struct UnixEndpoint {
+    path: PathBuf,
+    listener: Option<std::os::unix::net::UnixListener>,
+    allowed_uids: Option<HashSet<u32>>,
+}
+
+impl Endpoint for UnixEndpoint {
+    type Listener = UnixListener;
+
+    fn bind(config: &ServiceConfig) -> Result<Self, Box<dyn Error>> {
+        let invalid = |parameter: &str, value: &str, reason: String| ConfigError::InvalidParameter {
+            service: config.name.clone(),
+            parameter: parameter.to_string(),
+            value: value.to_string(),
+            reason,
+        };
+
+        let mode = match config.parameters.get("mode") {
+            Some(mode) => match u32::from_str_radix(mode, 8) {
+                Ok(bits) if bits <= 0o777 => Some(bits),
+                _ => return Err(invalid("mode", mode, "expected octal permissions, e.g. 660".to_string()).into()),
+            },
+            None => None,
+        };
+        let allowed_uids = match config.parameters.get("allowed_uids") {
+            Some(uids) => Some(uids.split(',')
+                .filter(|uid| !uid.trim().is_empty())
+                .map(|uid| uid.trim().parse().map_err(|_e| invalid("allowed_uids", uids, format!("{} is no user id", uid.trim()))))
+                .collect::<Result<HashSet<u32>, ConfigError>>()?),
+            None => None,
+        };
+
+        let path = PathBuf::from(config.parameter("path")?);
+        let listener = bind(&path, mode)
+            .map_err(|e| ServiceError::Connection { service: config.name.clone(), reason: format!("can`t listen on {}: {}", path.display(), e) })?;
+        Ok(UnixEndpoint { path, listener: Some(listener), allowed_uids })
+    }
+
+    fn address(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn listener(&mut self) -> Option<std::io::Result<UnixListener>> {
+        self.listener.take().map(UnixListener::from_std)
+    }
+
+    fn peer(&self, stream: &UnixStream, _peer: &tokio::net::unix::SocketAddr) -> Result<Peer, String> {
+        let credentials = stream.peer_cred().map_err(|e| format!("can`t get the peer credentials: {}", e))?;
+        if let Some(allowed_uids) = &self.allowed_uids {
+            if !allowed_uids.contains(&credentials.uid()) {
+                return Err(format!("user {} is not allowed", credentials.uid()));
+            }
+        }
+
+        let mut headers = vec![(PEER_UID_HEADER, credentials.uid().to_string()), (PEER_GID_HEADER, credentials.gid().to_string())];
+        let name = match credentials.pid() {
+            Some(pid) => {
+                headers.push((PEER_PID_HEADER, pid.to_string()));
+                format!("user {} (pid {})", credentials.uid(), pid)
+            }
+            None => format!("user {}", credentials.uid()),
+        };
+        Ok(Peer { name, headers })
+    }
+
+    fn close(&mut self, service: &str) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Service {} can`t remove socket file {}: {}", service, self.path.display(), e);
+        }
+    }
+}
+
+/// Binds the socket file, replacing a stale one left by a process that is gone
+fn bind(path: &Path, mode: Option<u32>) -> std::io::Result<std::os::unix::net::UnixListener> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() && std::os::unix::net::UnixStream::connect(path).is_err() {
+            debug!("Removing stale socket file {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    let listener = match mode {
+        Some(mode) => bind_with_mode(path, mode)?,
+        None => std::os::unix::net::UnixListener::bind(path)?,
+    };
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Binds in a directory only this user can enter and links the socket file to `path` once it
+/// has its permissions, so nobody can connect before
+fn bind_with_mode(path: &Path, mode: u32) -> std::io::Result<std::os::unix::net::UnixListener> {
+    let file_name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file name"))?;
+    let directory = path.with_file_name(format!(".{}.{}", file_name.to_string_lossy(), std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&directory)?;
+
+    let staged = directory.join(file_name);
+    let result = std::os::unix::net::UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+        // Unlike a rename, linking fails when `path` is taken
+        std::fs::hard_link(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&directory);
+    result
+}
+
+builtin_service!(unix_service => UnixService::on_start);
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::services::codec::{read_length_prefixed, write_length_prefixed};
+    use actix_rt::net::UnixStream;
+    use crate::route::Target;
+    use crate::tests::capture::Capture;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn test_unix_echo() {
+        let path = std::env::temp_dir().join(format!("any_message-unix-{}.sock", std::process::id()));
+        // A leftover of a crashed run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path));
+
+        // Frames go to UnixIn, which the service consumes itself, so they come back to the connection
+        let published = Capture::watch(|target| target == &Target::Consumer("UnixIn".to_string()));
+        let mut core = Core::new("Node01".to_string());
+        core.add_interceptor(Box::new(published.clone())).unwrap();
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "UnixCore"
+Node:
+  name: "Node01"
+  unix:
+    type: "UnixService"
+    parameters:
+      path: "{}"
+      target: "Consumer(UnixIn)"
+      mode: "600"
+    subscribe_on_messages:
+      - "UnixIn"
+"#, path.display())).build().unwrap();
+        core.apply_config(config).await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        // Bound in a private directory that is gone once the socket file is in place
+        let staged = path.with_file_name(format!(".{}.{}", path.file_name().unwrap().to_string_lossy(), std::process::id()));
+        assert!(!staged.exists());
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        write_length_prefixed(&mut stream, b"ping").await.unwrap();
+        let echo = actix_rt::time::timeout(Duration::from_secs(2), read_length_prefixed(&mut stream, 1024)).await
+            .expect("No echo").unwrap();
+        assert_eq!(echo, Some(b"ping".to_vec()));
+
+        let headers = published.messages()[0].1.headers().clone();
+        assert_eq!(headers["peer_pid"], std::process::id().to_string());
+        assert!(headers["peer_uid"].parse::<u32>().is_ok());
+
+        core.stop_service("unix").await;
+        assert!(!path.exists());
+    }
+}
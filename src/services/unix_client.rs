@@ -0,0 +1,81 @@
+use std::error::Error;
+use std::path::PathBuf;
+use actix_rt::net::UnixStream;
+use futures_util::future::LocalBoxFuture;
+use crate::config::ServiceConfig;
+use crate::plugin::builtin_service;
+use crate::services::stream_client::{Connector, StreamClientService};
+
+/// Connects to the socket file `path`, see `StreamClientService` for the other parameters
+pub type UnixClientService = StreamClientService<UnixConnector>;
+
+pub struct UnixConnector {
+    path: PathBuf,
+}
+
+impl Connector for UnixConnector {
+    type Stream = UnixStream;
+
+    fn from_config(config: &ServiceConfig) -> Result<Self, Box<dyn Error>> {
+        Ok(UnixConnector { path: PathBuf::from(config.parameter("path")?) })
+    }
+
+    fn connect(&self) -> LocalBoxFuture<'static, std::io::Result<Self::Stream>> {
+        Box::pin(UnixStream::connect(self.path.clone()))
+    }
+
+    fn peer(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+builtin_service!(unix_client_service => UnixClientService::on_start);
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::message::{BaseMessage, Parcel};
+    use crate::route::{Route, RouteSheet, Target};
+    use crate::services::codec::{read_length_prefixed, write_length_prefixed};
+    use actix_rt::net::UnixListener;
+    use actix_rt::time::timeout;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn test_unix_client() {
+        let path = std::env::temp_dir().join(format!("any_message-unix-client-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // Frames the client reads go to ClientOut, which it consumes itself, so they are written back
+        let mut core = Core::new("Node01".to_string());
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "UnixClientCore"
+Node:
+  name: "Node01"
+  client:
+    type: "UnixClientService"
+    parameters:
+      path: "{}"
+      target: "Consumer(ClientOut)"
+    subscribe_on_messages:
+      - "ClientOut"
+"#, path.display())).build().unwrap();
+        core.apply_config(config).await.unwrap();
+
+        let (mut stream, _) = timeout(Duration::from_secs(2), listener.accept()).await.expect("No connection").unwrap();
+        write_length_prefixed(&mut stream, b"ping").await.unwrap();
+        let echo = timeout(Duration::from_secs(2), read_length_prefixed(&mut stream, 1024)).await.expect("No echo").unwrap();
+        assert_eq!(echo, Some(b"ping".to_vec()));
+
+        let route_sheet = RouteSheet::new(Target::Consumer("ClientOut".to_string()), Route::new());
+        core.node().do_send(Parcel::new(vec![BaseMessage::new(b"pong".to_vec(), None)], route_sheet));
+        let frame = timeout(Duration::from_secs(2), read_length_prefixed(&mut stream, 1024)).await.expect("No frame").unwrap();
+        assert_eq!(frame, Some(b"pong".to_vec()));
+
+        core.stop_service("client").await;
+        let _ = std::fs::remove_file(&path);
+    }
+}
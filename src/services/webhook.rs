@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+use actix::{Actor, ActorFutureExt, Addr, ArbiterHandle, AsyncContext, Context, Handler, Message};
+use actix::fut::wrap_future;
+use actix_rt::net::{TcpListener, TcpStream};
+use log::{debug, trace, warn};
+use tokio::sync::oneshot;
+use crate::config::{ConfigError, ServiceConfig};
+use crate::plugin::builtin_service;
+use crate::http::{read_request, HttpError, HttpRequest, HttpResponse};
+use crate::message::{BaseMessage, Parcel, REPLY_STATUS_HEADER};
+use crate::node::Node;
+use crate::route::{Route, RouteSheet, Target};
+use crate::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use crate::services::codec::PEER_ADDRESS_HEADER;
+use crate::services::listener::{bind_tcp, listen};
+use crate::signal::Tick;
+
+pub const REQUEST_ID_HEADER: &str = "request_id";
+pub const HTTP_METHOD_HEADER: &str = "http_method";
+pub const HTTP_PATH_HEADER: &str = "http_path";
+pub const HTTP_QUERY_HEADER: &str = "http_query";
+/// Status of the HTTP response, if a reply sets it
+pub const HTTP_STATUS_HEADER: &str = "http_status";
+
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+const DEFAULT_CONTENT_TYPES: &str = "application/json=json,text/plain=text,application/x-www-form-urlencoded=form,*=raw";
+
+/// How the body of a request becomes the data of its message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyCodec {
+    /// The body as it is
+    Raw,
+    /// Utf-8 text
+    Text,
+    /// A JSON document
+    Json,
+    /// Form fields, turned into a JSON object of strings
+    Form,
+}
+
+impl BodyCodec {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "raw" => Some(BodyCodec::Raw),
+            "text" => Some(BodyCodec::Text),
+            "json" => Some(BodyCodec::Json),
+            "form" => Some(BodyCodec::Form),
+            _ => None,
+        }
+    }
+
+    pub fn decode(&self, body: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self {
+            BodyCodec::Raw => Ok(body),
+            BodyCodec::Text => String::from_utf8(body)
+                .map(String::into_bytes)
+                .map_err(|e| format!("body is not utf-8: {}", e)),
+            BodyCodec::Json => match serde_json::from_slice::<serde_json::Value>(&body) {
+                Ok(_) => Ok(body),
+                Err(e) => Err(format!("body is not JSON: {}", e)),
+            },
+            BodyCodec::Form => {
+                let body = std::str::from_utf8(&body).map_err(|e| format!("body is not utf-8: {}", e))?;
+                let mut fields = serde_json::Map::new();
+                for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    let name = percent_decode(name).ok_or_else(|| format!("bad form field \"{}\"", pair))?;
+                    let value = percent_decode(value).ok_or_else(|| format!("bad form field \"{}\"", pair))?;
+                    fields.insert(name, serde_json::Value::String(value));
+                }
+                serde_json::to_vec(&fields).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Decodes `+` and `%XX` of a form field, `None` if the result isn`t utf-8
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let high = (input.next()? as char).to_digit(16)?;
+                let low = (input.next()? as char).to_digit(16)?;
+                bytes.push((high * 16 + low) as u8);
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Media type of a `Content-Type` header without its parameters, e.g. "application/json"
+fn media_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or("").trim().to_lowercase()
+}
+
+struct Accepted {
+    stream: TcpStream,
+    peer: SocketAddr,
+}
+
+impl Message for Accepted {
+    type Result = ();
+}
+
+struct Received {
+    request: HttpRequest,
+    peer: SocketAddr,
+    respond: oneshot::Sender<HttpResponse>,
+}
+
+impl Message for Received {
+    type Result = ();
+}
+
+/// Listens on `address` and publishes the body of every POST request to the target of its
+/// path, with the request headers (lowercase) and the `request_id`, `http_method`,
+/// `http_path`, `http_query` and `peer_address` headers.
+///
+/// Requests are answered with 202 right away, unless the service is `synchronous`: then the
+/// reply to the parcel becomes the response. Its data is the body, its `http_status` and
+/// `content-type` headers set the status and type; without `http_status` a reply with
+/// `status: error` is a 502. No reply within `reply_timeout_in_millis` is a 504.
+///
+/// A request not read within `request_timeout_in_millis` is a 408, connections beyond
+/// `max_connections` are closed.
+///
+/// Parameters: `address`, `paths` (e.g. "/hooks/github=Consumer(GithubEvent),/hooks/call=/Originate"),
+/// `content_types` (media type to raw, text, json or form, "*" for any other type),
+/// `max_body_size`, `synchronous`, `reply_timeout_in_millis`, `max_pending`,
+/// `request_timeout_in_millis` and `max_connections`.
+pub struct WebhookService {
+    name: String,
+    address: String,
+    listener: Option<std::net::TcpListener>,
+    paths: HashMap<String, Target>,
+    content_types: HashMap<String, BodyCodec>,
+    max_body_size: usize,
+    synchronous: bool,
+    reply_timeout: Duration,
+    max_pending: usize,
+    request_timeout: Duration,
+    max_connections: usize,
+    connections: usize,
+    pending: HashMap<String, oneshot::Sender<HttpResponse>>,
+    next_request_id: u64,
+    route: Route,
+    node: Option<Addr<Node>>,
+}
+
+impl WebhookService {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> {
+        let invalid = |parameter: &str, value: &str, reason: String| ConfigError::InvalidParameter {
+            service: config.name.clone(),
+            parameter: parameter.to_string(),
+            value: value.to_string(),
+            reason,
+        };
+
+        let mut paths = HashMap::new();
+        let value = config.parameter("paths")?;
+        for entry in value.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (path, target) = entry.split_once('=')
+                .ok_or_else(|| invalid("paths", value, format!("expected path=target, got \"{}\"", entry.trim())))?;
+            let target: Target = target.parse().map_err(|e| invalid("paths", value, format!("{}", e)))?;
+            paths.insert(path.trim().to_string(), target);
+        }
+
+        let mut content_types = HashMap::new();
+        let value: String = config.parse_parameter_or("content_types", DEFAULT_CONTENT_TYPES.to_string())?;
+        for entry in value.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let codec = entry.split_once('=')
+                .and_then(|(content_type, codec)| Some((media_type(content_type), BodyCodec::parse(codec)?)))
+                .ok_or_else(|| invalid("content_types", &value, format!("expected type=raw|text|json|form, got \"{}\"", entry.trim())))?;
+            content_types.insert(codec.0, codec.1);
+        }
+
+        let address = config.parameter("address")?.clone();
+        let listener = bind_tcp(&config.name, &address)?;
+
+        Ok(Box::new(WebhookService {
+            name: config.name.clone(),
+            address,
+            listener: Some(listener),
+            paths,
+            content_types,
+            max_body_size: config.parse_parameter_or("max_body_size", DEFAULT_MAX_BODY_SIZE)?,
+            synchronous: config.parse_parameter_or("synchronous", false)?,
+            reply_timeout: Duration::from_millis(config.parse_parameter_or("reply_timeout_in_millis", 30_000)?),
+            max_pending: config.parse_parameter_or("max_pending", 1024)?,
+            request_timeout: Duration::from_millis(config.parse_parameter_or("request_timeout_in_millis", 10_000)?),
+            max_connections: config.parse_parameter_or("max_connections", 1024)?,
+            connections: 0,
+            pending: HashMap::new(),
+            next_request_id: 0,
+            route: Route::new(),
+            node: None,
+        }))
+    }
+
+    /// The message of the request and its target, or the response refusing it
+    fn to_message(&self, request: HttpRequest, peer: SocketAddr) -> Result<(Target, BaseMessage), HttpResponse> {
+        if request.method != "POST" {
+            return Err(HttpResponse::new(405).header("Allow", "POST"));
+        }
+        let target = self.paths.get(request.route())
+            .ok_or_else(|| HttpResponse::new(404))?
+            .clone();
+
+        let content_type = media_type(request.header("content-type").map_or("", |content_type| content_type.as_str()));
+        let codec = self.content_types.get(&content_type)
+            .or_else(|| self.content_types.get("*"))
+            .ok_or_else(|| HttpResponse::new(415).text(format!("content type \"{}\" is not accepted", content_type)))?;
+
+        let path = request.route().to_string();
+        let query = request.path.split_once('?').map_or("", |(_path, query)| query).to_string();
+        let data = codec.decode(request.body).map_err(|reason| HttpResponse::new(400).text(reason))?;
+
+        let mut message = BaseMessage::new(data, None);
+        *message.headers_mut() = request.headers;
+        let message = message
+            .with_header(HTTP_METHOD_HEADER, request.method)
+            .with_header(HTTP_PATH_HEADER, path)
+            .with_header(HTTP_QUERY_HEADER, query)
+            .with_header(PEER_ADDRESS_HEADER, peer.to_string());
+        Ok((target, message))
+    }
+}
+
+/// The response for the reply to a synchronous request
+fn to_response(message: &BaseMessage) -> HttpResponse {
+    let failed = message.header(REPLY_STATUS_HEADER).is_some_and(|status| status == "error");
+    let status = message.header(HTTP_STATUS_HEADER)
+        .and_then(|status| status.parse().ok())
+        .unwrap_or(if failed { 502 } else { 200 });
+    let content_type = message.header("content-type").map_or("application/octet-stream", |content_type| content_type.as_str());
+
+    HttpResponse::new(status)
+        .header("Content-Type", content_type)
+        .body(message.data().clone())
+}
+
+builtin_service!(webhook_service => WebhookService::on_start);
+
+impl Actor for WebhookService {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(listener) = self.listener.take() {
+            listen(ctx, &self.name, &self.address, self.node.as_ref(), TcpListener::from_std(listener), |stream, peer| Accepted { stream, peer });
+        }
+    }
+}
+
+impl Service for WebhookService {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Reply to webhook request {:?}", message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl Handler<Accepted> for WebhookService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Accepted, ctx: &mut Self::Context) -> Self::Result {
+        if self.connections >= self.max_connections {
+            warn!("Service {} has {} connections, closing the one from {}", self.name, self.connections, msg.peer);
+            return;
+        }
+        self.connections += 1;
+
+        let addr = ctx.address();
+        let max_body_size = self.max_body_size;
+        let request_timeout = self.request_timeout;
+        let mut stream = msg.stream;
+        let peer = msg.peer;
+        let answer = async move {
+            let request = actix_rt::time::timeout(request_timeout, read_request(&mut stream, max_body_size)).await
+                .unwrap_or(Err(HttpError::Timeout));
+            let response = match request {
+                Ok(request) => {
+                    let (respond, response) = oneshot::channel();
+                    addr.do_send(Received { request, peer, respond });
+                    response.await.unwrap_or_else(|_e| HttpResponse::new(503))
+                }
+                Err(e) => e.response(),
+            };
+
+            if let Err(e) = response.write(&mut stream).await {
+                debug!("Can`t answer webhook request from {}: {}", peer, e);
+            }
+        };
+        ctx.spawn(wrap_future(answer).map(|(), service: &mut Self, _ctx| service.connections -= 1));
+    }
+}
+
+impl Handler<Received> for WebhookService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Received, ctx: &mut Self::Context) -> Self::Result {
+        let (target, message) = match self.to_message(msg.request, msg.peer) {
+            Ok(request) => request,
+            Err(response) => {
+                let _ = msg.respond.send(response);
+                return;
+            }
+        };
+        let node = match &self.node {
+            Some(node) => node,
+            None => {
+                let _ = msg.respond.send(HttpResponse::new(503));
+                return;
+            }
+        };
+        if self.synchronous && self.pending.len() >= self.max_pending {
+            warn!("Service {} waits for {} replies, refusing request from {}", self.name, self.pending.len(), msg.peer);
+            let _ = msg.respond.send(HttpResponse::new(503));
+            return;
+        }
+
+        self.next_request_id += 1;
+        let request_id = self.next_request_id.to_string();
+        debug!("Service {} publishes request {} to {}", self.name, request_id, target.as_string());
+
+        // Replies come back to "::name:request_id"
+        let from = self.route.clone().set_inner_id(request_id.clone()).clone();
+        let message = message.with_header(REQUEST_ID_HEADER, request_id.clone());
+        node.do_send(Parcel::new(vec![message], RouteSheet::new(target, from)));
+
+        match self.synchronous {
+            true => {
+                self.pending.insert(request_id.clone(), msg.respond);
+                ctx.run_later(self.reply_timeout, move |service, _ctx| {
+                    if let Some(respond) = service.pending.remove(&request_id) {
+                        warn!("Service {} got no reply to request {}", service.name, request_id);
+                        let _ = respond.send(HttpResponse::new(504));
+                    }
+                });
+            }
+            false => {
+                let _ = msg.respond.send(HttpResponse::new(202).text(request_id));
+            }
+        }
+    }
+}
+
+impl Handler<Parcel> for WebhookService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        let request_id = match msg.target() {
+            Target::Route(route) => route.inner_id().clone(),
+            Target::Consumer(_) => String::new(),
+        };
+
+        let respond = match self.pending.remove(&request_id) {
+            Some(respond) => respond,
+            None => {
+                debug!("Service {} isn`t waiting for request {:?}, dropping reply", self.name, request_id);
+                return;
+            }
+        };
+        let response = match msg.unpack().first() {
+            Some(message) => {
+                self.handle_message(message);
+                to_response(message)
+            }
+            None => HttpResponse::new(204),
+        };
+        let _ = respond.send(response);
+    }
+}
+
+impl Handler<Tick> for WebhookService {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::message::BaseMessage;
+    use crate::route::Target;
+    use crate::services::webhook::{BodyCodec, HTTP_STATUS_HEADER};
+    use crate::tests::capture::Capture;
+    use actix_rt::net::TcpStream;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_codecs() {
+        assert_eq!(BodyCodec::Form.decode(b"name=J%C3%BCrgen+K&empty=&flag".to_vec()).unwrap(),
+                   r#"{"empty":"","flag":"","name":"Jürgen K"}"#.as_bytes().to_vec());
+        assert!(BodyCodec::Form.decode(b"bad=%G1".to_vec()).is_err());
+        assert!(BodyCodec::Json.decode(b"{\"a\":".to_vec()).is_err());
+        assert!(BodyCodec::Text.decode(vec![0xff]).is_err());
+        assert_eq!(BodyCodec::Raw.decode(vec![0xff]).unwrap(), vec![0xff]);
+    }
+
+    #[actix_rt::test]
+    async fn test_synchronous_webhook() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let mut core = Core::new("Node01".to_string());
+        // Answers parcels for Consumer(Hook) with their data in upper case
+        let upper = Capture::reply(|target| target == &Target::Consumer("Hook".to_string()), |message| {
+            BaseMessage::new(message.data().to_ascii_uppercase(), None)
+                .with_header(HTTP_STATUS_HEADER, "201".to_string())
+                .with_header("content-type", message.headers()["content-type"].clone())
+        });
+        core.add_interceptor(Box::new(upper)).unwrap();
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "WebhookCore"
+Node:
+  name: "Node01"
+  webhook:
+    type: "WebhookService"
+    parameters:
+      address: "127.0.0.1:{}"
+      paths: "/hooks/upper=Consumer(Hook), /hooks/nobody=Consumer(Nobody)"
+      content_types: "text/plain=text"
+      synchronous: "true"
+      reply_timeout_in_millis: "200"
+      request_timeout_in_millis: "200"
+"#, port)).build().unwrap();
+        core.apply_config(config).await.unwrap();
+
+        let post = |path: &str, content_type: &str, body: &str| {
+            let request = format!("POST {} HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}", path, content_type, body.len(), body);
+            async move {
+                let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                actix_rt::time::timeout(Duration::from_secs(2), stream.read_to_string(&mut response)).await
+                    .expect("No response").unwrap();
+                response
+            }
+        };
+
+        let response = post("/hooks/upper", "text/plain; charset=utf-8", "hello").await;
+        assert!(response.starts_with("HTTP/1.1 201 "), "{}", response);
+        assert!(response.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(response.ends_with("\r\n\r\nHELLO"));
+
+        assert!(post("/hooks/other", "text/plain", "hello").await.starts_with("HTTP/1.1 404 "));
+        assert!(post("/hooks/upper", "application/json", "{}").await.starts_with("HTTP/1.1 415 "));
+        assert!(post("/hooks/nobody", "text/plain", "hello").await.starts_with("HTTP/1.1 504 "));
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(b"POST /hooks/upper HTTP/1.1\r\n").await.unwrap();
+        let mut response = String::new();
+        actix_rt::time::timeout(Duration::from_secs(2), stream.read_to_string(&mut response)).await.expect("No response").unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+
+        core.stop_service("webhook").await;
+    }
+}
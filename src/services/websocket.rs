@@ -0,0 +1,647 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use actix::{Actor, ActorContext, ActorFutureExt, Addr, ArbiterHandle, AsyncContext, Context, Handler, Message};
+use actix::fut::wrap_future;
+use actix_rt::net::{TcpListener, TcpStream};
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, trace, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tokio_tungstenite::WebSocketStream;
+use crate::config::ServiceConfig;
+use crate::plugin::builtin_service;
+use crate::message::{BaseMessage, Parcel};
+use crate::node::Node;
+use crate::route::{Route, RouteSheet, Target};
+use crate::service::{spawn_service_actor, Service, ServiceCore, ServiceError, ServiceRecipients};
+use crate::services::codec::{CONNECTION_ID_HEADER, PEER_ADDRESS_HEADER};
+use crate::services::listener::{bind_tcp, listen};
+use crate::signal::{SubscribeTransientSignal, Tick, UnsubscribeTransientSignal};
+use crate::transport::Transport;
+
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// How long a frame may take to be written before the client counts as gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A text frame of the client, answers carry its `id`
+#[derive(Debug, Deserialize)]
+struct ClientFrame {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(flatten)]
+    request: ClientRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientRequest {
+    Auth { token: String },
+    Subscribe { message_types: Vec<String> },
+    Unsubscribe { message_types: Vec<String> },
+    /// `data` is sent as is if it is a string, as JSON otherwise
+    Publish {
+        target: String,
+        #[serde(default)]
+        data: Value,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// Like `Publish`, the reply to the parcel comes back as `reply` with the same `id`
+    Call {
+        target: String,
+        #[serde(default)]
+        data: Value,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerFrame {
+    Ok {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        reason: String,
+    },
+    Message { target: String, data: String, headers: HashMap<String, String> },
+    Reply { id: String, data: String, headers: HashMap<String, String> },
+}
+
+fn to_data(data: Value) -> Vec<u8> {
+    match data {
+        Value::Null => vec![],
+        Value::String(text) => text.into_bytes(),
+        data => data.to_string().into_bytes(),
+    }
+}
+
+/// What the connections of a service share
+struct Settings {
+    path: String,
+    tokens: HashSet<String>,
+    auth_timeout: Duration,
+    max_message_size: usize,
+    max_queued_frames: usize,
+}
+
+struct Accepted {
+    stream: TcpStream,
+    peer: SocketAddr,
+}
+
+impl Message for Accepted {
+    type Result = ();
+}
+
+struct ConnectionClosed {
+    connection_id: String,
+}
+
+impl Message for ConnectionClosed {
+    type Result = ();
+}
+
+/// Listens on `address` for WebSocket clients on `path`. A client authenticates with one
+/// of the `tokens` within `auth_timeout_in_millis` and then talks JSON text frames:
+///
+/// - `{"type": "auth", "token": "..."}`
+/// - `{"type": "subscribe", "message_types": ["AmiEvent"]}` and `unsubscribe`, while
+///   subscribed the connection gets `{"type": "message", "target", "data", "headers"}`
+/// - `{"type": "publish", "target": "Consumer(Dial)", "data": ..., "headers": {...}}`
+/// - `{"type": "call", "id": "1", "target": "/Originate", "data": ...}`, answered with
+///   `{"type": "reply", "id": "1", "data", "headers"}`
+///
+/// Other frames are answered with `ok` or `error`, carrying the `id` of the frame if it has
+/// one. Published messages get the `connection_id` and `peer_address` headers.
+///
+/// The handshake has to be done within `auth_timeout_in_millis` as well, and a client that
+/// has `max_queued_frames` frames waiting to be written is disconnected.
+///
+/// Parameters: `address`, `tokens` (comma separated), `path` (default "/"),
+/// `auth_timeout_in_millis`, `max_message_size`, `max_queued_frames` and `max_connections`.
+pub struct WebSocketService {
+    name: String,
+    address: String,
+    listener: Option<std::net::TcpListener>,
+    settings: Arc<Settings>,
+    max_connections: usize,
+    connections: HashMap<String, Addr<Connection>>,
+    next_connection_id: u64,
+    route: Route,
+    node: Option<Addr<Node>>,
+}
+
+impl WebSocketService {
+    pub fn on_start(config: ServiceConfig) -> Result<Box<dyn Service>, Box<dyn Error>> {
+        let tokens = config.parameter("tokens")?.split(',')
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .collect();
+
+        let address = config.parameter("address")?.clone();
+        let listener = bind_tcp(&config.name, &address)?;
+
+        Ok(Box::new(WebSocketService {
+            name: config.name.clone(),
+            address,
+            listener: Some(listener),
+            settings: Arc::new(Settings {
+                path: config.parse_parameter_or("path", "/".to_string())?,
+                tokens,
+                auth_timeout: Duration::from_millis(config.parse_parameter_or("auth_timeout_in_millis", 5_000)?),
+                max_message_size: config.parse_parameter_or("max_message_size", DEFAULT_MAX_MESSAGE_SIZE)?,
+                max_queued_frames: config.parse_parameter_or("max_queued_frames", 1024)?,
+            }),
+            max_connections: config.parse_parameter_or("max_connections", 1024)?,
+            connections: HashMap::new(),
+            next_connection_id: 0,
+            route: Route::new(),
+            node: None,
+        }))
+    }
+
+}
+
+builtin_service!(websocket_service => WebSocketService::on_start);
+
+impl Actor for WebSocketService {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(listener) = self.listener.take() {
+            listen(ctx, &self.name, &self.address, self.node.as_ref(), TcpListener::from_std(listener), |stream, peer| Accepted { stream, peer });
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        for connection in self.connections.values() {
+            connection.do_send(Closed { reason: "service stopped".to_string() });
+        }
+    }
+}
+
+impl Service for WebSocketService {
+    fn config_system(&mut self, service_core: &mut ServiceCore, node: Addr<Node>) {
+        self.route = service_core.route().clone();
+        self.node = Some(node);
+    }
+
+    fn handle_message(&self, message: &BaseMessage) {
+        trace!("Reply for WebSocket client {:?}", message);
+    }
+
+    fn spawn(self: Box<Self>, arbiter: &ArbiterHandle) -> Result<ServiceRecipients, ServiceError> {
+        spawn_service_actor(*self, arbiter)
+    }
+}
+
+impl Handler<Accepted> for WebSocketService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Accepted, ctx: &mut Self::Context) -> Self::Result {
+        let node = match &self.node {
+            Some(node) => node.clone(),
+            None => return,
+        };
+        if self.connections.len() >= self.max_connections {
+            warn!("Service {} has {} connections, closing the one from {}", self.name, self.connections.len(), msg.peer);
+            return;
+        }
+
+        self.next_connection_id += 1;
+        let connection_id = self.next_connection_id.to_string();
+        debug!("Service {} accepted connection {} from {}", self.name, connection_id, msg.peer);
+
+        let connection = Connection {
+            id: connection_id.clone(),
+            peer: msg.peer,
+            stream: Some(msg.stream),
+            settings: self.settings.clone(),
+            service: ctx.address(),
+            service_route: self.route.clone(),
+            node,
+            transport: None,
+            writer: None,
+            authenticated: false,
+        };
+        self.connections.insert(connection_id, connection.start());
+    }
+}
+
+impl Handler<ConnectionClosed> for WebSocketService {
+    type Result = ();
+
+    fn handle(&mut self, msg: ConnectionClosed, _ctx: &mut Self::Context) -> Self::Result {
+        self.connections.remove(&msg.connection_id);
+    }
+}
+
+impl Handler<Parcel> for WebSocketService {
+    type Result = ();
+
+    /// Replies to calls come to "::name:connection_id.call_id"
+    fn handle(&mut self, msg: Parcel, _ctx: &mut Self::Context) -> Self::Result {
+        let inner_id = match msg.target() {
+            Target::Route(route) => route.inner_id().clone(),
+            Target::Consumer(_) => String::new(),
+        };
+        let (connection_id, call_id) = match inner_id.split_once('.') {
+            Some(ids) => ids,
+            None => {
+                debug!("Service {} got a parcel that is no reply, dropping it", self.name);
+                return;
+            }
+        };
+
+        match self.connections.get(connection_id) {
+            Some(connection) => {
+                for message in msg.unpack() {
+                    self.handle_message(message);
+                }
+                connection.do_send(CallReply { id: call_id.to_string(), messages: msg.unpack().clone() });
+            }
+            None => debug!("Connection {} of service {} is closed, dropping reply", connection_id, self.name),
+        }
+    }
+}
+
+impl Handler<Tick> for WebSocketService {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Tick, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+type WsSink = SplitSink<WebSocketStream<TcpStream>, WsMessage>;
+
+struct Frame(String);
+
+impl Message for Frame {
+    type Result = ();
+}
+
+struct CallReply {
+    id: String,
+    messages: Vec<BaseMessage>,
+}
+
+impl Message for CallReply {
+    type Result = ();
+}
+
+struct Closed {
+    reason: String,
+}
+
+impl Message for Closed {
+    type Result = ();
+}
+
+/// One client, subscribed in the topology with its own transport while it is connected
+struct Connection {
+    id: String,
+    peer: SocketAddr,
+    stream: Option<TcpStream>,
+    settings: Arc<Settings>,
+    service: Addr<WebSocketService>,
+    service_route: Route,
+    node: Addr<Node>,
+    transport: Option<Transport>,
+    writer: Option<Sender<WsMessage>>,
+    authenticated: bool,
+}
+
+impl Connection {
+    fn send(&mut self, frame: ServerFrame, ctx: &mut Context<Self>) {
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        let text = match serde_json::to_string(&frame) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Can`t serialize frame for connection {}: {}", self.id, e);
+                return;
+            }
+        };
+
+        if let Err(TrySendError::Full(_frame)) = writer.try_send(WsMessage::Text(text)) {
+            warn!("Connection {} from {} has {} frames waiting, closing it", self.id, self.peer, self.settings.max_queued_frames);
+            // Dropping the sender ends the writer
+            self.writer = None;
+            ctx.stop();
+        }
+    }
+
+    fn handshake_done(&mut self, result: Result<WebSocketStream<TcpStream>, String>, ctx: &mut Context<Self>) {
+        let websocket = match result {
+            Ok(websocket) => websocket,
+            Err(e) => {
+                debug!("WebSocket handshake with {} failed: {}", self.peer, e);
+                ctx.stop();
+                return;
+            }
+        };
+
+        let (sink, mut stream) = websocket.split();
+        let (sender, receiver) = channel(self.settings.max_queued_frames.max(1));
+        self.writer = Some(sender);
+        // Not bound to the actor, so the close frame is still written once it stops
+        actix_rt::spawn(write_frames(sink, receiver));
+
+        let addr = ctx.address();
+        ctx.spawn(wrap_future(async move {
+            let reason = loop {
+                match stream.next().await {
+                    Some(Ok(WsMessage::Text(text))) => addr.do_send(Frame(text)),
+                    Some(Ok(WsMessage::Binary(data))) => addr.do_send(Frame(String::from_utf8_lossy(&data).into_owned())),
+                    Some(Ok(WsMessage::Close(_))) | None => break "closed by peer".to_string(),
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => break e.to_string(),
+                }
+            };
+            addr.do_send(Closed { reason });
+        }));
+
+        ctx.run_later(self.settings.auth_timeout, |connection, ctx| {
+            if !connection.authenticated {
+                connection.send(ServerFrame::Error { id: None, reason: "authentication timed out".to_string() }, ctx);
+                ctx.stop();
+            }
+        });
+    }
+
+    fn publish(&self, target: &str, data: Value, headers: HashMap<String, String>, from: Route) -> Result<(), String> {
+        let target: Target = target.parse().map_err(|e| format!("{}", e))?;
+        let mut message = BaseMessage::new(to_data(data), None);
+        *message.headers_mut() = headers;
+        let message = message
+            .with_header(CONNECTION_ID_HEADER, self.id.clone())
+            .with_header(PEER_ADDRESS_HEADER, self.peer.to_string());
+        self.node.do_send(Parcel::new(vec![message], RouteSheet::new(target, from)));
+        Ok(())
+    }
+}
+
+async fn write_frames(mut sink: WsSink, mut receiver: Receiver<WsMessage>) {
+    while let Some(frame) = receiver.recv().await {
+        match actix_rt::time::timeout(WRITE_TIMEOUT, sink.send(frame)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                debug!("Can`t write to WebSocket connection {}", e);
+                return;
+            }
+            Err(_elapsed) => {
+                debug!("Writing to WebSocket connection timed out");
+                return;
+            }
+        }
+    }
+    let _ = actix_rt::time::timeout(WRITE_TIMEOUT, sink.close()).await;
+}
+
+impl Actor for Connection {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.transport = Some(Transport::new(ctx.address().recipient()));
+
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => return,
+        };
+        let path = self.settings.path.clone();
+        // The error response is what tungstenite expects of the callback
+        #[allow(clippy::result_large_err)]
+        let check_path = move |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+            match request.uri().path() == path {
+                true => Ok(response),
+                false => {
+                    let mut not_found = ErrorResponse::new(None);
+                    *not_found.status_mut() = StatusCode::NOT_FOUND;
+                    Err(not_found)
+                }
+            }
+        };
+        let config = WebSocketConfig {
+            max_message_size: Some(self.settings.max_message_size),
+            max_frame_size: Some(self.settings.max_message_size),
+            ..WebSocketConfig::default()
+        };
+
+        let handshake = tokio_tungstenite::accept_hdr_async_with_config(stream, check_path, Some(config));
+        let handshake = actix_rt::time::timeout(self.settings.auth_timeout, handshake);
+        ctx.spawn(wrap_future(handshake).map(|result, connection: &mut Connection, ctx| {
+            let result = match result {
+                Ok(result) => result.map_err(|e: WsError| e.to_string()),
+                Err(_elapsed) => Err("timed out".to_string()),
+            };
+            connection.handshake_done(result, ctx)
+        }));
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(transport) = self.transport.take() {
+            self.node.do_send(UnsubscribeTransientSignal { message_types: None, transport });
+        }
+        self.service.do_send(ConnectionClosed { connection_id: self.id.clone() });
+    }
+}
+
+impl Handler<Frame> for Connection {
+    type Result = ();
+
+    fn handle(&mut self, msg: Frame, ctx: &mut Self::Context) -> Self::Result {
+        let frame: ClientFrame = match serde_json::from_str(&msg.0) {
+            Ok(frame) => frame,
+            Err(e) => {
+                self.send(ServerFrame::Error { id: None, reason: format!("can`t parse frame: {}", e) }, ctx);
+                return;
+            }
+        };
+        let id = frame.id;
+        if !self.authenticated && !matches!(frame.request, ClientRequest::Auth { .. }) {
+            self.send(ServerFrame::Error { id, reason: "not authenticated".to_string() }, ctx);
+            return;
+        }
+        let transport = match &self.transport {
+            Some(transport) => transport.clone(),
+            None => return,
+        };
+
+        let result = match frame.request {
+            ClientRequest::Auth { token } => {
+                if !self.settings.tokens.contains(&token) {
+                    debug!("Connection {} from {} has an invalid token", self.id, self.peer);
+                    self.send(ServerFrame::Error { id, reason: "invalid token".to_string() }, ctx);
+                    ctx.stop();
+                    return;
+                }
+                self.authenticated = true;
+                Ok(())
+            }
+            ClientRequest::Subscribe { message_types } => {
+                self.node.do_send(SubscribeTransientSignal { message_types, transport });
+                Ok(())
+            }
+            ClientRequest::Unsubscribe { message_types } => {
+                self.node.do_send(UnsubscribeTransientSignal { message_types: Some(message_types), transport });
+                Ok(())
+            }
+            ClientRequest::Publish { target, data, headers } => self.publish(&target, data, headers, self.service_route.clone()),
+            ClientRequest::Call { target, data, headers } => match &id {
+                Some(call_id) => {
+                    let from = self.service_route.clone().set_inner_id(format!("{}.{}", self.id, call_id)).clone();
+                    match self.publish(&target, data, headers, from) {
+                        // Answered by the reply
+                        Ok(()) => return,
+                        Err(reason) => Err(reason),
+                    }
+                }
+                None => Err("call without id".to_string()),
+            },
+        };
+
+        match result {
+            Ok(()) => self.send(ServerFrame::Ok { id }, ctx),
+            Err(reason) => self.send(ServerFrame::Error { id, reason }, ctx),
+        }
+    }
+}
+
+impl Handler<Parcel> for Connection {
+    type Result = ();
+
+    fn handle(&mut self, msg: Parcel, ctx: &mut Self::Context) -> Self::Result {
+        for message in msg.unpack() {
+            self.send(ServerFrame::Message {
+                target: msg.target().as_string(),
+                data: String::from_utf8_lossy(message.data()).into_owned(),
+                headers: message.headers().clone(),
+            }, ctx);
+        }
+    }
+}
+
+impl Handler<CallReply> for Connection {
+    type Result = ();
+
+    fn handle(&mut self, msg: CallReply, ctx: &mut Self::Context) -> Self::Result {
+        for message in &msg.messages {
+            self.send(ServerFrame::Reply {
+                id: msg.id.clone(),
+                data: String::from_utf8_lossy(message.data()).into_owned(),
+                headers: message.headers().clone(),
+            }, ctx);
+        }
+    }
+}
+
+impl Handler<Closed> for Connection {
+    type Result = ();
+
+    fn handle(&mut self, msg: Closed, ctx: &mut Self::Context) -> Self::Result {
+        debug!("Connection {} from {} closed: {}", self.id, self.peer, msg.reason);
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::core::Core;
+    use crate::route::Target;
+    use crate::tests::capture::Capture;
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Sends the frame, if any, and returns the next one
+    async fn exchange(client: &mut Client, frame: Value) -> Value {
+        if !frame.is_null() {
+            client.send(Message::Text(frame.to_string())).await.unwrap();
+        }
+        match actix_rt::time::timeout(Duration::from_secs(2), client.next()).await.expect("No frame") {
+            Some(Ok(Message::Text(text))) => serde_json::from_str(&text).unwrap(),
+            frame => panic!("Unexpected frame {:?}", frame),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_gateway() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let mut core = Core::new("Node01".to_string());
+        // Answers calls of "/Echo" with their own messages
+        let echo = Capture::reply(|target| matches!(target, Target::Route(route) if route.operation_name() == "Echo"), |message| message.clone());
+        core.add_interceptor(Box::new(echo)).unwrap();
+        let config = ConfigBuilder::from_string(format!(r#"
+Core:
+  name: "WebSocketCore"
+Node:
+  name: "Node01"
+  gateway:
+    type: "WebSocketService"
+    parameters:
+      address: "127.0.0.1:{}"
+      path: "/events"
+      tokens: "s3cret, other"
+      auth_timeout_in_millis: "500"
+"#, port)).build().unwrap();
+        core.apply_config(config).await.unwrap();
+
+        let url = format!("ws://127.0.0.1:{}/events", port);
+        let (mut client, _response) = tokio_tungstenite::connect_async(url.as_str()).await.unwrap();
+        let answer = exchange(&mut client, json!({"type": "subscribe", "id": "1", "message_types": ["Event"]})).await;
+        assert_eq!(answer, json!({"type": "error", "id": "1", "reason": "not authenticated"}));
+        assert_eq!(exchange(&mut client, json!({"type": "auth", "id": "2", "token": "s3cret"})).await, json!({"type": "ok", "id": "2"}));
+        assert_eq!(exchange(&mut client, json!({"type": "subscribe", "id": "3", "message_types": ["Event"]})).await, json!({"type": "ok", "id": "3"}));
+
+        assert_eq!(exchange(&mut client, json!({"type": "publish", "target": "Consumer(Event)", "data": {"a": 1}})).await, json!({"type": "ok"}));
+        let message = exchange(&mut client, Value::Null).await;
+        assert_eq!(message["type"], "message");
+        assert_eq!(message["target"], "Consumer(Event)");
+        assert_eq!(message["data"], r#"{"a":1}"#);
+        assert_eq!(message["headers"]["connection_id"], "1");
+
+        let reply = exchange(&mut client, json!({"type": "call", "id": "c1", "target": "/Echo", "data": "ping", "headers": {"x": "y"}})).await;
+        assert_eq!(reply["type"], "reply");
+        assert_eq!(reply["id"], "c1");
+        assert_eq!(reply["data"], "ping");
+        assert_eq!(reply["headers"]["x"], "y");
+
+        let (mut intruder, _response) = tokio_tungstenite::connect_async(url.as_str()).await.unwrap();
+        intruder.send(Message::Text(json!({"type": "auth", "token": "guess"}).to_string())).await.unwrap();
+        assert!(matches!(intruder.next().await, Some(Ok(Message::Text(text))) if text.contains("invalid token")));
+        assert!(matches!(intruder.next().await, Some(Ok(Message::Close(_))) | None));
+
+        assert!(tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/other", port)).await.is_err());
+
+        // Never starts the handshake
+        let mut silent = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut rest = vec![];
+        actix_rt::time::timeout(Duration::from_secs(2), silent.read_to_end(&mut rest)).await.expect("Not closed").unwrap();
+
+        core.stop_service("gateway").await;
+    }
+}
@@ -1,11 +1,14 @@
-use actix::Message;
+use actix::{Message, Recipient};
 use crate::message::Parcel;
 use crate::error::Error;
 use crate::route::Route;
 use crate::transport::Transport;
 use std::time::Instant;
 use crate::operation::Operation;
-use crate::service::ServiceRecipients;
+use crate::service::{ServiceRecipients, ServiceStatistics};
+use crate::statistics::StatisticsSnapshot;
+use crate::health::{HealthReport, Readiness};
+use std::sync::{Arc, Mutex};
 
 pub struct GetMessagesSignal { pub send_to: Route }
 impl Message for GetMessagesSignal { type Result = Result<Option<Parcel>, Error>; }
@@ -13,11 +16,37 @@ impl Message for GetMessagesSignal { type Result = Result<Option<Parcel>, Error>
 pub struct HasRouteSignal { route: Route }
 impl Message for HasRouteSignal { type Result = Result<bool, Error>; }
 
-pub struct RegisterServiceInNodeSignal { pub transport: Transport, pub name: String, pub operations: Vec<Operation>, pub consume_messages: Vec<String> }
+pub struct RegisterServiceInNodeSignal { pub transport: Transport, pub heartbeat: Recipient<Heartbeat>, pub name: String, pub operations: Vec<Operation>, pub consume_messages: Vec<String>, pub statistics: Arc<Mutex<ServiceStatistics>> }
 impl Message for RegisterServiceInNodeSignal { type Result = (); }
 
+pub struct UnregisterServiceInNodeSignal { pub name: String }
+impl Message for UnregisterServiceInNodeSignal { type Result = (); }
+
+pub struct UpdateServiceRoutesSignal { pub name: String, pub operations: Vec<Operation>, pub consume_messages: Vec<String> }
+impl Message for UpdateServiceRoutesSignal { type Result = (); }
+
+/// Subscribes a transport that isn`t a service, e.g. a client connection, until it is unsubscribed
+pub struct SubscribeTransientSignal { pub message_types: Vec<String>, pub transport: Transport }
+impl Message for SubscribeTransientSignal { type Result = (); }
+
+/// Removes the subscriptions of the transport to `message_types`, or all of them when `None`
+pub struct UnsubscribeTransientSignal { pub message_types: Option<Vec<String>>, pub transport: Transport }
+impl Message for UnsubscribeTransientSignal { type Result = (); }
+
+/// Statistics of one service (with the node's own), or of the whole node when `service` is `None`
+pub struct GetStatistics { pub service: Option<String> }
+impl Message for GetStatistics { type Result = Result<StatisticsSnapshot, Error>; }
+
+/// Sent by the core to the node and by the node to every service, a service that can`t answer is unhealthy
 pub struct Heartbeat {}
-impl Message for Heartbeat { type Result = (); }
+impl Message for Heartbeat { type Result = Result<(), Error>; }
+
+/// Services report whether they can do their job, e.g. a client service while it is disconnected
+pub struct ReportReadiness { pub service: String, pub readiness: Readiness }
+impl Message for ReportReadiness { type Result = (); }
+
+pub struct GetHealth {}
+impl Message for GetHealth { type Result = HealthReport; }
 
 pub struct Tick {
     time: Instant,
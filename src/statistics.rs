@@ -0,0 +1,277 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use crate::service::ServiceStatistics;
+
+/// Upper bounds of latency buckets in seconds, the last bucket catches everything above.
+pub const LATENCY_BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Rates are calculated over the last minute
+pub const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: Duration,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: [0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: Duration::default(),
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.count += 1;
+        self.sum += latency;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count {
+            0 => None,
+            count => Some(self.sum / count as u32),
+        }
+    }
+
+    /// Cumulative counts per upper bound, the way Prometheus histograms are exposed.
+    pub fn cumulative_buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        LATENCY_BUCKETS.iter().zip(self.buckets.iter())
+            .map(|(bound, count)| {
+                total += count;
+                (*bound, total)
+            })
+            .collect()
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts events per second for the last `window` seconds.
+#[derive(Debug, Clone)]
+pub struct SlidingWindow {
+    window: Duration,
+    started: Instant,
+    seconds: VecDeque<(u64, u64)>,
+}
+
+impl SlidingWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            started: Instant::now(),
+            seconds: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, amount: u64) {
+        self.record_at(Instant::now(), amount);
+    }
+
+    pub fn rate(&mut self) -> f64 {
+        self.rate_at(Instant::now())
+    }
+
+    fn record_at(&mut self, now: Instant, amount: u64) {
+        let second = self.second(now);
+        self.expire(second);
+
+        match self.seconds.back_mut() {
+            Some((last, count)) if *last == second => *count += amount,
+            _ => self.seconds.push_back((second, amount)),
+        }
+    }
+
+    fn rate_at(&mut self, now: Instant) -> f64 {
+        let second = self.second(now);
+        self.expire(second);
+
+        // A young window is not divided by the whole period, otherwise the rate starts near zero
+        let elapsed = now.duration_since(self.started).min(self.window).as_secs_f64().max(1.0);
+        let total: u64 = self.seconds.iter().map(|(_, count)| count).sum();
+        total as f64 / elapsed
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.duration_since(self.started).as_secs()
+    }
+
+    fn expire(&mut self, second: u64) {
+        let window = self.window.as_secs();
+        while let Some((first, _)) = self.seconds.front() {
+            if first + window > second {
+                break;
+            }
+            self.seconds.pop_front();
+        }
+    }
+}
+
+impl Default for SlidingWindow {
+    fn default() -> Self {
+        Self::new(RATE_WINDOW)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Counters {
+    pub count: u64,
+    pub bytes: u64,
+    pub errors: u64,
+    pub latency: Histogram,
+}
+
+impl Counters {
+    pub fn record(&mut self, bytes: u64, latency: Duration, ok: bool) {
+        self.count += 1;
+        self.bytes += bytes;
+        self.latency.record(latency);
+        if !ok {
+            self.errors += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TargetStatistics {
+    pub queued: u64,
+    pub parcels_in: u64,
+    pub parcels_out: u64,
+    pub parcels_dropped: u64,
+    /// Time parcels spend in the node queue before dispatch
+    pub queue_latency: Histogram,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeStatistics {
+    started: Instant,
+    pub parcels_in: u64,
+    pub parcels_out: u64,
+    pub parcels_dropped: u64,
+    pub parcels_in_per_second: f64,
+    pub parcels_out_per_second: f64,
+    pub targets: HashMap<String, TargetStatistics>,
+    in_rate: SlidingWindow,
+    out_rate: SlidingWindow,
+}
+
+impl NodeStatistics {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            parcels_in: 0,
+            parcels_out: 0,
+            parcels_dropped: 0,
+            parcels_in_per_second: 0.0,
+            parcels_out_per_second: 0.0,
+            targets: Default::default(),
+            in_rate: SlidingWindow::default(),
+            out_rate: SlidingWindow::default(),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn record_in(&mut self, target: String) {
+        self.parcels_in += 1;
+        self.in_rate.record(1);
+        self.targets.entry(target).or_default().parcels_in += 1;
+    }
+
+    pub fn record_out(&mut self, target: String, queued_for: Duration) {
+        self.parcels_out += 1;
+        self.out_rate.record(1);
+        let statistics = self.targets.entry(target).or_default();
+        statistics.parcels_out += 1;
+        statistics.queue_latency.record(queued_for);
+    }
+
+    pub fn record_dropped(&mut self, target: String) {
+        self.parcels_dropped += 1;
+        self.targets.entry(target).or_default().parcels_dropped += 1;
+    }
+
+    /// Copy of the statistics with rates calculated for this moment and the current queue depths.
+    pub fn snapshot(&mut self, queued: HashMap<String, u64>) -> NodeStatistics {
+        self.parcels_in_per_second = self.in_rate.rate();
+        self.parcels_out_per_second = self.out_rate.rate();
+
+        for statistics in self.targets.values_mut() {
+            statistics.queued = 0;
+        }
+        for (target, depth) in queued {
+            self.targets.entry(target).or_default().queued = depth;
+        }
+
+        self.clone()
+    }
+}
+
+impl Default for NodeStatistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Answer to `GetStatistics`, `services` holds just the requested service when one was asked for.
+#[derive(Debug, Clone)]
+pub struct StatisticsSnapshot {
+    pub node: NodeStatistics,
+    pub services: HashMap<String, ServiceStatistics>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::statistics::{Histogram, SlidingWindow};
+    use std::time::Duration;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new();
+        histogram.record(Duration::from_micros(500));
+        histogram.record(Duration::from_millis(20));
+        histogram.record(Duration::from_secs(60));
+
+        let buckets = histogram.cumulative_buckets();
+        assert_eq!(buckets[0], (0.001, 1));
+        assert_eq!(buckets[3], (0.025, 2));
+        assert_eq!(buckets.last().unwrap().1, 2);
+        assert_eq!(histogram.count(), 3);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let mut window = SlidingWindow::new(Duration::from_secs(10));
+        let start = window.started;
+
+        for second in 0..10 {
+            window.record_at(start + Duration::from_secs(second), 5);
+        }
+        assert_eq!(window.rate_at(start + Duration::from_secs(10)), 4.5);
+
+        // Everything recorded is out of the window
+        assert_eq!(window.rate_at(start + Duration::from_secs(30)), 0.0);
+        assert!(window.seconds.is_empty());
+    }
+}
@@ -44,9 +44,101 @@ mod base_core_tests {
         System::new().block_on(async {
             CoreBuilder::new(|| {
                 Node::new("default".to_string())
-            }).build().await.run().await;
+            }).build().await.expect("Can`t build core").run().await;
         });
 
         j.join();
     }
-}
\ No newline at end of file
+}
+/// The interceptor of the service tests
+#[cfg(test)]
+pub(crate) mod capture {
+    use crate::interceptor::{InterceptAction, InterceptContext, InterceptPoint, Interceptor};
+    use crate::message::{BaseMessage, Parcel};
+    use crate::route::Target;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    type Matches = dyn Fn(&Target) -> bool + Send + Sync;
+    type Answer = dyn Fn(&BaseMessage) -> BaseMessage + Send + Sync;
+
+    #[derive(Clone)]
+    enum Then {
+        Continue,
+        Reject,
+        Reply(Arc<Answer>),
+    }
+
+    /// Keeps the messages of the parcels whose target `matches` at `Ingress`, with that target
+    #[derive(Clone)]
+    pub struct Capture {
+        matches: Arc<Matches>,
+        then: Then,
+        messages: Arc<Mutex<Vec<(Target, BaseMessage)>>>,
+    }
+
+    impl Capture {
+        fn new(matches: impl Fn(&Target) -> bool + Send + Sync + 'static, then: Then) -> Self {
+            Self { matches: Arc::new(matches), then, messages: Arc::default() }
+        }
+
+        /// Lets the parcels through
+        pub fn watch(matches: impl Fn(&Target) -> bool + Send + Sync + 'static) -> Self {
+            Self::new(matches, Then::Continue)
+        }
+
+        /// Rejects the parcels
+        pub fn keep(matches: impl Fn(&Target) -> bool + Send + Sync + 'static) -> Self {
+            Self::new(matches, Then::Reject)
+        }
+
+        /// Sends the parcels back to where they came from, every message replaced by its answer
+        pub fn reply(matches: impl Fn(&Target) -> bool + Send + Sync + 'static,
+                     answer: impl Fn(&BaseMessage) -> BaseMessage + Send + Sync + 'static) -> Self {
+            Self::new(matches, Then::Reply(Arc::new(answer)))
+        }
+
+        pub fn messages(&self) -> Vec<(Target, BaseMessage)> {
+            self.messages.lock().unwrap().clone()
+        }
+
+        /// Waits up to 2 seconds for `count` messages
+        pub async fn wait_for(&self, count: usize) -> Vec<(Target, BaseMessage)> {
+            let started = Instant::now();
+            while self.messages.lock().unwrap().len() < count {
+                assert!(started.elapsed() < Duration::from_secs(2), "Got {} of {} messages", self.messages.lock().unwrap().len(), count);
+                actix_rt::time::sleep(Duration::from_millis(10)).await;
+            }
+            self.messages()
+        }
+    }
+
+    impl Interceptor for Capture {
+        fn name(&self) -> &str {
+            "capture"
+        }
+
+        fn points(&self) -> &[InterceptPoint] {
+            &[InterceptPoint::Ingress]
+        }
+
+        fn intercept(&self, _context: &InterceptContext, parcel: &mut Parcel) -> InterceptAction {
+            if !(self.matches)(parcel.target()) {
+                return InterceptAction::Continue;
+            }
+            let target = parcel.target().clone();
+            self.messages.lock().unwrap().extend(parcel.unpack().iter().map(|message| (target.clone(), message.clone())));
+
+            match &self.then {
+                Then::Continue => InterceptAction::Continue,
+                Then::Reject => InterceptAction::Reject("captured".to_string()),
+                Then::Reply(answer) => {
+                    for message in parcel.messages_mut() {
+                        *message = answer(message);
+                    }
+                    InterceptAction::Reroute(Target::Route(parcel.route_sheet().from().clone()))
+                }
+            }
+        }
+    }
+}
@@ -13,14 +13,18 @@ pub struct Topology {
 
 #[derive(Debug)]
 pub enum TopologyError {
-    RouteNotFound
+    RouteNotFound(String),
+    ServiceNotFound(String),
 }
 
 impl Error for TopologyError {}
 
 impl Display for TopologyError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        match self {
+            TopologyError::RouteNotFound(route) => write!(f, "no transport for route {}", route),
+            TopologyError::ServiceNotFound(service) => write!(f, "service {} is not registered", service),
+        }
     }
 }
 
@@ -53,27 +57,50 @@ impl Topology {
         }
     }
 
+    pub fn is_subscriber(&self, message_type: &str, transport: &Transport) -> bool {
+        self.subscribers.get(message_type).is_some_and(|transports| transports.contains(transport))
+    }
+
+    pub fn remove_subscriber(&mut self, message_type: &str, transport: &Transport) {
+        trace!("Removing subscriber for {}", message_type);
+        if let Some(transports) = self.subscribers.get_mut(message_type) {
+            transports.retain(|subscriber| subscriber != transport);
+            if transports.is_empty() {
+                self.subscribers.remove(message_type);
+            }
+        }
+    }
+
+    /// Removes every route and subscription served by the transport.
+    pub fn remove_transport(&mut self, transport: &Transport) {
+        trace!("Removing transport {:?}", transport);
+        self.route_table.retain(|_, route_transport| route_transport != transport);
+
+        for transports in self.subscribers.values_mut() {
+            transports.retain(|subscriber| subscriber != transport);
+        }
+        self.subscribers.retain(|_, transports| !transports.is_empty());
+    }
+
     pub fn route_exist(&self, route: Route) -> bool {
         self.route_table.contains_key(&route.as_string())
     }
 
+    /// A route with an inner id, e.g. a reply to "::client:42", falls back to the route without it
     pub fn find_transport_for_route(&self, target: &Route) -> Option<Transport> {
         trace!("Finding transport for route {}", target.as_string());
         let string_target = target.as_string();
 
-        match self.route_table.contains_key(string_target.as_str()) {
-            false => {
-                None
+        match self.route_table.get(&string_target) {
+            Some(transport) => {
+                trace!("Founded!");
+                Some(transport.clone())
             }
-            true => {
-                let transport_ref = self.route_table.get(&string_target);
-                if transport_ref.is_some() {
-                    trace!("Founded!");
-                    Some(transport_ref.unwrap().clone())
-                } else {
-                    None
-                }
+            None if !target.inner_id().is_empty() => {
+                let without_inner_id = target.clone().set_inner_id(String::new()).clone();
+                self.route_table.get(&without_inner_id.as_string()).cloned()
             }
+            None => None,
         }
     }
 
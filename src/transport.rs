@@ -1,8 +1,45 @@
 use actix::Recipient;
+use actix::prelude::SendError;
 use crate::message::Parcel;
 use log::{trace};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
+pub enum TransportError {
+    Closed,
+    Full,
+    Io(std::io::Error),
+}
+
+impl Error for TransportError {}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportError::Closed => write!(f, "recipient is closed"),
+            TransportError::Full => write!(f, "recipient mailbox is full"),
+            TransportError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for TransportError {
+    fn from(e: std::io::Error) -> Self {
+        TransportError::Io(e)
+    }
+}
+
+impl<M> From<SendError<M>> for TransportError {
+    fn from(e: SendError<M>) -> Self {
+        match e {
+            SendError::Closed(_) => TransportError::Closed,
+            SendError::Full(_) => TransportError::Full,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Transport {
     target: Recipient<Parcel>,
     is_open: bool,
@@ -13,9 +50,19 @@ impl Transport {
         Transport { target, is_open: true }
     }
 
-    pub fn send_parcel(&self, parcel: Parcel) {
+    pub fn send_parcel(&self, parcel: Parcel) -> Result<(), TransportError> {
+        trace!("Sending parcel");
+        self.target.do_send(parcel)?;
+        Ok(())
+    }
+
+    /// Like `send_parcel`, but gives the parcel back when it can`t be sent
+    pub fn try_send_parcel(&self, parcel: Parcel) -> Result<(), (TransportError, Box<Parcel>)> {
         trace!("Sending parcel");
-        self.target.do_send(parcel);
+        self.target.do_send(parcel).map_err(|e| match e {
+            SendError::Closed(parcel) => (TransportError::Closed, Box::new(parcel)),
+            SendError::Full(parcel) => (TransportError::Full, Box::new(parcel)),
+        })
     }
 
     pub fn is_open(&self) -> bool {
@@ -0,0 +1,87 @@
+use any_message::config::ConfigBuilder;
+use any_message::core::CoreBuilder;
+use any_message::message::{BaseMessage, Parcel};
+use any_message::node::Node;
+use actix::Addr;
+use any_message::route::{Route, RouteSheet, Target};
+use any_message::signal::GetStatistics;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const CONFIG: &str = r#"
+Core:
+  name: "EchoCore"
+Node:
+  name: "Node01"
+  echo:
+    type: "EchoService"
+    parameters:
+      message_type: "EchoRequest"
+      reply_type: "EchoReply"
+"#;
+
+/// Builds examples/echo_plugin in its own target directory, the one of the test is locked by cargo
+fn build_echo_plugin() -> PathBuf {
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("echo_plugin");
+    let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_e| "cargo".to_string()))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(&["build", "--quiet", "-p", "echo_plugin", "--target-dir"])
+        .arg(&target_dir)
+        .status()
+        .expect("Can`t run cargo");
+    assert!(status.success(), "echo_plugin build failed");
+
+    target_dir.join("debug").join(format!("{}echo_plugin.{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_EXTENSION))
+}
+
+async fn echo_replies(node: &Addr<Node>) -> u64 {
+    let snapshot = node.send(GetStatistics { service: None }).await
+        .expect("Node doesn`t answer")
+        .expect("No statistics");
+
+    snapshot.node.targets.get(&Target::Consumer("EchoReply".to_string()).as_string())
+        .map_or(0, |target| target.parcels_in)
+}
+
+#[actix_rt::test]
+async fn echo_plugin() {
+    let library = build_echo_plugin();
+
+    let mut core = CoreBuilder::new(|| Node::new("Node01".to_string()))
+        .plugins(vec![library.display().to_string()])
+        .build().await
+        .expect("Can`t build core");
+
+    let plugins = core.plugins();
+    assert_eq!(plugins.len(), 1);
+    assert!(plugins[0].loaded, "{:?}", plugins[0].error);
+    assert_eq!(plugins[0].name.as_deref(), Some("EchoPlugin"));
+
+    let config = ConfigBuilder::from_string(CONFIG.to_string()).build().unwrap();
+    core.apply_config(config).await.expect("Can`t start the echo service");
+
+    // The plugin is reloaded and unloaded while the core runs
+    let node = core.node();
+    let handle = core.handle();
+    actix_rt::spawn(async move { core.run().await });
+
+    assert_echo(&node, 1).await;
+    handle.reload_plugin("EchoPlugin").await.expect("Can`t reload the plugin");
+    assert_echo(&node, 2).await;
+
+    handle.unload_plugin("EchoPlugin").await.expect("Can`t unload the plugin");
+    assert!(handle.unload_plugin("EchoPlugin").await.is_err());
+}
+
+/// Sends a request and waits until `replies` echo replies were routed
+async fn assert_echo(node: &Addr<Node>, replies: u64) {
+    let request = RouteSheet::new(Target::Consumer("EchoRequest".to_string()), Route::new());
+    node.do_send(Parcel::new(vec![BaseMessage::new(b"ping".to_vec(), None)], request));
+
+    let started = Instant::now();
+    while echo_replies(node).await < replies {
+        assert!(started.elapsed() < Duration::from_secs(5), "No echo reply");
+        actix_rt::time::sleep(Duration::from_millis(20)).await;
+    }
+}